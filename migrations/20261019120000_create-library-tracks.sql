@@ -0,0 +1,14 @@
+CREATE TABLE library_tracks (
+    id          TEXT    NOT NULL,
+    path        TEXT    NOT NULL,
+    title       TEXT,
+    artist      TEXT,
+    album       TEXT,
+    duration_ms INTEGER,
+
+    created_at  TEXT    NOT NULL,
+    updated_at  TEXT,
+
+    PRIMARY KEY (id),
+    UNIQUE (path)
+);
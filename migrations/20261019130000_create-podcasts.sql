@@ -0,0 +1,25 @@
+CREATE TABLE podcasts (
+    id                  TEXT    NOT NULL,
+    guild_id            TEXT    NOT NULL,
+    feed_url            TEXT    NOT NULL,
+    title               TEXT    NOT NULL,
+    announce_channel_id TEXT,
+    last_episode_id     TEXT,
+
+    created_at          TEXT    NOT NULL,
+    updated_at          TEXT,
+
+    PRIMARY KEY (id),
+    UNIQUE (guild_id, feed_url)
+);
+
+CREATE TABLE podcast_progress (
+    guild_id    TEXT    NOT NULL,
+    episode_url TEXT    NOT NULL,
+    position_ms INTEGER NOT NULL,
+
+    created_at  TEXT    NOT NULL,
+    updated_at  TEXT,
+
+    PRIMARY KEY (guild_id, episode_url)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE relay_tokens (
+    guild_id    TEXT    NOT NULL,
+    token       TEXT    NOT NULL,
+
+    created_at  TEXT    NOT NULL,
+    updated_at  TEXT,
+
+    PRIMARY KEY (guild_id)
+);
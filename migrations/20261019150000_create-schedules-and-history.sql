@@ -0,0 +1,27 @@
+CREATE TABLE schedules (
+    id          TEXT    NOT NULL,
+    guild_id    TEXT    NOT NULL,
+    channel_id  TEXT    NOT NULL,
+    url         TEXT    NOT NULL,
+    -- Local time of day as "HH:MM"
+    time        TEXT    NOT NULL,
+    -- Bit 0 is monday, bit 6 sunday
+    weekdays    INTEGER NOT NULL,
+    -- Local date of the last run, so a schedule runs at most once per day
+    last_run    TEXT,
+
+    created_at  TEXT    NOT NULL,
+    updated_at  TEXT,
+
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE play_history (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id    TEXT    NOT NULL,
+    url         TEXT    NOT NULL,
+    title       TEXT,
+    played_at   TEXT    NOT NULL
+);
+
+CREATE INDEX play_history_guild ON play_history (guild_id, played_at);
@@ -0,0 +1,7 @@
+CREATE TABLE command_guilds (
+    guild_id    TEXT    NOT NULL,
+
+    created_at  TEXT    NOT NULL,
+
+    PRIMARY KEY (guild_id)
+);
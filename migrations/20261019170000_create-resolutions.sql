@@ -0,0 +1,9 @@
+CREATE TABLE resolutions (
+    url         TEXT    NOT NULL,
+    output      TEXT    NOT NULL,
+
+    created_at  TEXT    NOT NULL,
+    expires_at  TEXT    NOT NULL,
+
+    PRIMARY KEY (url)
+);
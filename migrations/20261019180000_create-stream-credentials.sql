@@ -0,0 +1,12 @@
+CREATE TABLE stream_credentials (
+    guild_id    TEXT    NOT NULL,
+    -- "url:<prefix>" or "favorite:<id>"
+    target      TEXT    NOT NULL,
+    -- Encrypted, see src/credentials.rs
+    secret      TEXT    NOT NULL,
+
+    created_at  TEXT    NOT NULL,
+    updated_at  TEXT,
+
+    PRIMARY KEY (guild_id, target)
+);
@@ -0,0 +1,11 @@
+CREATE TABLE guild_domains (
+    guild_id    TEXT    NOT NULL,
+    domain      TEXT    NOT NULL,
+    -- 1 if the domain is blocked, 0 if it's allowed
+    blocked     INTEGER NOT NULL,
+
+    created_at  TEXT    NOT NULL,
+    updated_at  TEXT,
+
+    PRIMARY KEY (guild_id, domain)
+);
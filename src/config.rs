@@ -1,73 +1,829 @@
 use std::env;
+use std::fmt::{self, Display};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use directories::ProjectDirs;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::{credentials, logger, url_policy};
+
+/// Log filter used if neither `RUST_LOG` nor `logging.filter` is set
+const DEFAULT_LOG_FILTER: &str = "debug,hyper=info,h2=info,rustls=info,reqwest=info";
+/// Shown instead of secrets when printing the config
+const REDACTED: &str = "<redacted>";
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub project_dirs: ProjectDirs,
     pub database_path: String,
 
-    pub discord_token: String,
+    pub discord_token: Secret,
     pub is_debug: bool,
     pub debug_guild: Option<u64>,
     pub self_deaf: bool,
     pub max_volume: u32,
-    pub should_publish_global: bool,
+
+    pub ytdl: YtdlConfig,
+    pub outbound: OutboundConfig,
+    /// Only URLs on these domains (or their subdomains) can be played, unless it's empty
+    pub allowed_domains: Vec<String>,
+    /// URLs on these domains (or their subdomains) can't be played
+    pub blocked_domains: Vec<String>,
+    /// Maximum number of tracks queued from a single playlist
+    pub max_playlist_entries: usize,
+
+    /// Directory of audio files available via `/library`
+    pub library_path: Option<PathBuf>,
+
+    /// How often subscribed podcast feeds are checked for new episodes, `None` to disable
+    pub podcast_refresh_interval: Option<Duration>,
+
+    /// Highest bitrate (bits/s) picked from the variants of an HLS stream, `None` for the best one
+    pub hls_max_bitrate: Option<u64>,
+    /// Guilds playing the same live stream share a single connection and decoder
+    pub share_streams: bool,
+
+    /// Address of the embedded HTTP server (relay), `None` to disable it
+    pub http_bind: Option<SocketAddr>,
+    /// URL under which the HTTP server is reachable from the outside, without a trailing slash
+    pub public_url: String,
+    /// Bearer token for the control API, `None` to disable the API
+    pub api_token: Option<Secret>,
+    /// Discord OAuth2 application used for logging into the dashboard, `None` to disable it
+    pub dashboard_oauth: Option<OAuthConfig>,
+    /// Serve Prometheus metrics at `/metrics`
+    pub metrics_enabled: bool,
+
+    /// `tracing` filter directives, e.g. `info,discomfort_fm=debug`
+    pub log_filter: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct YtdlConfig {
+    /// Path or name of the yt-dlp executable
+    pub program: String,
+    /// The bot downloads yt-dlp to `program` and keeps it up to date
+    pub managed: bool,
+    /// Release installed instead of the latest one, e.g. `2024.10.07` (managed only)
+    pub version: Option<String>,
+    /// How often to check for a new release (managed only), `None` to disable updates
+    pub update_interval: Option<Duration>,
+    /// Cookies file (Netscape format) passed via `--cookies`
+    pub cookies: Option<PathBuf>,
+    /// Format selector passed via `-f`
+    pub format: String,
+    pub proxy: Option<String>,
+    /// Passed to every invocation, after the arguments above
+    pub extra_args: Vec<String>,
+    /// yt-dlp is killed if it runs longer than this
+    pub timeout: Duration,
+    /// How many yt-dlp processes can run at once
+    pub max_concurrent: usize,
+    /// How long resolved URLs and their metadata are cached, `None` to disable the cache
+    pub cache_ttl: Option<Duration>,
+}
+
+/// Settings of outgoing HTTP requests (streams, feeds, probing)
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboundConfig {
+    /// HTTP(S) proxy for all requests, e.g. `http://127.0.0.1:3128`. Also used by yt-dlp unless
+    /// it has its own.
+    pub proxy: Option<String>,
+    pub connect_timeout: Duration,
+    pub user_agent: String,
+    /// Allow URLs which resolve to loopback, private or link-local addresses (e.g. a radio in the
+    /// LAN)
+    pub allow_private: bool,
+    /// Key (32 bytes, base64) of the stream credentials stored in the database, `None` to use a
+    /// generated key file
+    pub credentials_key: Option<Secret>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OAuthConfig {
+    pub client_id: String,
+    pub client_secret: Secret,
+    /// Endpoint the authorization code is exchanged at, can point to a stand-in for testing
+    pub token_url: String,
+    /// Base URL of the Discord API, used to get the user and their guilds
+    pub api_url: String,
+}
+
+/// A token or password, which is redacted when the config is printed or logged
+#[derive(Clone, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{REDACTED}")
+    }
+}
+
+/// The current config, which can be re-read while the bot is running (`SIGHUP` or `/reload`).
+///
+/// Values which are only used on startup keep their old value until the next restart.
+#[derive(Clone)]
+pub struct ConfigHandle {
+    current: Arc<RwLock<Arc<Config>>>,
+    /// Config file given on the command line
+    path: Option<PathBuf>,
+}
+
+/// What changed when reloading the config, by environment variable
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    pub applied: Vec<&'static str>,
+    pub restart_required: Vec<&'static str>,
+}
+
+impl ConfigHandle {
+    pub fn new(config: Config, path: Option<PathBuf>) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+            path,
+        }
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().clone()
+    }
+
+    /// Re-reads the config file and applies the values that can change at runtime.
+    ///
+    /// Environment variables can't change while the bot is running, so only the file is relevant.
+    pub fn reload(&self) -> Result<ReloadReport, ConfigError> {
+        let mut config = Config::load(self.path.as_deref())?;
+
+        let mut current = self.current.write();
+        let report = config.take_startup_values(&current);
+
+        if config.log_filter != current.log_filter {
+            if let Err(e) = logger::set_filter(&config.log_filter) {
+                return Err(ConfigError(vec![format!(
+                    "couldn't apply the log filter: {e}"
+                )]));
+            }
+        }
+
+        *current = Arc::new(config);
+
+        Ok(report)
+    }
+}
+
+impl Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.applied.is_empty() && self.restart_required.is_empty() {
+            return write!(f, "nothing changed");
+        }
+
+        if !self.applied.is_empty() {
+            write!(f, "applied {}", self.applied.join(", "))?;
+        }
+        if !self.restart_required.is_empty() {
+            if !self.applied.is_empty() {
+                write!(f, "; ")?;
+            }
+            write!(
+                f,
+                "{} only change after a restart",
+                self.restart_required.join(", ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Everything that was wrong with the config, so it can be fixed in one go
+#[derive(Debug)]
+pub struct ConfigError(Vec<String>);
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for error in &self.0 {
+            write!(f, "\n  - {error}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Contents of the config file. Every value can be overridden by its environment variable.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    discord: DiscordSection,
+    audio: AudioSection,
+    ytdl: YtdlSection,
+    outbound: OutboundSection,
+    database: DatabaseSection,
+    http: HttpSection,
+    logging: LoggingSection,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct DiscordSection {
+    token: Option<String>,
+    debug: Option<bool>,
+    debug_guild: Option<u64>,
+    self_deaf: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct AudioSection {
+    max_volume: Option<u32>,
+    max_playlist_entries: Option<usize>,
+    library_path: Option<PathBuf>,
+    /// In minutes, 0 disables the refresh
+    podcast_refresh_interval: Option<u64>,
+    hls_max_bitrate: Option<u64>,
+    share_streams: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct YtdlSection {
+    program: Option<String>,
+    managed: Option<bool>,
+    version: Option<String>,
+    /// In hours, 0 disables updates
+    update_interval: Option<u64>,
+    cookies: Option<PathBuf>,
+    format: Option<String>,
+    proxy: Option<String>,
+    args: Option<Vec<String>>,
+    /// In seconds
+    timeout: Option<u64>,
+    max_concurrent: Option<usize>,
+    /// In minutes, 0 disables the cache
+    cache_ttl: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct OutboundSection {
+    proxy: Option<String>,
+    /// In seconds
+    connect_timeout: Option<u64>,
+    user_agent: Option<String>,
+    allow_private: Option<bool>,
+    allowed_domains: Option<Vec<String>>,
+    blocked_domains: Option<Vec<String>>,
+    credentials_key: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct DatabaseSection {
+    url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct HttpSection {
+    bind: Option<SocketAddr>,
+    public_url: Option<String>,
+    api_token: Option<String>,
+    metrics: Option<bool>,
+    dashboard: Option<DashboardSection>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct DashboardSection {
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    token_url: Option<String>,
+    api_url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingSection {
+    filter: Option<String>,
 }
 
 impl Config {
-    pub fn get() -> Result<Self, Box<dyn std::error::Error>> {
+    /// Loads the config from the environment, falling back to the config file at `path` (or
+    /// `config.toml`/`config.yaml` in the config directory, if it exists) and then the defaults
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
         let project_dirs =
             directories::ProjectDirs::from("com", "github.sebbl0508", "discomfort-fm")
-                .ok_or("couldn't get base directories")?;
+                .ok_or_else(|| ConfigError(vec!["couldn't get base directories".to_string()]))?;
 
-        tracing::debug!(
-            "creating folder \"{}\"",
-            project_dirs.data_local_dir().display()
-        );
-        std::fs::create_dir_all(project_dirs.data_local_dir())?;
-
-        let discord_token = env_load_or_err("DISCORD_TOKEN")?;
-
-        let debug = env_load_bool_with_default("DEBUG", false);
-
-        // Require "DEBUG_GUILD" to be set, if debug mode was enabled via the env variable
-        let debug_guild = {
-            let debug_guild_res = env_load_or_err("DEBUG_GUILD");
-            if debug {
-                Some(debug_guild_res?.parse::<u64>()?)
-            } else {
-                debug_guild_res
-                    .and_then(|v| {
-                        v.parse::<u64>()
-                            .map_err(|_| "cannot parse dev guild as u64".to_string())
-                    })
-                    .ok()
+        std::fs::create_dir_all(project_dirs.data_local_dir()).map_err(|e| {
+            ConfigError(vec![format!(
+                "couldn't create folder \"{}\": {e}",
+                project_dirs.data_local_dir().display()
+            )])
+        })?;
+
+        let mut loader = Loader::default();
+
+        let path = match path {
+            Some(v) => Some(v.to_path_buf()),
+            None => default_config_path(&project_dirs),
+        };
+        let file = match &path {
+            Some(path) => loader.read_file(path),
+            None => FileConfig::default(),
+        };
+        let FileConfig {
+            discord,
+            audio,
+            ytdl,
+            outbound,
+            database,
+            http,
+            logging,
+        } = file;
+
+        let errors_before = loader.errors.len();
+        let discord_token = loader.secret("DISCORD_TOKEN", discord.token);
+        if discord_token.is_none() && loader.errors.len() == errors_before {
+            loader.error("DISCORD_TOKEN (discord.token) is required");
+        }
+
+        let debug = loader.flag("DEBUG", discord.debug).unwrap_or(false);
+        let debug_guild = loader.value("DEBUG_GUILD", discord.debug_guild);
+        // Commands are registered in the debug guild, so it's required in debug mode
+        if debug && debug_guild.is_none() {
+            loader.error("DEBUG_GUILD (discord.debug_guild) is required in debug mode");
+        }
+
+        let self_deaf = loader.flag("SELF_DEAF", discord.self_deaf).unwrap_or(true);
+
+        let max_volume = loader.value("MAX_VOLUME", audio.max_volume).unwrap_or(100);
+
+        let database_path = loader
+            .value("DATABASE_URL", database.url)
+            .unwrap_or_else(|| default_database_path(&project_dirs));
+
+        let allowed_domains = loader.domains("ALLOWED_DOMAINS", outbound.allowed_domains);
+        let blocked_domains = loader.domains("BLOCKED_DOMAINS", outbound.blocked_domains);
+
+        let outbound = OutboundConfig {
+            proxy: loader.value("OUTBOUND_PROXY", outbound.proxy),
+            connect_timeout: Duration::from_secs(
+                loader
+                    .value("OUTBOUND_CONNECT_TIMEOUT", outbound.connect_timeout)
+                    .unwrap_or(10),
+            ),
+            user_agent: loader
+                .value("OUTBOUND_USER_AGENT", outbound.user_agent)
+                .unwrap_or_else(|| {
+                    concat!("discomfort-fm/", env!("CARGO_PKG_VERSION")).to_string()
+                }),
+            allow_private: loader
+                .flag("OUTBOUND_ALLOW_PRIVATE", outbound.allow_private)
+                .unwrap_or(false),
+            credentials_key: loader.secret("CREDENTIALS_KEY", outbound.credentials_key),
+        };
+        if let Some(proxy) = &outbound.proxy {
+            if let Err(e) = reqwest::Proxy::all(proxy) {
+                loader.error(format!("OUTBOUND_PROXY: invalid proxy \"{proxy}\": {e}"));
+            }
+        }
+        if let Some(key) = &outbound.credentials_key {
+            if let Err(e) = credentials::decode_key(key.expose()) {
+                loader.error(format!("CREDENTIALS_KEY: {e}"));
+            }
+        }
+
+        let mut ytdl = loader.ytdl(ytdl, &project_dirs);
+        if ytdl.proxy.is_none() {
+            ytdl.proxy = outbound.proxy.clone();
+        }
+        let max_playlist_entries = loader
+            .value("MAX_PLAYLIST_ENTRIES", audio.max_playlist_entries)
+            .unwrap_or(100);
+
+        let library_path = loader.value("LIBRARY_PATH", audio.library_path);
+
+        // In minutes, 0 disables the refresh
+        let podcast_refresh_interval = loader
+            .value("PODCAST_REFRESH_INTERVAL", audio.podcast_refresh_interval)
+            .unwrap_or(30);
+        let podcast_refresh_interval = (podcast_refresh_interval > 0)
+            .then(|| Duration::from_secs(podcast_refresh_interval * 60));
+
+        let hls_max_bitrate = loader.value("HLS_MAX_BITRATE", audio.hls_max_bitrate);
+
+        let share_streams = loader
+            .flag("SHARE_STREAMS", audio.share_streams)
+            .unwrap_or(true);
+
+        let http_bind = loader.value("HTTP_BIND", http.bind);
+        let public_url = loader
+            .value("PUBLIC_URL", http.public_url)
+            .or_else(|| http_bind.map(|v| format!("http://{v}")))
+            .unwrap_or_default()
+            .trim_end_matches('/')
+            .to_string();
+        let api_token = loader.secret("API_TOKEN", http.api_token);
+
+        let dashboard = http.dashboard.unwrap_or_default();
+        let dashboard_oauth = match (
+            loader.value("DASHBOARD_CLIENT_ID", dashboard.client_id),
+            loader.secret("DASHBOARD_CLIENT_SECRET", dashboard.client_secret),
+        ) {
+            (Some(client_id), Some(client_secret)) => Some(OAuthConfig {
+                client_id,
+                client_secret,
+                token_url: loader
+                    .value("DASHBOARD_TOKEN_URL", dashboard.token_url)
+                    .unwrap_or_else(|| "https://discord.com/api/oauth2/token".to_string()),
+                api_url: loader
+                    .value("DASHBOARD_API_URL", dashboard.api_url)
+                    .unwrap_or_else(|| "https://discord.com/api/v10".to_string())
+                    .trim_end_matches('/')
+                    .to_string(),
+            }),
+            (None, None) => None,
+            _ => {
+                loader.error(
+                    "DASHBOARD_CLIENT_ID and DASHBOARD_CLIENT_SECRET (http.dashboard) have to be set together",
+                );
+                None
             }
         };
 
-        let should_publish_global = env_load_bool_with_default("PUBLISH_GLOBAL", false);
+        let metrics_enabled = loader
+            .flag("METRICS_ENABLED", http.metrics)
+            .unwrap_or(false);
 
-        let self_deaf = env_load_bool_with_default("SELF_DEAF", true);
-        let max_volume = env_load_or_err("MAX_VOLUME")
-            .unwrap_or_else(|_| "100".to_string())
-            .parse::<u32>()?;
+        let log_filter = loader
+            .value("RUST_LOG", logging.filter)
+            .unwrap_or_else(|| DEFAULT_LOG_FILTER.to_string());
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&log_filter) {
+            loader.error(format!("invalid log filter \"{log_filter}\": {e}"));
+        }
 
-        let database_path = env_load_or_err("DATABASE_URL")
-            .unwrap_or_else(|_| default_database_path(&project_dirs));
+        if !loader.errors.is_empty() {
+            return Err(ConfigError(loader.errors));
+        }
 
         Ok(Self {
             project_dirs,
             database_path,
 
-            discord_token,
+            discord_token: discord_token.unwrap_or_else(|| Secret(String::new())),
             is_debug: debug,
-            should_publish_global,
             debug_guild,
             self_deaf,
             max_volume,
+
+            ytdl,
+            outbound,
+            allowed_domains,
+            blocked_domains,
+            max_playlist_entries,
+
+            library_path,
+
+            podcast_refresh_interval,
+
+            hls_max_bitrate,
+            share_streams,
+
+            http_bind,
+            public_url,
+            api_token,
+            dashboard_oauth,
+            metrics_enabled,
+
+            log_filter,
         })
     }
+
+    /// Compares the reloaded config with the `running` one. Values which are only used on startup
+    /// are reset to their running value.
+    fn take_startup_values(&mut self, running: &Config) -> ReloadReport {
+        let mut report = ReloadReport::default();
+
+        macro_rules! applied {
+            ($($field:ident => $name:literal),* $(,)?) => {$(
+                if self.$field != running.$field {
+                    report.applied.push($name);
+                }
+            )*};
+        }
+        macro_rules! restart_required {
+            ($($field:ident => $name:literal),* $(,)?) => {$(
+                if self.$field != running.$field {
+                    report.restart_required.push($name);
+                    self.$field = running.$field.clone();
+                }
+            )*};
+        }
+
+        applied!(
+            self_deaf => "SELF_DEAF",
+            max_volume => "MAX_VOLUME",
+            max_playlist_entries => "MAX_PLAYLIST_ENTRIES",
+            hls_max_bitrate => "HLS_MAX_BITRATE",
+            share_streams => "SHARE_STREAMS",
+            allowed_domains => "ALLOWED_DOMAINS",
+            blocked_domains => "BLOCKED_DOMAINS",
+            public_url => "PUBLIC_URL",
+            metrics_enabled => "METRICS_ENABLED",
+            log_filter => "RUST_LOG",
+        );
+        restart_required!(
+            database_path => "DATABASE_URL",
+            discord_token => "DISCORD_TOKEN",
+            is_debug => "DEBUG",
+            debug_guild => "DEBUG_GUILD",
+            ytdl => "YTDL_*",
+            outbound => "OUTBOUND_*",
+            library_path => "LIBRARY_PATH",
+            podcast_refresh_interval => "PODCAST_REFRESH_INTERVAL",
+            http_bind => "HTTP_BIND",
+            api_token => "API_TOKEN",
+            dashboard_oauth => "DASHBOARD_*",
+        );
+
+        report
+    }
+
+    /// The effective config in the format of the config file, with secrets redacted
+    pub fn to_toml(&self) -> String {
+        let redacted = || Some(REDACTED.to_string());
+
+        let file = FileConfig {
+            discord: DiscordSection {
+                token: redacted(),
+                debug: Some(self.is_debug),
+                debug_guild: self.debug_guild,
+                self_deaf: Some(self.self_deaf),
+            },
+            audio: AudioSection {
+                max_volume: Some(self.max_volume),
+                max_playlist_entries: Some(self.max_playlist_entries),
+                library_path: self.library_path.clone(),
+                podcast_refresh_interval: Some(
+                    self.podcast_refresh_interval
+                        .map(|v| v.as_secs() / 60)
+                        .unwrap_or(0),
+                ),
+                hls_max_bitrate: self.hls_max_bitrate,
+                share_streams: Some(self.share_streams),
+            },
+            ytdl: YtdlSection {
+                program: Some(self.ytdl.program.clone()),
+                managed: Some(self.ytdl.managed),
+                version: self.ytdl.version.clone(),
+                update_interval: Some(
+                    self.ytdl
+                        .update_interval
+                        .map(|v| v.as_secs() / 3600)
+                        .unwrap_or(0),
+                ),
+                cookies: self.ytdl.cookies.clone(),
+                format: Some(self.ytdl.format.clone()),
+                proxy: self.ytdl.proxy.clone(),
+                args: Some(self.ytdl.extra_args.clone()),
+                timeout: Some(self.ytdl.timeout.as_secs()),
+                max_concurrent: Some(self.ytdl.max_concurrent),
+                cache_ttl: Some(self.ytdl.cache_ttl.map(|v| v.as_secs() / 60).unwrap_or(0)),
+            },
+            outbound: OutboundSection {
+                proxy: self.outbound.proxy.clone(),
+                connect_timeout: Some(self.outbound.connect_timeout.as_secs()),
+                user_agent: Some(self.outbound.user_agent.clone()),
+                allow_private: Some(self.outbound.allow_private),
+                allowed_domains: Some(self.allowed_domains.clone()),
+                blocked_domains: Some(self.blocked_domains.clone()),
+                credentials_key: self
+                    .outbound
+                    .credentials_key
+                    .as_ref()
+                    .and_then(|_| redacted()),
+            },
+            database: DatabaseSection {
+                url: Some(self.database_path.clone()),
+            },
+            http: HttpSection {
+                bind: self.http_bind,
+                public_url: Some(self.public_url.clone()),
+                api_token: self.api_token.as_ref().and_then(|_| redacted()),
+                metrics: Some(self.metrics_enabled),
+                dashboard: self.dashboard_oauth.as_ref().map(|v| DashboardSection {
+                    client_id: Some(v.client_id.clone()),
+                    client_secret: redacted(),
+                    token_url: Some(v.token_url.clone()),
+                    api_url: Some(v.api_url.clone()),
+                }),
+            },
+            logging: LoggingSection {
+                filter: Some(self.log_filter.clone()),
+            },
+        };
+
+        toml::to_string(&file).expect("config can be serialized")
+    }
+}
+
+/// Collects the values of the config, and everything that's wrong with them
+#[derive(Default)]
+struct Loader {
+    errors: Vec<String>,
+}
+
+impl Loader {
+    fn error(&mut self, error: impl Into<String>) {
+        self.errors.push(error.into());
+    }
+
+    fn read_file(&mut self, path: &Path) -> FileConfig {
+        let content = match std::fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) => {
+                self.error(format!("couldn't read \"{}\": {e}", path.display()));
+                return FileConfig::default();
+            }
+        };
+
+        let is_yaml = path.extension().is_some_and(|v| v == "yaml" || v == "yml");
+        let result = if is_yaml {
+            serde_yaml::from_str(&content).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&content).map_err(|e| e.to_string())
+        };
+
+        result.unwrap_or_else(|e| {
+            self.error(format!("couldn't parse \"{}\": {e}", path.display()));
+            FileConfig::default()
+        })
+    }
+
+    /// Value of the environment variable `key`, falling back to the value from the file
+    fn value<T>(&mut self, key: &str, file_value: Option<T>) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let Some(value) = env_load(key) else {
+            return file_value;
+        };
+
+        match value.parse() {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.error(format!("{key}: couldn't parse \"{value}\" ({e})"));
+                None
+            }
+        }
+    }
+
+    fn ytdl(&mut self, section: YtdlSection, project_dirs: &ProjectDirs) -> YtdlConfig {
+        let managed = self.flag("YTDL_MANAGED", section.managed).unwrap_or(false);
+        // A managed yt-dlp is downloaded next to the database by default
+        let program = self
+            .value("YTDL_PROGRAM", section.program)
+            .unwrap_or_else(|| {
+                if managed {
+                    project_dirs
+                        .data_local_dir()
+                        .join("yt-dlp")
+                        .to_string_lossy()
+                        .into_owned()
+                } else {
+                    "yt-dlp".to_string()
+                }
+            });
+
+        // In hours, 0 disables updates
+        let update_interval = self
+            .value("YTDL_UPDATE_INTERVAL", section.update_interval)
+            .unwrap_or(24);
+
+        // Split like arguments on a command line, without support for quoting
+        let extra_args = match env_load("YTDL_ARGS") {
+            Some(v) => v.split_whitespace().map(str::to_string).collect(),
+            None => section.args.unwrap_or_default(),
+        };
+
+        let max_concurrent = self
+            .value("YTDL_MAX_CONCURRENT", section.max_concurrent)
+            .unwrap_or(4);
+        if max_concurrent == 0 {
+            self.error("YTDL_MAX_CONCURRENT (ytdl.max_concurrent) has to be at least 1");
+        }
+
+        // In minutes, 0 disables the cache
+        let cache_ttl = self
+            .value("YTDL_CACHE_TTL", section.cache_ttl)
+            .unwrap_or(180);
+
+        YtdlConfig {
+            program,
+            managed,
+            version: self.value("YTDL_VERSION", section.version),
+            update_interval: (update_interval > 0)
+                .then(|| Duration::from_secs(update_interval * 3600)),
+            cookies: self.value("YTDL_COOKIES", section.cookies),
+            format: self
+                .value("YTDL_FORMAT", section.format)
+                .unwrap_or_else(|| "ba[abr>0][vcodec=none]/best".to_string()),
+            proxy: self.value("YTDL_PROXY", section.proxy),
+            extra_args,
+            timeout: Duration::from_secs(
+                self.value("YTDL_TIMEOUT", section.timeout).unwrap_or(120),
+            ),
+            max_concurrent,
+            cache_ttl: (cache_ttl > 0).then(|| Duration::from_secs(cache_ttl * 60)),
+        }
+    }
+
+    /// Like [`Self::value`], but the secret can also be read from the file at `<key>_FILE` (e.g.
+    /// a Docker secret)
+    fn secret(&mut self, key: &str, file_value: Option<String>) -> Option<Secret> {
+        let file_key = format!("{key}_FILE");
+
+        let value = match (env_load(key), env_load(&file_key)) {
+            (Some(_), Some(_)) => {
+                self.error(format!("only one of {key} and {file_key} can be set"));
+                return None;
+            }
+            (Some(v), None) => v,
+            (None, Some(path)) => match std::fs::read_to_string(&path) {
+                // Files usually end with a newline, which isn't part of the secret
+                Ok(v) => v.trim().to_string(),
+                Err(e) => {
+                    self.error(format!("{file_key}: couldn't read \"{path}\": {e}"));
+                    return None;
+                }
+            },
+            (None, None) => file_value?,
+        };
+
+        (!value.is_empty()).then_some(Secret(value))
+    }
+
+    /// A list of domains, separated by commas or spaces in the environment variable
+    fn domains(&mut self, key: &str, file_value: Option<Vec<String>>) -> Vec<String> {
+        let values = match env_load(key) {
+            Some(v) => v
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+                .collect(),
+            None => file_value.unwrap_or_default(),
+        };
+
+        values
+            .iter()
+            .filter_map(|v| match url_policy::normalize_domain(v) {
+                Some(domain) => Some(domain),
+                None => {
+                    self.error(format!("{key}: \"{v}\" isn't a domain"));
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Like [`Self::value`], but also accepts `TRUE`/`False`
+    fn flag(&mut self, key: &str, file_value: Option<bool>) -> Option<bool> {
+        let Some(value) = env_load(key) else {
+            return file_value;
+        };
+
+        match value.to_lowercase().as_str() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => {
+                self.error(format!("{key}: expected true or false, got \"{value}\""));
+                None
+            }
+        }
+    }
+}
+
+/// `config.toml` (or `.yaml`/`.yml`) in the config directory, if it exists
+fn default_config_path(project_dirs: &ProjectDirs) -> Option<PathBuf> {
+    ["config.toml", "config.yaml", "config.yml"]
+        .iter()
+        .map(|v| project_dirs.config_dir().join(v))
+        .find(|v| v.is_file())
 }
 
 fn default_database_path(project_dirs: &ProjectDirs) -> String {
@@ -77,18 +833,7 @@ fn default_database_path(project_dirs: &ProjectDirs) -> String {
     format!("sqlite://{}?mode=rwc", db_dir.display())
 }
 
-fn env_load_or_err(key: &str) -> Result<String, String> {
-    env::var(key).map_err(|_| format!("couldn't load {} from env", key))
-}
-
-fn env_load_bool_with_default(key: &str, default: bool) -> bool {
-    match env_load_or_err(key)
-        .unwrap_or_default()
-        .to_lowercase()
-        .as_str()
-    {
-        "true" => true,
-        "false" => false,
-        _ => default,
-    }
+/// Gets the environment variable `key`, empty ones count as unset
+fn env_load(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|v| !v.is_empty())
 }
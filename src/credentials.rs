@@ -0,0 +1,303 @@
+//! Headers and logins of private (or geo-fenced) streams, set via `/credentials`.
+//!
+//! They're stored encrypted (ChaCha20-Poly1305) in the database, with `CREDENTIALS_KEY` or a key
+//! which is generated on first use. The guild and target are part of the authenticated data, so an
+//! encrypted value only works for the station it was set for.
+
+use std::fmt::{self, Display};
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use poise::serenity_prelude::GuildId;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::config::Config;
+use crate::database::{actions, DatabaseContext};
+use crate::discord::Error;
+
+const KEY_FILE: &str = "credentials.key";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// What credentials apply to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Every URL starting with the prefix
+    Url(String),
+    /// A favorite, by ID
+    Favorite(String),
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Url(prefix) => write!(f, "url:{prefix}"),
+            Self::Favorite(id) => write!(f, "favorite:{id}"),
+        }
+    }
+}
+
+impl Target {
+    /// Parses the form stored in the database
+    pub fn parse(value: &str) -> Option<Self> {
+        if let Some(prefix) = value.strip_prefix("url:") {
+            return Some(Self::Url(prefix.to_string()));
+        }
+
+        value
+            .strip_prefix("favorite:")
+            .map(|id| Self::Favorite(id.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+/// Sent with every request of a stream
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamAuth {
+    pub headers: Vec<(String, String)>,
+    pub basic_auth: Option<BasicAuth>,
+}
+
+impl StreamAuth {
+    /// The headers to send, including the `Authorization` header of the login
+    pub fn header_map(&self) -> Result<HeaderMap, Error> {
+        let mut map = HeaderMap::new();
+
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("\"{name}\" isn't a valid header name"))?;
+            let mut value = HeaderValue::from_str(value)
+                .map_err(|_| format!("the value of header \"{name}\" isn't valid"))?;
+            value.set_sensitive(true);
+            map.insert(name, value);
+        }
+
+        if let Some(auth) = &self.basic_auth {
+            let encoded = BASE64.encode(format!("{}:{}", auth.username, auth.password));
+            let mut value = HeaderValue::from_str(&format!("Basic {encoded}"))?;
+            value.set_sensitive(true);
+            map.insert(AUTHORIZATION, value);
+        }
+
+        Ok(map)
+    }
+
+    /// Arguments which make yt-dlp send the headers
+    pub fn ytdl_args(&self) -> Result<Vec<String>, Error> {
+        let mut args = Vec::new();
+        for (name, value) in &self.header_map()? {
+            args.push("--add-header".to_string());
+            args.push(format!("{name}:{}", value.to_str()?));
+        }
+
+        Ok(args)
+    }
+}
+
+/// Parses headers in the form `Name: value; Other-Name: value`
+pub fn parse_headers(value: &str) -> Result<Vec<(String, String)>, String> {
+    let headers = value
+        .split(';')
+        .filter(|v| !v.trim().is_empty())
+        .map(|header| {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| format!("\"{}\" is missing a `:`", header.trim()))?;
+            let (name, value) = (name.trim(), value.trim());
+
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("\"{name}\" isn't a valid header name"))?;
+            HeaderValue::from_str(value)
+                .map_err(|_| format!("the value of \"{name}\" isn't a valid header value"))?;
+
+            Ok((name.to_string(), value.to_string()))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(headers)
+}
+
+/// Decodes a key given in the config
+pub fn decode_key(value: &str) -> Result<[u8; KEY_LEN], String> {
+    let bytes = BASE64
+        .decode(value.trim())
+        .map_err(|e| format!("the key isn't valid base64: {e}"))?;
+
+    bytes
+        .try_into()
+        .map_err(|v: Vec<u8>| format!("the key has to be {KEY_LEN} bytes, got {}", v.len()))
+}
+
+/// Encrypts and decrypts stream credentials
+#[derive(Clone)]
+pub struct Credentials {
+    cipher: Arc<ChaCha20Poly1305>,
+}
+
+impl Credentials {
+    /// Uses the configured key, or the key file in the data directory (which is created if it
+    /// doesn't exist)
+    pub fn load(config: &Config) -> Result<Self, Error> {
+        let key = match &config.outbound.credentials_key {
+            Some(key) => decode_key(key.expose())?,
+            None => load_or_generate_key(&config.project_dirs.data_local_dir().join(KEY_FILE))?,
+        };
+
+        Ok(Self {
+            cipher: Arc::new(ChaCha20Poly1305::new(Key::from_slice(&key))),
+        })
+    }
+
+    /// Encrypts `auth`, returning the nonce and ciphertext as base64
+    fn encrypt(
+        &self,
+        guild_id: GuildId,
+        target: &Target,
+        auth: &StreamAuth,
+    ) -> Result<String, Error> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plaintext = serde_json::to_vec(auth)?;
+        let aad = aad(guild_id, target);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| "couldn't encrypt the credentials")?;
+
+        let mut value = nonce.to_vec();
+        value.extend_from_slice(&ciphertext);
+
+        Ok(BASE64.encode(value))
+    }
+
+    fn decrypt(
+        &self,
+        guild_id: GuildId,
+        target: &Target,
+        value: &str,
+    ) -> Result<StreamAuth, Error> {
+        let bytes = BASE64.decode(value)?;
+        if bytes.len() < NONCE_LEN {
+            return Err("the encrypted credentials are too short".into());
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let aad = aad(guild_id, target);
+
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| "couldn't decrypt the credentials, did the key change?")?;
+
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    pub async fn set(
+        &self,
+        database: &DatabaseContext,
+        guild_id: GuildId,
+        target: &Target,
+        auth: &StreamAuth,
+    ) -> Result<(), Error> {
+        let secret = self.encrypt(guild_id, target, auth)?;
+
+        let mut conn = database.get_connection().await?;
+        actions::stream_credential_insert_or_update(
+            &mut conn,
+            guild_id,
+            &target.to_string(),
+            &secret,
+        )
+        .await
+    }
+
+    /// The credentials for playing `url`: Those of the favorite if it's played as one, otherwise
+    /// the ones with the longest matching URL prefix
+    pub async fn find(
+        &self,
+        database: &DatabaseContext,
+        guild_id: GuildId,
+        url: &Url,
+        favorite_id: Option<&str>,
+    ) -> Result<Option<StreamAuth>, Error> {
+        let mut conn = database.get_connection().await?;
+        let rows = actions::stream_credentials_get_by_guild(&mut conn, guild_id).await?;
+        drop(conn);
+
+        let best = rows
+            .into_iter()
+            .filter_map(|v| {
+                let target = Target::parse(&v.target)?;
+                let rank = match &target {
+                    Target::Favorite(id) if Some(id.as_str()) == favorite_id => usize::MAX,
+                    Target::Url(prefix) if url.as_str().starts_with(prefix.as_str()) => {
+                        prefix.len()
+                    }
+                    _ => return None,
+                };
+
+                Some((rank, target, v.secret))
+            })
+            .max_by_key(|(rank, _, _)| *rank);
+
+        let Some((_, target, secret)) = best else {
+            return Ok(None);
+        };
+
+        self.decrypt(guild_id, &target, &secret).map(Some)
+    }
+}
+
+fn aad(guild_id: GuildId, target: &Target) -> String {
+    format!("{guild_id}/{target}")
+}
+
+fn load_or_generate_key(path: &Path) -> Result<[u8; KEY_LEN], Error> {
+    match std::fs::read_to_string(path) {
+        Ok(v) => return decode_key(&v).map_err(|e| format!("\"{}\": {e}", path.display()).into()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(format!("couldn't read \"{}\": {e}", path.display()).into()),
+    }
+
+    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("couldn't create \"{}\": {e}", path.display()))?;
+    file.write_all(BASE64.encode(key).as_bytes())?;
+
+    tracing::info!(
+        "generated the key of the stream credentials at \"{}\"",
+        path.display()
+    );
+
+    Ok(key.into())
+}
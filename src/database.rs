@@ -1,14 +1,39 @@
-use chrono::{DateTime, Utc};
-use poise::serenity_prelude::GuildId;
-use sqlx::{pool::PoolConnection, sqlite::SqlitePoolOptions, Sqlite};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc, Weekday};
+use poise::serenity_prelude::{ChannelId, GuildId, UserId};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::migrate::Migrator;
+use sqlx::{pool::PoolConnection, sqlite::SqlitePoolOptions, Sqlite, SqliteConnection};
 
 use crate::discord::Error;
 
-#[derive(Clone)]
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, Clone)]
 pub struct DatabaseContext {
     pub pool: sqlx::Pool<Sqlite>,
 }
 
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+/// Contents of all tables, as written by `discomfort-fm db export`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Dump {
+    /// Version of the last migration applied to the exported database
+    pub schema_version: i64,
+    /// Rows by table, with their values by column
+    pub tables: BTreeMap<String, Vec<Map<String, Value>>>,
+}
+
 impl DatabaseContext {
     pub async fn new(connection_uri: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let pool = SqlitePoolOptions::new()
@@ -20,7 +45,37 @@ impl DatabaseContext {
     }
 
     pub async fn init(&self) -> Result<(), Box<dyn std::error::Error>> {
-        sqlx::migrate!("./migrations").run(&self.pool).await?;
+        MIGRATOR.run(&self.pool).await?;
+
+        Ok(())
+    }
+
+    /// All migrations known to the bot and whether they were applied
+    pub async fn migrations(&self) -> Result<Vec<MigrationStatus>, Error> {
+        let mut conn = self.get_connection().await?;
+        let applied = applied_migrations(&mut conn).await?;
+
+        Ok(MIGRATOR
+            .iter()
+            .map(|v| MigrationStatus {
+                version: v.version,
+                description: v.description.to_string(),
+                applied: applied.contains(&v.version),
+            })
+            .collect())
+    }
+
+    /// Checks that the database is reachable and all migrations were applied
+    pub async fn check_ready(&self) -> Result<(), Error> {
+        let missing = self
+            .migrations()
+            .await?
+            .iter()
+            .filter(|v| !v.applied)
+            .count();
+        if missing > 0 {
+            return Err(format!("{missing} migrations weren't applied").into());
+        }
 
         Ok(())
     }
@@ -28,6 +83,168 @@ impl DatabaseContext {
     pub async fn get_connection(&self) -> Result<PoolConnection<Sqlite>, Error> {
         Ok(self.pool.acquire().await?)
     }
+
+    /// Checks that the database can be written to, without leaving anything behind
+    pub async fn check_writable(&self) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("CREATE TABLE _doctor (value INTEGER)")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO _doctor (value) VALUES (1)")
+            .execute(&mut *tx)
+            .await?;
+        tx.rollback().await?;
+
+        Ok(())
+    }
+
+    /// Reads all rows of all tables
+    pub async fn export(&self) -> Result<Dump, Error> {
+        let mut conn = self.get_connection().await?;
+        let schema_version = applied_migrations(&mut conn)
+            .await?
+            .into_iter()
+            .max()
+            .unwrap_or(0);
+
+        let mut tables = BTreeMap::new();
+        for table in table_names(&mut conn).await? {
+            let columns = column_names(&mut conn, &table).await?;
+            let object = columns
+                .iter()
+                .map(|v| format!("'{v}', \"{v}\""))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            let rows: Vec<String> =
+                sqlx::query_scalar(&format!("SELECT json_object({object}) FROM \"{table}\""))
+                    .fetch_all(&mut *conn)
+                    .await?;
+            let rows = rows
+                .iter()
+                .map(|v| serde_json::from_str(v))
+                .collect::<Result<_, _>>()?;
+
+            tables.insert(table, rows);
+        }
+
+        Ok(Dump {
+            schema_version,
+            tables,
+        })
+    }
+
+    /// Replaces the contents of every table in `dump` with its rows, returning the number of
+    /// inserted rows.
+    ///
+    /// The database has to be migrated at least to the schema of the dump.
+    pub async fn import(&self, dump: &Dump) -> Result<usize, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let schema_version = applied_migrations(&mut tx)
+            .await?
+            .into_iter()
+            .max()
+            .unwrap_or(0);
+        if dump.schema_version > schema_version {
+            return Err(format!(
+                "the dump is from a newer version (schema {}, the database is at {schema_version})",
+                dump.schema_version
+            )
+            .into());
+        }
+
+        let tables = table_names(&mut tx).await?;
+        let mut imported = 0;
+
+        for (table, rows) in &dump.tables {
+            // Names are put into the query, so only known ones are accepted
+            if !tables.contains(table) {
+                return Err(format!("unknown table \"{table}\"").into());
+            }
+            let columns = column_names(&mut tx, table).await?;
+
+            sqlx::query(&format!("DELETE FROM \"{table}\""))
+                .execute(&mut *tx)
+                .await?;
+
+            for row in rows {
+                if let Some(unknown) = row.keys().find(|v| !columns.contains(v)) {
+                    return Err(format!("unknown column \"{unknown}\" in table \"{table}\"").into());
+                }
+
+                let names = row
+                    .keys()
+                    .map(|v| format!("\"{v}\""))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let placeholders = vec!["?"; row.len()].join(", ");
+                let sql = format!("INSERT INTO \"{table}\" ({names}) VALUES ({placeholders})");
+
+                let mut query = sqlx::query(&sql);
+                for value in row.values() {
+                    query = match value {
+                        Value::Null => query.bind(None::<String>),
+                        Value::Bool(v) => query.bind(*v),
+                        Value::Number(v) => match v.as_i64() {
+                            Some(v) => query.bind(v),
+                            None => query.bind(v.as_f64()),
+                        },
+                        Value::String(v) => query.bind(v.clone()),
+                        _ => {
+                            return Err(
+                                format!("unsupported value {value} in table \"{table}\"").into()
+                            )
+                        }
+                    };
+                }
+                query.execute(&mut *tx).await?;
+
+                imported += 1;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(imported)
+    }
+}
+
+/// Versions of the migrations which were applied successfully
+async fn applied_migrations(conn: &mut SqliteConnection) -> Result<Vec<i64>, Error> {
+    // The table is created with the first migration
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !exists {
+        return Ok(Vec::new());
+    }
+
+    Ok(
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+            .fetch_all(&mut *conn)
+            .await?,
+    )
+}
+
+/// Tables with data of the bot, without the internal ones of SQLite and sqlx
+async fn table_names(conn: &mut SqliteConnection) -> Result<Vec<String>, Error> {
+    Ok(sqlx::query_scalar(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name != '_sqlx_migrations' ORDER BY name",
+    )
+    .fetch_all(&mut *conn)
+    .await?)
+}
+
+async fn column_names(conn: &mut SqliteConnection, table: &str) -> Result<Vec<String>, Error> {
+    Ok(
+        sqlx::query_scalar("SELECT name FROM pragma_table_info(?) ORDER BY cid")
+            .bind(table)
+            .fetch_all(&mut *conn)
+            .await?,
+    )
 }
 
 pub trait FromRawRow {
@@ -81,11 +298,370 @@ impl FromRawRow for GuildRow {
     }
 }
 
+#[derive(Debug, sqlx::FromRow)]
+pub struct LibraryTrackRowRaw {
+    pub id: String,
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration_ms: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LibraryTrackRow {
+    pub id: String,
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl FromRawRow for LibraryTrackRow {
+    type RawRow = LibraryTrackRowRaw;
+
+    fn from_raw_row(raw_row: Self::RawRow) -> Self {
+        LibraryTrackRow {
+            id: raw_row.id,
+            path: PathBuf::from(raw_row.path),
+            title: raw_row.title,
+            artist: raw_row.artist,
+            album: raw_row.album,
+            duration: raw_row
+                .duration_ms
+                .and_then(|v| u64::try_from(v).ok())
+                .map(Duration::from_millis),
+        }
+    }
+}
+
+impl LibraryTrackRow {
+    /// `Artist - Title`, falling back to the file name if there are no tags
+    pub fn display_name(&self) -> String {
+        let title = self.title.clone().unwrap_or_else(|| {
+            self.path
+                .file_name()
+                .map(|v| v.to_string_lossy().into_owned())
+                .unwrap_or_else(|| self.path.display().to_string())
+        });
+
+        match &self.artist {
+            Some(artist) => format!("{artist} - {title}"),
+            None => title,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct PodcastRowRaw {
+    pub id: String,
+    pub guild_id: String,
+    pub feed_url: String,
+    pub title: String,
+    pub announce_channel_id: Option<String>,
+    pub last_episode_id: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PodcastRow {
+    pub id: String,
+    pub guild_id: GuildId,
+    pub feed_url: String,
+    pub title: String,
+    pub announce_channel_id: Option<ChannelId>,
+    pub last_episode_id: Option<String>,
+}
+
+impl FromRawRow for PodcastRow {
+    type RawRow = PodcastRowRaw;
+
+    fn from_raw_row(raw_row: Self::RawRow) -> Self {
+        PodcastRow {
+            guild_id: GuildId::new(raw_row.guild_id.parse().unwrap_or_else(|_| {
+                panic!(
+                    "couldn't parse guild-id from \"{}\" (podcast {})",
+                    &raw_row.guild_id, &raw_row.id
+                )
+            })),
+            announce_channel_id: raw_row.announce_channel_id.map(|v| {
+                ChannelId::new(v.parse().unwrap_or_else(|_| {
+                    panic!(
+                        "couldn't parse channel-id from \"{}\" (podcast {})",
+                        &v, &raw_row.id
+                    )
+                }))
+            }),
+            id: raw_row.id,
+            feed_url: raw_row.feed_url,
+            title: raw_row.title,
+            last_episode_id: raw_row.last_episode_id,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct RelayTokenRowRaw {
+    pub guild_id: String,
+    pub token: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayTokenRow {
+    pub guild_id: GuildId,
+    pub token: String,
+}
+
+impl FromRawRow for RelayTokenRow {
+    type RawRow = RelayTokenRowRaw;
+
+    fn from_raw_row(raw_row: Self::RawRow) -> Self {
+        RelayTokenRow {
+            guild_id: GuildId::new(raw_row.guild_id.parse().unwrap_or_else(|_| {
+                panic!(
+                    "couldn't parse guild-id from \"{}\" (relay token)",
+                    &raw_row.guild_id
+                )
+            })),
+            token: raw_row.token,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ResolutionRowRaw {
+    pub output: String,
+    pub expires_at: String,
+}
+
+/// What yt-dlp resolved a URL to
+#[derive(Debug, Clone)]
+pub struct ResolutionRow {
+    /// JSON output of yt-dlp
+    pub output: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl FromRawRow for ResolutionRow {
+    type RawRow = ResolutionRowRaw;
+
+    fn from_raw_row(raw_row: Self::RawRow) -> Self {
+        ResolutionRow {
+            expires_at: raw_row.expires_at.parse().unwrap_or_else(|_| {
+                panic!(
+                    "couldn't parse expiry from \"{}\" (resolution)",
+                    &raw_row.expires_at
+                )
+            }),
+            output: raw_row.output,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct FavoriteRowRaw {
+    pub id: String,
+    pub user_id: String,
+    pub title: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct FavoriteRow {
+    pub id: String,
+    /// The user who added the favorite
+    pub user_id: UserId,
+    pub title: String,
+    pub uri: String,
+}
+
+impl FromRawRow for FavoriteRow {
+    type RawRow = FavoriteRowRaw;
+
+    fn from_raw_row(raw_row: Self::RawRow) -> Self {
+        FavoriteRow {
+            user_id: UserId::new(raw_row.user_id.parse().unwrap_or_else(|_| {
+                panic!(
+                    "couldn't parse user-id from \"{}\" (favorite {})",
+                    &raw_row.user_id, &raw_row.id
+                )
+            })),
+            id: raw_row.id,
+            title: raw_row.title,
+            uri: raw_row.uri,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct StreamCredentialRowRaw {
+    pub target: String,
+    pub secret: String,
+}
+
+/// Headers and login of a stream, which are sent when playing it
+#[derive(Debug, Clone)]
+pub struct StreamCredentialRow {
+    /// `url:<prefix>` or `favorite:<id>`
+    pub target: String,
+    /// Encrypted, only [`crate::credentials::Credentials`] can read it
+    pub secret: String,
+}
+
+impl FromRawRow for StreamCredentialRow {
+    type RawRow = StreamCredentialRowRaw;
+
+    fn from_raw_row(raw_row: Self::RawRow) -> Self {
+        StreamCredentialRow {
+            target: raw_row.target,
+            secret: raw_row.secret,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct GuildDomainRowRaw {
+    pub domain: String,
+    pub blocked: bool,
+}
+
+/// A domain on the allow- or blocklist of a guild
+#[derive(Debug, Clone)]
+pub struct GuildDomainRow {
+    pub domain: String,
+    pub blocked: bool,
+}
+
+impl FromRawRow for GuildDomainRow {
+    type RawRow = GuildDomainRowRaw;
+
+    fn from_raw_row(raw_row: Self::RawRow) -> Self {
+        GuildDomainRow {
+            domain: raw_row.domain,
+            blocked: raw_row.blocked,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ScheduleRowRaw {
+    pub id: String,
+    pub guild_id: String,
+    pub channel_id: String,
+    pub url: String,
+    pub time: String,
+    pub weekdays: i64,
+    pub last_run: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScheduleRow {
+    pub id: String,
+    pub guild_id: GuildId,
+    /// Voice channel joined by the schedule
+    pub channel_id: ChannelId,
+    pub url: String,
+    /// Local time of day
+    pub time: NaiveTime,
+    /// Bit 0 is monday, bit 6 sunday
+    pub weekdays: u8,
+    /// Local date of the last run
+    pub last_run: Option<NaiveDate>,
+}
+
+impl ScheduleRow {
+    pub fn runs_on(&self, weekday: Weekday) -> bool {
+        self.weekdays & (1 << weekday.num_days_from_monday()) != 0
+    }
+}
+
+impl FromRawRow for ScheduleRow {
+    type RawRow = ScheduleRowRaw;
+
+    fn from_raw_row(raw_row: Self::RawRow) -> Self {
+        ScheduleRow {
+            guild_id: GuildId::new(raw_row.guild_id.parse().unwrap_or_else(|_| {
+                panic!(
+                    "couldn't parse guild-id from \"{}\" (schedule {})",
+                    &raw_row.guild_id, &raw_row.id
+                )
+            })),
+            channel_id: ChannelId::new(raw_row.channel_id.parse().unwrap_or_else(|_| {
+                panic!(
+                    "couldn't parse channel-id from \"{}\" (schedule {})",
+                    &raw_row.channel_id, &raw_row.id
+                )
+            })),
+            time: NaiveTime::parse_from_str(&raw_row.time, "%H:%M").unwrap_or_else(|_| {
+                panic!(
+                    "couldn't parse time \"{}\" (schedule {})",
+                    &raw_row.time, &raw_row.id
+                )
+            }),
+            weekdays: raw_row.weekdays as u8,
+            last_run: raw_row.last_run.map(|v| {
+                v.parse().unwrap_or_else(|_| {
+                    panic!("couldn't parse date \"{}\" (schedule {})", &v, &raw_row.id)
+                })
+            }),
+            id: raw_row.id,
+            url: raw_row.url,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct HistoryRowRaw {
+    pub id: i64,
+    pub url: String,
+    pub title: Option<String>,
+    pub played_at: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryRow {
+    pub url: String,
+    pub title: Option<String>,
+    pub played_at: DateTime<Utc>,
+}
+
+impl HistoryRow {
+    /// The title of the track, or the URL if no title is known
+    pub fn display_name(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.url)
+    }
+}
+
+impl FromRawRow for HistoryRow {
+    type RawRow = HistoryRowRaw;
+
+    fn from_raw_row(raw_row: Self::RawRow) -> Self {
+        HistoryRow {
+            played_at: raw_row.played_at.parse().unwrap_or_else(|_| {
+                panic!(
+                    "couldn't parse timestamp \"{}\" (history {})",
+                    &raw_row.played_at, raw_row.id
+                )
+            }),
+            url: raw_row.url,
+            title: raw_row.title,
+        }
+    }
+}
+
 pub mod actions {
-    use crate::database::{FromRawRow, GuildRow, GuildRowRaw};
+    use std::path::Path;
+    use std::time::Duration;
+
+    use crate::database::{
+        FavoriteRow, FavoriteRowRaw, FromRawRow, GuildDomainRow, GuildDomainRowRaw, GuildRow,
+        GuildRowRaw, HistoryRow, HistoryRowRaw, LibraryTrackRow, LibraryTrackRowRaw, PodcastRow,
+        PodcastRowRaw, RelayTokenRow, RelayTokenRowRaw, ResolutionRow, ResolutionRowRaw,
+        ScheduleRow, ScheduleRowRaw, StreamCredentialRow, StreamCredentialRowRaw,
+    };
     use crate::discord::Error;
-    use chrono::Utc;
-    use poise::serenity_prelude::GuildId;
+    use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+    use poise::serenity_prelude::{ChannelId, GuildId, UserId};
     use sqlx::SqliteConnection;
 
     pub async fn volume_insert_or_update(
@@ -138,4 +714,691 @@ pub mod actions {
 
         Ok(default_volume)
     }
+
+    pub async fn library_track_insert_or_update(
+        conn: &mut SqliteConnection,
+        path: &Path,
+        title: Option<&str>,
+        artist: Option<&str>,
+        album: Option<&str>,
+        duration: Option<Duration>,
+    ) -> Result<(), Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let _res = sqlx::query(
+            r"INSERT INTO library_tracks (id, path, title, artist, album, duration_ms, created_at, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ON CONFLICT(path) DO UPDATE SET title=excluded.title, artist=excluded.artist, album=excluded.album,
+            duration_ms=excluded.duration_ms, updated_at=excluded.updated_at",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(path.to_string_lossy())
+        .bind(title)
+        .bind(artist)
+        .bind(album)
+        .bind(duration.map(|v| v.as_millis() as i64))
+        .bind(&now)
+        .bind(&now)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes all library tracks which weren't updated since `indexed_since` (RFC 3339)
+    pub async fn library_tracks_remove_stale(
+        conn: &mut SqliteConnection,
+        indexed_since: &str,
+    ) -> Result<u64, Error> {
+        let res =
+            sqlx::query("DELETE FROM library_tracks WHERE updated_at IS NULL OR updated_at < ?1")
+                .bind(indexed_since)
+                .execute(conn)
+                .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    pub async fn library_tracks_search(
+        conn: &mut SqliteConnection,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<LibraryTrackRow>, Error> {
+        let pattern = format!("%{}%", query.trim());
+
+        let tracks = sqlx::query_as::<_, LibraryTrackRowRaw>(
+            r"SELECT id, path, title, artist, album, duration_ms FROM library_tracks
+        WHERE title LIKE ?1 OR artist LIKE ?1 OR album LIKE ?1 OR path LIKE ?1
+        ORDER BY artist, album, title, path LIMIT ?2",
+        )
+        .bind(pattern)
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(tracks
+            .into_iter()
+            .map(LibraryTrackRow::from_raw_row)
+            .collect())
+    }
+
+    pub async fn library_track_get(
+        conn: &mut SqliteConnection,
+        id: &str,
+    ) -> Result<Option<LibraryTrackRow>, Error> {
+        let track = sqlx::query_as::<_, LibraryTrackRowRaw>(
+            "SELECT id, path, title, artist, album, duration_ms FROM library_tracks WHERE id = ?1",
+        )
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(track.map(LibraryTrackRow::from_raw_row))
+    }
+
+    /// Subscribes a guild to a podcast feed. Returns `false` if it was already subscribed
+    pub async fn podcast_insert(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        feed_url: &str,
+        title: &str,
+        announce_channel_id: Option<ChannelId>,
+        last_episode_id: Option<&str>,
+    ) -> Result<bool, Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let res = sqlx::query(
+            r"INSERT INTO podcasts (id, guild_id, feed_url, title, announce_channel_id, last_episode_id, created_at, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ON CONFLICT(guild_id, feed_url) DO NOTHING",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(guild_id.get().to_string())
+        .bind(feed_url)
+        .bind(title)
+        .bind(announce_channel_id.map(|v| v.get().to_string()))
+        .bind(last_episode_id)
+        .bind(&now)
+        .bind(&now)
+        .execute(conn)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn podcast_delete(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        id: &str,
+    ) -> Result<bool, Error> {
+        let res = sqlx::query("DELETE FROM podcasts WHERE id = ?1 AND guild_id = ?2")
+            .bind(id)
+            .bind(guild_id.get().to_string())
+            .execute(conn)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn podcasts_get_by_guild(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+    ) -> Result<Vec<PodcastRow>, Error> {
+        let podcasts = sqlx::query_as::<_, PodcastRowRaw>(
+            "SELECT * FROM podcasts WHERE guild_id = ?1 ORDER BY title",
+        )
+        .bind(guild_id.get().to_string())
+        .fetch_all(conn)
+        .await?;
+
+        Ok(podcasts.into_iter().map(PodcastRow::from_raw_row).collect())
+    }
+
+    pub async fn podcasts_get_all(conn: &mut SqliteConnection) -> Result<Vec<PodcastRow>, Error> {
+        let podcasts = sqlx::query_as::<_, PodcastRowRaw>("SELECT * FROM podcasts")
+            .fetch_all(conn)
+            .await?;
+
+        Ok(podcasts.into_iter().map(PodcastRow::from_raw_row).collect())
+    }
+
+    pub async fn podcast_update_last_episode(
+        conn: &mut SqliteConnection,
+        id: &str,
+        last_episode_id: &str,
+    ) -> Result<(), Error> {
+        let _res =
+            sqlx::query("UPDATE podcasts SET last_episode_id = ?1, updated_at = ?2 WHERE id = ?3")
+                .bind(last_episode_id)
+                .bind(Utc::now().to_rfc3339())
+                .bind(id)
+                .execute(conn)
+                .await?;
+
+        Ok(())
+    }
+
+    pub async fn podcast_progress_get(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        episode_url: &str,
+    ) -> Result<Option<Duration>, Error> {
+        let position_ms = sqlx::query_scalar::<_, i64>(
+            "SELECT position_ms FROM podcast_progress WHERE guild_id = ?1 AND episode_url = ?2",
+        )
+        .bind(guild_id.get().to_string())
+        .bind(episode_url)
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(position_ms
+            .and_then(|v| u64::try_from(v).ok())
+            .map(Duration::from_millis))
+    }
+
+    pub async fn podcast_progress_insert_or_update(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        episode_url: &str,
+        position: Duration,
+    ) -> Result<(), Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let _res = sqlx::query(
+            r"INSERT INTO podcast_progress (guild_id, episode_url, position_ms, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT(guild_id, episode_url) DO UPDATE SET position_ms=excluded.position_ms, updated_at=excluded.updated_at",
+        )
+        .bind(guild_id.get().to_string())
+        .bind(episode_url)
+        .bind(position.as_millis() as i64)
+        .bind(&now)
+        .bind(&now)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn podcast_progress_delete(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        episode_url: &str,
+    ) -> Result<(), Error> {
+        let _res =
+            sqlx::query("DELETE FROM podcast_progress WHERE guild_id = ?1 AND episode_url = ?2")
+                .bind(guild_id.get().to_string())
+                .bind(episode_url)
+                .execute(conn)
+                .await?;
+
+        Ok(())
+    }
+
+    pub async fn relay_token_insert_or_update(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        token: &str,
+    ) -> Result<(), Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let _res = sqlx::query(
+            r"INSERT INTO relay_tokens (guild_id, token, created_at, updated_at) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(guild_id) DO UPDATE SET token=excluded.token, updated_at=excluded.updated_at",
+        )
+        .bind(guild_id.get().to_string())
+        .bind(token)
+        .bind(&now)
+        .bind(&now)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns `false` if the guild didn't have a token
+    pub async fn relay_token_delete(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+    ) -> Result<bool, Error> {
+        let res = sqlx::query("DELETE FROM relay_tokens WHERE guild_id = ?1")
+            .bind(guild_id.get().to_string())
+            .execute(conn)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn relay_tokens_get_all(
+        conn: &mut SqliteConnection,
+    ) -> Result<Vec<RelayTokenRow>, Error> {
+        let tokens = sqlx::query_as::<_, RelayTokenRowRaw>("SELECT * FROM relay_tokens")
+            .fetch_all(conn)
+            .await?;
+
+        Ok(tokens
+            .into_iter()
+            .map(RelayTokenRow::from_raw_row)
+            .collect())
+    }
+
+    /// Returns the resolution of `url`, unless it expired
+    pub async fn resolution_get(
+        conn: &mut SqliteConnection,
+        url: &str,
+    ) -> Result<Option<ResolutionRow>, Error> {
+        let resolution = sqlx::query_as::<_, ResolutionRowRaw>(
+            "SELECT output, expires_at FROM resolutions WHERE url = ?1 AND expires_at > ?2",
+        )
+        .bind(url)
+        .bind(Utc::now().to_rfc3339())
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(resolution.map(ResolutionRow::from_raw_row))
+    }
+
+    /// Saves the resolution of `url` and removes expired ones
+    pub async fn resolution_insert_or_update(
+        conn: &mut SqliteConnection,
+        url: &str,
+        output: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let now = Utc::now().to_rfc3339();
+
+        sqlx::query("DELETE FROM resolutions WHERE expires_at <= ?1")
+            .bind(&now)
+            .execute(&mut *conn)
+            .await?;
+
+        let _res = sqlx::query(
+            r"INSERT INTO resolutions (url, output, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(url) DO UPDATE SET output=excluded.output, created_at=excluded.created_at, expires_at=excluded.expires_at",
+        )
+        .bind(url)
+        .bind(output)
+        .bind(&now)
+        .bind(expires_at.to_rfc3339())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn resolution_delete(conn: &mut SqliteConnection, url: &str) -> Result<(), Error> {
+        let _res = sqlx::query("DELETE FROM resolutions WHERE url = ?1")
+            .bind(url)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remembers that commands were registered in a guild, so they can be removed later
+    pub async fn command_guild_insert(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+    ) -> Result<(), Error> {
+        let _res = sqlx::query(
+            "INSERT OR IGNORE INTO command_guilds (guild_id, created_at) VALUES (?1, ?2)",
+        )
+        .bind(guild_id.get().to_string())
+        .bind(Utc::now().to_rfc3339())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn command_guild_delete(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+    ) -> Result<(), Error> {
+        let _res = sqlx::query("DELETE FROM command_guilds WHERE guild_id = ?1")
+            .bind(guild_id.get().to_string())
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn command_guilds_get_all(
+        conn: &mut SqliteConnection,
+    ) -> Result<Vec<GuildId>, Error> {
+        let guild_ids: Vec<String> = sqlx::query_scalar("SELECT guild_id FROM command_guilds")
+            .fetch_all(conn)
+            .await?;
+
+        Ok(guild_ids
+            .iter()
+            .filter_map(|v| v.parse().ok())
+            .map(GuildId::new)
+            .collect())
+    }
+
+    pub async fn favorites_get_by_guild(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+    ) -> Result<Vec<FavoriteRow>, Error> {
+        let favorites = sqlx::query_as::<_, FavoriteRowRaw>(
+            "SELECT * FROM favorites WHERE guild_id = ?1 ORDER BY title",
+        )
+        .bind(guild_id.get().to_string())
+        .fetch_all(conn)
+        .await?;
+
+        Ok(favorites
+            .into_iter()
+            .map(FavoriteRow::from_raw_row)
+            .collect())
+    }
+
+    /// Searches the favorites of a guild by title
+    pub async fn favorites_search(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<FavoriteRow>, Error> {
+        let favorites = sqlx::query_as::<_, FavoriteRowRaw>(
+            "SELECT * FROM favorites WHERE guild_id = ?1 AND title LIKE ?2 ORDER BY title LIMIT ?3",
+        )
+        .bind(guild_id.get().to_string())
+        .bind(format!("%{}%", query.trim()))
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(favorites
+            .into_iter()
+            .map(FavoriteRow::from_raw_row)
+            .collect())
+    }
+
+    pub async fn favorite_get(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        id: &str,
+    ) -> Result<Option<FavoriteRow>, Error> {
+        let favorite = sqlx::query_as::<_, FavoriteRowRaw>(
+            "SELECT * FROM favorites WHERE id = ?1 AND guild_id = ?2",
+        )
+        .bind(id)
+        .bind(guild_id.get().to_string())
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(favorite.map(FavoriteRow::from_raw_row))
+    }
+
+    pub async fn favorite_insert(
+        conn: &mut SqliteConnection,
+        user_id: UserId,
+        guild_id: GuildId,
+        title: &str,
+        uri: &str,
+    ) -> Result<(), Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let _res = sqlx::query(
+            r"INSERT INTO favorites (id, user_id, guild_id, title, uri, created_at, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(user_id.get().to_string())
+        .bind(guild_id.get().to_string())
+        .bind(title)
+        .bind(uri)
+        .bind(&now)
+        .bind(&now)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn favorite_delete(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        id: &str,
+    ) -> Result<bool, Error> {
+        let res = sqlx::query("DELETE FROM favorites WHERE id = ?1 AND guild_id = ?2")
+            .bind(id)
+            .bind(guild_id.get().to_string())
+            .execute(conn)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn stream_credential_insert_or_update(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        target: &str,
+        secret: &str,
+    ) -> Result<(), Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let _res = sqlx::query(
+            r"INSERT INTO stream_credentials (guild_id, target, secret, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT(guild_id, target) DO UPDATE SET secret=excluded.secret, updated_at=excluded.updated_at",
+        )
+        .bind(guild_id.get().to_string())
+        .bind(target)
+        .bind(secret)
+        .bind(&now)
+        .bind(&now)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns `false` if there were no credentials for `target`
+    pub async fn stream_credential_delete(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        target: &str,
+    ) -> Result<bool, Error> {
+        let res = sqlx::query("DELETE FROM stream_credentials WHERE guild_id = ?1 AND target = ?2")
+            .bind(guild_id.get().to_string())
+            .bind(target)
+            .execute(conn)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn stream_credentials_get_by_guild(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+    ) -> Result<Vec<StreamCredentialRow>, Error> {
+        let credentials = sqlx::query_as::<_, StreamCredentialRowRaw>(
+            "SELECT target, secret FROM stream_credentials WHERE guild_id = ?1 ORDER BY target",
+        )
+        .bind(guild_id.get().to_string())
+        .fetch_all(conn)
+        .await?;
+
+        Ok(credentials
+            .into_iter()
+            .map(StreamCredentialRow::from_raw_row)
+            .collect())
+    }
+
+    pub async fn guild_domain_insert_or_update(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        domain: &str,
+        blocked: bool,
+    ) -> Result<(), Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let _res = sqlx::query(
+            r"INSERT INTO guild_domains (guild_id, domain, blocked, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT(guild_id, domain) DO UPDATE SET blocked=excluded.blocked, updated_at=excluded.updated_at",
+        )
+        .bind(guild_id.get().to_string())
+        .bind(domain)
+        .bind(blocked)
+        .bind(&now)
+        .bind(&now)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns `false` if the domain wasn't on a list
+    pub async fn guild_domain_delete(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        domain: &str,
+    ) -> Result<bool, Error> {
+        let res = sqlx::query("DELETE FROM guild_domains WHERE guild_id = ?1 AND domain = ?2")
+            .bind(guild_id.get().to_string())
+            .bind(domain)
+            .execute(conn)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn guild_domains_get(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+    ) -> Result<Vec<GuildDomainRow>, Error> {
+        let domains = sqlx::query_as::<_, GuildDomainRowRaw>(
+            "SELECT domain, blocked FROM guild_domains WHERE guild_id = ?1 ORDER BY domain",
+        )
+        .bind(guild_id.get().to_string())
+        .fetch_all(conn)
+        .await?;
+
+        Ok(domains
+            .into_iter()
+            .map(GuildDomainRow::from_raw_row)
+            .collect())
+    }
+
+    pub async fn schedules_get_by_guild(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+    ) -> Result<Vec<ScheduleRow>, Error> {
+        let schedules = sqlx::query_as::<_, ScheduleRowRaw>(
+            "SELECT * FROM schedules WHERE guild_id = ?1 ORDER BY time",
+        )
+        .bind(guild_id.get().to_string())
+        .fetch_all(conn)
+        .await?;
+
+        Ok(schedules
+            .into_iter()
+            .map(ScheduleRow::from_raw_row)
+            .collect())
+    }
+
+    pub async fn schedules_get_all(conn: &mut SqliteConnection) -> Result<Vec<ScheduleRow>, Error> {
+        let schedules = sqlx::query_as::<_, ScheduleRowRaw>("SELECT * FROM schedules")
+            .fetch_all(conn)
+            .await?;
+
+        Ok(schedules
+            .into_iter()
+            .map(ScheduleRow::from_raw_row)
+            .collect())
+    }
+
+    pub async fn schedule_insert(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        url: &str,
+        time: NaiveTime,
+        weekdays: u8,
+    ) -> Result<(), Error> {
+        let now = Utc::now().to_rfc3339();
+
+        let _res = sqlx::query(
+            r"INSERT INTO schedules (id, guild_id, channel_id, url, time, weekdays, created_at, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(guild_id.get().to_string())
+        .bind(channel_id.get().to_string())
+        .bind(url)
+        .bind(time.format("%H:%M").to_string())
+        .bind(weekdays as i64)
+        .bind(&now)
+        .bind(&now)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn schedule_delete(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        id: &str,
+    ) -> Result<bool, Error> {
+        let res = sqlx::query("DELETE FROM schedules WHERE id = ?1 AND guild_id = ?2")
+            .bind(id)
+            .bind(guild_id.get().to_string())
+            .execute(conn)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn schedule_update_last_run(
+        conn: &mut SqliteConnection,
+        id: &str,
+        last_run: NaiveDate,
+    ) -> Result<(), Error> {
+        let _res = sqlx::query("UPDATE schedules SET last_run = ?1, updated_at = ?2 WHERE id = ?3")
+            .bind(last_run.to_string())
+            .bind(Utc::now().to_rfc3339())
+            .bind(id)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn history_insert(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        url: &str,
+        title: Option<&str>,
+    ) -> Result<(), Error> {
+        let _res = sqlx::query(
+            "INSERT INTO play_history (guild_id, url, title, played_at) VALUES (?1, ?2, ?3, ?4)",
+        )
+        .bind(guild_id.get().to_string())
+        .bind(url)
+        .bind(title)
+        .bind(Utc::now().to_rfc3339())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The latest played tracks of a guild, newest first
+    pub async fn history_get_by_guild(
+        conn: &mut SqliteConnection,
+        guild_id: GuildId,
+        limit: u32,
+    ) -> Result<Vec<HistoryRow>, Error> {
+        let history = sqlx::query_as::<_, HistoryRowRaw>(
+            "SELECT * FROM play_history WHERE guild_id = ?1 ORDER BY id DESC LIMIT ?2",
+        )
+        .bind(guild_id.get().to_string())
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(history.into_iter().map(HistoryRow::from_raw_row).collect())
+    }
 }
@@ -0,0 +1,63 @@
+use crate::discord::player::deafen_all;
+use crate::discord::utils::get_songbird_or_error;
+use crate::discord::{Context, Error};
+use crate::doctor;
+
+/// Re-read the config file (same as sending SIGHUP)
+#[poise::command(slash_command, owners_only)]
+pub async fn reload(ctx: Context<'_>) -> Result<(), Error> {
+    let report = match ctx.data().config.reload() {
+        Ok(v) => v,
+        Err(e) => {
+            ctx.say(format!(
+                "The new config is broken, so I'm sticking with the old one:\n```\n{e}\n```"
+            ))
+            .await?;
+            return Ok(());
+        }
+    };
+
+    tracing::info!("reloaded config: {report}");
+
+    if report.applied.contains(&"SELF_DEAF") {
+        let songbird_mgr = get_songbird_or_error(&ctx).await?;
+        deafen_all(&songbird_mgr, ctx.data().config.get().self_deaf).await;
+    }
+
+    let mut reply = if report.applied.is_empty() {
+        "Reloaded the config, nothing I can apply changed.".to_string()
+    } else {
+        format!(
+            "Reloaded the config and applied `{}`.",
+            report.applied.join("`, `")
+        )
+    };
+    if !report.restart_required.is_empty() {
+        reply.push_str(&format!(
+            "\n`{}` only change after a restart.",
+            report.restart_required.join("`, `")
+        ));
+    }
+
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// Check that yt-dlp, Opus, the database and decoding work
+#[poise::command(slash_command, owners_only)]
+pub async fn status(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let report = doctor::run(&ctx.data().ytdl, &ctx.data().database).await;
+    report.log();
+
+    let summary = if report.is_ok() {
+        "Everything looks healthy, ready to make some noise!"
+    } else {
+        "Something's off, `/play` probably won't work until this is fixed:"
+    };
+    ctx.say(format!("{summary}\n{report}")).await?;
+
+    Ok(())
+}
@@ -1,28 +1,56 @@
-use poise::serenity_prelude::GuildChannel;
-use songbird::tracks::Track;
-use songbird::TrackEvent;
+use std::time::Duration;
+
+use poise::serenity_prelude::{
+    ComponentInteractionDataKind, CreateActionRow, CreateInteractionResponse, CreateSelectMenu,
+    CreateSelectMenuKind, CreateSelectMenuOption, GuildChannel,
+};
+use poise::CreateReply;
+use reqwest::header::HeaderMap;
 use url::Url;
 
-use crate::database::actions::{volume_get_or_insert_default, volume_insert_or_update};
+use crate::database::actions::volume_get_or_insert_default;
+use crate::discord::commands::queue::{enqueue_playlist, try_get_playlist};
 use crate::discord::error::VoiceChannelJoinError;
+use crate::discord::player::{
+    add_global_events, play_only, set_volume, stop_all, webradio_input, WebradioInput,
+    INITIAL_DEFAULT_VOLUME,
+};
+use crate::discord::tracks::format_duration;
 use crate::discord::utils::{
-    get_guild_id_or_error, get_songbird_or_error, try_get_user_voice_channel,
-    try_join_user_voice_channel,
+    blocked_reply, check_url_or_reply, get_guild_id_or_error, get_or_join_voice_handler,
+    get_songbird_or_error, try_join_user_voice_channel,
 };
-use crate::discord::voice::TrackErrorNotifier;
 use crate::discord::{Context, Error};
+use crate::hls::is_hls_url;
+use crate::stream;
+use crate::url_policy::blocked_reason;
 
-const INITIAL_DEFAULT_VOLUME: i32 = 100;
+/// Number of results offered when searching via `/play query:...`
+const SEARCH_RESULTS: usize = 5;
+const SEARCH_SELECT_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// Play some radio!
 #[poise::command(slash_command)]
 pub async fn play(
     ctx: Context<'_>,
-    #[description = "Webradio URL"] url: String,
+    #[description = "Webradio URL"] url: Option<String>,
+    #[description = "Search YouTube for something to play"] query: Option<String>,
+    #[description = "Shuffle the tracks of a playlist"] shuffle: Option<bool>,
 ) -> Result<(), Error> {
     ctx.defer().await?;
 
-    let mut conn = ctx.data().database.get_connection().await?;
+    let url = match (url, query) {
+        (Some(url), _) => url,
+        (None, Some(query)) => match select_search_result(&ctx, &query).await? {
+            Some(url) => url,
+            None => return Ok(()),
+        },
+        (None, None) => {
+            ctx.say("Give me either a URL or a search query to play something")
+                .await?;
+            return Ok(());
+        }
+    };
 
     let Ok(url) = Url::parse(&url) else {
         ctx.say(format!(
@@ -33,77 +61,260 @@ pub async fn play(
         return Ok(());
     };
 
-    let guild_id = get_guild_id_or_error(&ctx)?;
-    let songbird_mgr = get_songbird_or_error(&ctx).await?;
+    play_url(&ctx, &url, shuffle.unwrap_or(false), None, None).await
+}
 
-    let voice_handler = match songbird_mgr.get(guild_id) {
-        Some(v) => v,
-        None => match try_join_user_voice_channel(&ctx, &songbird_mgr).await {
-            Ok(handler) => {
-                let mut handler_lock = handler.lock().await;
-                handler_lock.add_global_event(TrackEvent::Error.into(), TrackErrorNotifier);
+/// Plays `url`, replacing whatever is playing. Playlists are added to the queue instead.
+///
+/// `fallback_title` is shown if the title of the track isn't known. `favorite_id` is set if a
+/// favorite is played, so its credentials are used.
+pub(super) async fn play_url(
+    ctx: &Context<'_>,
+    url: &Url,
+    shuffle: bool,
+    fallback_title: Option<String>,
+    favorite_id: Option<&str>,
+) -> Result<(), Error> {
+    if !check_url_or_reply(ctx, url).await? {
+        return Ok(());
+    }
 
-                handler_lock.deafen(ctx.data().config.self_deaf).await?;
+    // Playlists are expanded into the queue instead of being played as a single input
+    if let Some(playlist) = try_get_playlist(ctx, url).await {
+        return enqueue_playlist(ctx, playlist, shuffle).await;
+    }
 
-                drop(handler_lock);
-                handler
-            }
-            Err(VoiceChannelJoinError::UserNotInVoiceChannel) => {
-                ctx.say("I'm not in a voice channel and it seems you are not in a voice channel i can access...").await?;
+    let guild_id = get_guild_id_or_error(ctx)?;
+
+    let (webradio_input, mut track_info) =
+        match webradio_input(ctx.data(), guild_id, url, favorite_id).await? {
+            WebradioInput::Playable(input, track_info) => (input, track_info),
+            WebradioInput::Unsupported(reason) => {
+                ctx.say(format!("I can't play <{url}>: {reason}")).await?;
                 return Ok(());
             }
-            Err(VoiceChannelJoinError::Other(e)) => {
-                ctx.say("There was an error joining your voice channel...")
-                    .await?;
-                return Err(e);
+            WebradioInput::Blocked(blocked) => {
+                ctx.say(blocked_reply(url, &blocked)).await?;
+                return Ok(());
             }
-        },
+        };
+    if track_info.title.is_none() {
+        track_info.title = fallback_title;
+    }
+    let songbird_mgr = get_songbird_or_error(ctx).await?;
+
+    let Some(voice_handler) = get_or_join_voice_handler(ctx, &songbird_mgr).await? else {
+        return Ok(());
+    };
+
+    let reply = match track_info.duration {
+        Some(duration) => format!(
+            "Playing **{}** `[{}]`",
+            track_info.display_name(),
+            format_duration(duration)
+        ),
+        None => format!("Playing {}", track_info.display_name()),
     };
 
-    {
-        let voice_handler_lock = voice_handler.lock().await;
+    play_only(
+        ctx.data(),
+        guild_id,
+        &voice_handler,
+        webradio_input,
+        track_info,
+    )
+    .await?;
 
-        // A voice handle can still exist for a guild, even though the bot isn't connected to any VC
-        if voice_handler_lock.current_connection().is_none() {
-            // Prevent deadlock. This could be handled better, but i don't care :3
-            drop(voice_handler_lock);
+    ctx.say(reply).await?;
 
-            let user_vc = match try_get_user_voice_channel(&ctx, &ctx.author().id).await {
-                Ok(v) => v,
-                Err(VoiceChannelJoinError::UserNotInVoiceChannel) => {
-                    ctx.say("I'm not in a voice channel and it seems you are not in a voice channel i can access").await?;
-                    return Ok(());
-                }
-                Err(VoiceChannelJoinError::Other(e)) => {
-                    return Err(e);
-                }
-            };
+    Ok(())
+}
+
+/// Show the container, codec, sample rate and bitrate of an audio stream
+#[poise::command(slash_command)]
+pub async fn probe(
+    ctx: Context<'_>,
+    #[description = "URL of the audio stream"] url: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
 
-            songbird_mgr.join(guild_id, user_vc).await?;
+    let Ok(url) = Url::parse(&url) else {
+        ctx.say(format!(
+            "Error parsing URL \"{}\". Are you sure it's correct?",
+            url
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    if !check_url_or_reply(&ctx, &url).await? {
+        return Ok(());
+    }
+
+    if is_hls_url(&url) {
+        ctx.say("That's an HLS playlist, I play those natively (AAC or MP3 segments)")
+            .await?;
+        return Ok(());
+    }
+
+    let policy = ctx.data().url_policy(ctx.guild_id()).await?;
+    let client = ctx.data().outbound.guarded();
+    let info = match stream::probe(&client, &policy, &url, &HeaderMap::new()).await {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            ctx.say("That doesn't look like an audio stream, I'd try to play it via yt-dlp")
+                .await?;
+            return Ok(());
+        }
+        Err(e) => {
+            if let Some(blocked) = blocked_reason(&*e) {
+                ctx.say(blocked_reply(&url, blocked)).await?;
+                return Ok(());
+            }
+            tracing::debug!("couldn't probe \"{url}\": {e}");
+            ctx.say(format!("I couldn't read <{url}>: {e}")).await?;
+            return Ok(());
+        }
+    };
+
+    let mut lines = Vec::new();
+    if let Some(name) = &info.name {
+        lines.push(format!("**{name}**"));
+    }
+    lines.push(format!("Container: `{}`", info.container));
+    lines.push(format!("Codec: `{}`", info.codec));
+    if let Some(sample_rate) = info.sample_rate {
+        lines.push(format!("Sample rate: `{sample_rate} Hz`"));
+    }
+    if let Some(channels) = info.channels {
+        lines.push(format!("Channels: `{channels}`"));
+    }
+    if let Some(bitrate) = info.bitrate {
+        lines.push(format!("Bitrate: `{} kbit/s`", bitrate / 1000));
+    }
+    match &info.unsupported {
+        Some(reason) => lines.push(format!("❌ I can't play this: {reason}")),
+        None => {
+            lines.push("✅ I can play this natively".to_string());
+
+            match info.opus_passthrough() {
+                Ok(()) => lines.push(
+                    "✅ Opus frames are passed through unchanged (at volume `100`)".to_string(),
+                ),
+                Err(reason) => lines.push(format!("➖ No Opus passthrough, {reason}")),
+            }
         }
     }
 
-    let vol = volume_get_or_insert_default(&mut conn, guild_id, INITIAL_DEFAULT_VOLUME).await?;
+    ctx.say(lines.join("\n")).await?;
 
-    // convert 0-100 to 0.0-1.0
-    let vol: f32 = vol as f32 / 100.0;
+    Ok(())
+}
 
-    let mut voice_handler_lock = voice_handler.lock().await;
+/// Search YouTube for `query` and let the user pick one of the results.
+///
+/// Returns `Ok(None)` if nothing was selected.
+async fn select_search_result(ctx: &Context<'_>, query: &str) -> Result<Option<String>, Error> {
+    let results = match ctx.data().ytdl.search(query, SEARCH_RESULTS).await {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("error searching for \"{query}\": {e:?}");
+            ctx.say(format!("I couldn't find anything for \"{query}\""))
+                .await?;
+            return Ok(None);
+        }
+    };
 
-    let webradio_input_ytdl =
-        songbird::input::YoutubeDl::new(reqwest::Client::new(), url.to_string());
+    let options = results
+        .iter()
+        .filter_map(|result| {
+            let url = result.source_url.as_ref()?;
+            let title = result.title.as_deref().unwrap_or(url);
 
-    let track_handle = voice_handler_lock.play_only(Track::from(webradio_input_ytdl).volume(vol));
+            let mut description = result.channel.clone().unwrap_or_default();
+            if let Some(duration) = result.duration {
+                description = format!("{description} [{}]", format_duration(duration));
+            }
 
-    ctx.data()
-        .guild_tracks
-        .write()
-        .await
-        .insert(guild_id, track_handle);
+            // Discord limits labels and descriptions to 100 characters
+            Some(
+                CreateSelectMenuOption::new(truncate(title, 100), url.as_str())
+                    .description(truncate(description.trim(), 100)),
+            )
+        })
+        .collect::<Vec<_>>();
 
-    ctx.say(format!("Playing {}", url)).await?;
+    if options.is_empty() {
+        ctx.say(format!("I couldn't find anything for \"{query}\""))
+            .await?;
+        return Ok(None);
+    }
 
-    Ok(())
+    let custom_id = format!("search-{}", ctx.id());
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .content(format!("Results for \"{query}\":"))
+                .components(vec![CreateActionRow::SelectMenu(
+                    CreateSelectMenu::new(&custom_id, CreateSelectMenuKind::String { options })
+                        .placeholder("Select something to play"),
+                )]),
+        )
+        .await?;
+
+    let interaction = reply
+        .message()
+        .await?
+        .await_component_interaction(ctx)
+        .author_id(ctx.author().id)
+        .custom_ids(vec![custom_id])
+        .timeout(SEARCH_SELECT_TIMEOUT)
+        .await;
+
+    let Some(interaction) = interaction else {
+        reply
+            .edit(
+                *ctx,
+                CreateReply::default()
+                    .content("Nothing was selected")
+                    .components(vec![]),
+            )
+            .await?;
+        return Ok(None);
+    };
+
+    interaction
+        .create_response(ctx, CreateInteractionResponse::Acknowledge)
+        .await?;
+
+    let ComponentInteractionDataKind::StringSelect { values } = &interaction.data.kind else {
+        return Err("unexpected component interaction kind".into());
+    };
+    let url = values
+        .first()
+        .ok_or("no value selected in search result menu")?
+        .to_owned();
+
+    reply
+        .edit(
+            *ctx,
+            CreateReply::default()
+                .content(format!("Selected <{url}>"))
+                .components(vec![]),
+        )
+        .await?;
+
+    Ok(Some(url))
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    if s.chars().count() <= max_chars {
+        return s.to_string();
+    }
+
+    let mut truncated = s.chars().take(max_chars - 1).collect::<String>();
+    truncated.push('…');
+    truncated
 }
 
 /// Stop playing radio
@@ -128,8 +339,7 @@ pub async fn stop(ctx: Context<'_>) -> Result<(), Error> {
         return Ok(());
     }
 
-    ctx.data().guild_tracks.write().await.remove(&guild_id);
-    voice_handler_lock.stop();
+    stop_all(ctx.data(), guild_id, &mut voice_handler_lock).await;
 
     ctx.say("Stopping...").await?;
 
@@ -159,12 +369,18 @@ pub async fn join(
     let songbird_mgr = get_songbird_or_error(&ctx).await?;
 
     if let Some(channel) = channel {
+        let is_new = songbird_mgr.get(channel.guild_id).is_none();
+
         match songbird_mgr.join(channel.guild_id, channel.id).await {
             Ok(handler) => {
                 let mut handler_lock = handler.lock().await;
-                handler_lock.add_global_event(TrackEvent::Error.into(), TrackErrorNotifier);
+                if is_new {
+                    add_global_events(&mut handler_lock, ctx.data(), channel.guild_id);
+                }
 
-                handler_lock.deafen(ctx.data().config.self_deaf).await?;
+                handler_lock
+                    .deafen(ctx.data().config.get().self_deaf)
+                    .await?;
             }
             Err(e) => {
                 ctx.say("There was an error joining the voice channel...")
@@ -177,12 +393,18 @@ pub async fn join(
         return Ok(());
     }
 
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let is_new = songbird_mgr.get(guild_id).is_none();
+
     match try_join_user_voice_channel(&ctx, &songbird_mgr).await {
         Ok(handler) => {
-            handler
-                .lock()
-                .await
-                .deafen(ctx.data().config.self_deaf)
+            let mut handler_lock = handler.lock().await;
+            if is_new {
+                add_global_events(&mut handler_lock, ctx.data(), guild_id);
+            }
+
+            handler_lock
+                .deafen(ctx.data().config.get().self_deaf)
                 .await?;
         }
         Err(VoiceChannelJoinError::UserNotInVoiceChannel) => {
@@ -217,7 +439,9 @@ pub async fn disconnect(ctx: Context<'_>) -> Result<(), Error> {
 
     ctx.data().guild_tracks.write().await.remove(&guild_id);
 
-    handler.lock().await.leave().await?;
+    let mut handler_lock = handler.lock().await;
+    handler_lock.queue().stop();
+    handler_lock.leave().await?;
 
     ctx.say("Bye bye!").await?;
 
@@ -232,35 +456,26 @@ pub async fn volume(
 ) -> Result<(), Error> {
     ctx.defer().await?;
 
-    let mut conn = ctx.data().database.get_connection().await?;
-
     let guild_id = get_guild_id_or_error(&ctx)?;
     let songbird_mgr = get_songbird_or_error(&ctx).await?;
 
-    let voice_handler = songbird_mgr.get(guild_id);
-
     if let Some(volume) = volume {
-        if volume > ctx.data().config.max_volume {
+        if volume > ctx.data().config.get().max_volume {
             ctx.say(format!(
                 "Volume `{volume}` is higher than the maximum (`{}`)",
-                ctx.data().config.max_volume
+                ctx.data().config.get().max_volume
             ))
             .await?;
             return Ok(());
         }
 
-        if voice_handler.is_some() {
-            if let Some(track_handle) = ctx.data().guild_tracks.read().await.get(&guild_id) {
-                track_handle.set_volume(volume as f32 / 100.0).ok();
-            }
-        }
-
-        volume_insert_or_update(&mut conn, guild_id, volume as i32).await?;
+        set_volume(ctx.data(), &songbird_mgr, guild_id, volume).await?;
 
         ctx.say(format!("Set volume to `{volume}`")).await?;
         return Ok(());
     };
 
+    let mut conn = ctx.data().database.get_connection().await?;
     let vol = volume_get_or_insert_default(&mut conn, guild_id, INITIAL_DEFAULT_VOLUME).await?;
 
     ctx.say(format!("The volume is set to `{vol}`")).await?;
@@ -0,0 +1,188 @@
+use poise::CreateReply;
+use url::Url;
+
+use crate::credentials::{parse_headers, BasicAuth, StreamAuth, Target};
+use crate::database::actions::{
+    favorite_get, stream_credential_delete, stream_credentials_get_by_guild,
+};
+use crate::discord::commands::favorite::autocomplete_favorite;
+use crate::discord::utils::get_guild_id_or_error;
+use crate::discord::{Context, Error};
+
+/// Manage the headers and logins of private streams
+#[poise::command(
+    slash_command,
+    subcommands("set", "remove", "list"),
+    subcommand_required,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn credentials(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Replies only to the caller, so nobody else sees anything about the credentials
+async fn reply(ctx: &Context<'_>, content: impl Into<String>) -> Result<(), Error> {
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+
+    Ok(())
+}
+
+/// Checks that exactly one of `url` and `favorite` is given and that they exist
+async fn resolve_target(
+    ctx: &Context<'_>,
+    url: Option<String>,
+    favorite: Option<String>,
+) -> Result<Option<Target>, Error> {
+    match (url, favorite) {
+        (Some(url), None) => {
+            let Ok(url) = Url::parse(&url) else {
+                reply(
+                    ctx,
+                    format!("Error parsing URL \"{url}\". Are you sure it's correct?"),
+                )
+                .await?;
+                return Ok(None);
+            };
+
+            Ok(Some(Target::Url(url.to_string())))
+        }
+        (None, Some(favorite)) => {
+            let guild_id = get_guild_id_or_error(ctx)?;
+            let mut conn = ctx.data().database.get_connection().await?;
+            if favorite_get(&mut conn, guild_id, &favorite)
+                .await?
+                .is_none()
+            {
+                reply(
+                    ctx,
+                    "I couldn't find that favorite. Pick one of the suggestions",
+                )
+                .await?;
+                return Ok(None);
+            }
+
+            Ok(Some(Target::Favorite(favorite)))
+        }
+        _ => {
+            reply(ctx, "Give me either a URL (prefix) or a favorite").await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Send headers or a login when playing a URL (or everything starting with it) or a favorite
+#[poise::command(slash_command)]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "URL, or the start of the URLs to use them for"] url: Option<String>,
+    #[description = "The favorite to use them for"]
+    #[autocomplete = "autocomplete_favorite"]
+    favorite: Option<String>,
+    #[description = "Username of the login (HTTP basic auth)"] username: Option<String>,
+    #[description = "Password of the login"] password: Option<String>,
+    #[description = "Headers, like \"Name: value; Other-Name: value\""] headers: Option<String>,
+) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let Some(target) = resolve_target(&ctx, url, favorite).await? else {
+        return Ok(());
+    };
+
+    let basic_auth = match (username, password) {
+        (Some(username), Some(password)) => Some(BasicAuth { username, password }),
+        (None, None) => None,
+        _ => {
+            reply(&ctx, "A login needs both a username and a password").await?;
+            return Ok(());
+        }
+    };
+    let headers = match headers.as_deref().map(parse_headers).transpose() {
+        Ok(v) => v.unwrap_or_default(),
+        Err(e) => {
+            reply(&ctx, format!("I can't make sense of the headers: {e}")).await?;
+            return Ok(());
+        }
+    };
+    if basic_auth.is_none() && headers.is_empty() {
+        reply(&ctx, "Give me a login, some headers or both").await?;
+        return Ok(());
+    }
+
+    let auth = StreamAuth {
+        headers,
+        basic_auth,
+    };
+    ctx.data()
+        .credentials
+        .set(&ctx.data().database, guild_id, &target, &auth)
+        .await?;
+
+    reply(&ctx, format!("Saved the credentials for `{target}` 🔒")).await
+}
+
+/// Stop sending headers or a login for a URL or favorite
+#[poise::command(slash_command)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "URL (prefix) the credentials were set for"] url: Option<String>,
+    #[description = "Favorite the credentials were set for"]
+    #[autocomplete = "autocomplete_favorite"]
+    favorite: Option<String>,
+) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let Some(target) = resolve_target(&ctx, url, favorite).await? else {
+        return Ok(());
+    };
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    if !stream_credential_delete(&mut conn, guild_id, &target.to_string()).await? {
+        return reply(&ctx, format!("There are no credentials for `{target}`")).await;
+    }
+
+    reply(&ctx, format!("Removed the credentials for `{target}`")).await
+}
+
+/// List what credentials are set for (without revealing them)
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    let rows = stream_credentials_get_by_guild(&mut conn, guild_id).await?;
+
+    if rows.is_empty() {
+        return reply(
+            &ctx,
+            "There are no credentials yet, add some via `/credentials set`",
+        )
+        .await;
+    }
+
+    let mut lines = Vec::new();
+    for row in rows {
+        let line = match Target::parse(&row.target) {
+            Some(Target::Url(prefix)) => format!("- URLs starting with <{prefix}>"),
+            Some(Target::Favorite(id)) => match favorite_get(&mut conn, guild_id, &id).await? {
+                Some(favorite) => format!("- Favorite **{}**", favorite.title),
+                None => format!("- Removed favorite `{id}`"),
+            },
+            None => format!("- `{}`", row.target),
+        };
+        lines.push(line);
+    }
+
+    reply(
+        &ctx,
+        format!("Credentials are set for:\n{}", lines.join("\n")),
+    )
+    .await
+}
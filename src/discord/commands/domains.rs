@@ -0,0 +1,122 @@
+use crate::database::actions::{
+    guild_domain_delete, guild_domain_insert_or_update, guild_domains_get,
+};
+use crate::discord::utils::get_guild_id_or_error;
+use crate::discord::{Context, Error};
+use crate::url_policy::normalize_domain;
+
+/// Decide which websites can be played on this server
+#[poise::command(
+    slash_command,
+    subcommands("allow", "block", "remove", "list"),
+    subcommand_required,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn domains(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Saves `domain` on one of the lists, replying what happened
+async fn set_domain(ctx: Context<'_>, domain: &str, blocked: bool) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let Some(domain) = normalize_domain(domain) else {
+        ctx.say(format!("\"{domain}\" doesn't look like a domain to me"))
+            .await?;
+        return Ok(());
+    };
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    guild_domain_insert_or_update(&mut conn, guild_id, &domain, blocked).await?;
+
+    let reply = if blocked {
+        format!("Blocked `{domain}` and its subdomains 🚫")
+    } else {
+        format!("Allowed `{domain}` and its subdomains. Once there's an allowed domain, everything else is off limits")
+    };
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// Only allow playing from this domain (and others on the allowlist)
+#[poise::command(slash_command)]
+pub async fn allow(
+    ctx: Context<'_>,
+    #[description = "Domain, e.g. example.com (includes subdomains)"] domain: String,
+) -> Result<(), Error> {
+    set_domain(ctx, &domain, false).await
+}
+
+/// Never play anything from this domain
+#[poise::command(slash_command)]
+pub async fn block(
+    ctx: Context<'_>,
+    #[description = "Domain, e.g. example.com (includes subdomains)"] domain: String,
+) -> Result<(), Error> {
+    set_domain(ctx, &domain, true).await
+}
+
+/// Remove a domain from the allow- or blocklist
+#[poise::command(slash_command)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The domain to remove"] domain: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let domain = normalize_domain(&domain).unwrap_or(domain);
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    if !guild_domain_delete(&mut conn, guild_id, &domain).await? {
+        ctx.say(format!("`{domain}` isn't on any list")).await?;
+        return Ok(());
+    }
+
+    ctx.say(format!("Removed `{domain}`")).await?;
+
+    Ok(())
+}
+
+/// List the allowed and blocked domains of this server
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    let rows = guild_domains_get(&mut conn, guild_id).await?;
+
+    if rows.is_empty() {
+        ctx.say("Every website is fair game here (as far as the bot allows). Use `/domains allow` or `/domains block` to change that")
+            .await?;
+        return Ok(());
+    }
+
+    let format = |blocked: bool| {
+        rows.iter()
+            .filter(|v| v.blocked == blocked)
+            .map(|v| format!("`{}`", v.domain))
+            .collect::<Vec<_>>()
+    };
+
+    let mut lines = Vec::new();
+    let allowed = format(false);
+    if !allowed.is_empty() {
+        lines.push(format!("✅ Allowed: {}", allowed.join(", ")));
+    }
+    let blocked = format(true);
+    if !blocked.is_empty() {
+        lines.push(format!("🚫 Blocked: {}", blocked.join(", ")));
+    }
+
+    ctx.say(lines.join("\n")).await?;
+
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use poise::serenity_prelude::AutocompleteChoice;
+use url::Url;
+
+use crate::credentials::Target;
+use crate::database::actions::{
+    favorite_delete, favorite_get, favorite_insert, favorites_get_by_guild, favorites_search,
+    stream_credential_delete,
+};
+use crate::discord::commands::audio::play_url;
+use crate::discord::utils::{check_url_or_reply, get_guild_id_or_error};
+use crate::discord::{Context, Error};
+
+/// Maximum number of choices Discord accepts for autocompletion
+const MAX_AUTOCOMPLETE_CHOICES: u32 = 25;
+/// Discord limits choice names to 100 characters
+const MAX_TITLE_CHARS: usize = 100;
+
+/// Play and manage this server's favorite stations
+#[poise::command(
+    slash_command,
+    subcommands("play", "add", "remove", "list"),
+    subcommand_required,
+    guild_only
+)]
+pub async fn favorite(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+pub(super) async fn autocomplete_favorite(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Vec<AutocompleteChoice> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Vec::new();
+    };
+
+    let favorites = match ctx.data().database.get_connection().await {
+        Ok(mut conn) => {
+            favorites_search(&mut conn, guild_id, partial, MAX_AUTOCOMPLETE_CHOICES).await
+        }
+        Err(e) => Err(e),
+    };
+
+    match favorites {
+        Ok(favorites) => favorites
+            .into_iter()
+            .map(|v| AutocompleteChoice::new(v.title, v.id))
+            .collect(),
+        Err(e) => {
+            tracing::error!("error autocompleting favorite \"{partial}\": {e:?}");
+            Vec::new()
+        }
+    }
+}
+
+/// Play one of the favorites
+#[poise::command(slash_command)]
+pub async fn play(
+    ctx: Context<'_>,
+    #[description = "The favorite to play"]
+    #[autocomplete = "autocomplete_favorite"]
+    favorite: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    let Some(favorite) = favorite_get(&mut conn, guild_id, &favorite).await? else {
+        ctx.say("I couldn't find that favorite. Pick one of the suggestions")
+            .await?;
+        return Ok(());
+    };
+    drop(conn);
+
+    let Ok(url) = Url::parse(&favorite.uri) else {
+        ctx.say(format!(
+            "The URL of **{}** is broken, better remove it",
+            favorite.title
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    play_url(&ctx, &url, false, Some(favorite.title), Some(&favorite.id)).await
+}
+
+/// Add a station to the favorites
+#[poise::command(slash_command)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "Name of the favorite"] title: String,
+    #[description = "URL to play"] url: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let title = title.trim();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+        ctx.say(format!(
+            "The name has to be between 1 and {MAX_TITLE_CHARS} characters long"
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let Ok(url) = Url::parse(&url) else {
+        ctx.say(format!(
+            "Error parsing URL \"{}\". Are you sure it's correct?",
+            url
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    // Favorites are played later on, so nobody should be able to save something that's blocked
+    if !check_url_or_reply(&ctx, &url).await? {
+        return Ok(());
+    }
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    favorite_insert(&mut conn, ctx.author().id, guild_id, title, url.as_str()).await?;
+
+    ctx.say(format!("Added **{title}** to the favorites"))
+        .await?;
+
+    Ok(())
+}
+
+/// Remove a station from the favorites
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The favorite to remove"]
+    #[autocomplete = "autocomplete_favorite"]
+    favorite: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    let Some(row) = favorite_get(&mut conn, guild_id, &favorite).await? else {
+        ctx.say("I couldn't find that favorite. Pick one of the suggestions")
+            .await?;
+        return Ok(());
+    };
+    favorite_delete(&mut conn, guild_id, &row.id).await?;
+    stream_credential_delete(
+        &mut conn,
+        guild_id,
+        &Target::Favorite(row.id.clone()).to_string(),
+    )
+    .await?;
+
+    ctx.say(format!("Removed **{}** from the favorites", row.title))
+        .await?;
+
+    Ok(())
+}
+
+/// List the favorites of this server
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    let favorites = favorites_get_by_guild(&mut conn, guild_id).await?;
+
+    if favorites.is_empty() {
+        ctx.say("There are no favorites yet, add some via `/favorite add`")
+            .await?;
+        return Ok(());
+    }
+
+    let lines = favorites
+        .iter()
+        .map(|v| format!("- **{}** <{}>", v.title, v.uri))
+        .collect::<Vec<_>>();
+
+    ctx.say(format!("Favorites:\n{}", lines.join("\n"))).await?;
+
+    Ok(())
+}
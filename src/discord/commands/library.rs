@@ -0,0 +1,163 @@
+use poise::serenity_prelude::AutocompleteChoice;
+use songbird::input::File;
+
+use crate::database::actions::{library_track_get, library_tracks_search};
+use crate::discord::commands::queue::play_or_enqueue;
+use crate::discord::tracks::{format_duration, TrackInfo};
+use crate::discord::{Context, Error};
+use crate::library;
+
+/// Maximum number of results shown by `/library search`
+const MAX_SEARCH_RESULTS: u32 = 15;
+/// Maximum number of choices Discord accepts for autocompletion
+const MAX_AUTOCOMPLETE_CHOICES: u32 = 25;
+
+const NO_LIBRARY_ERR: &str = "There is no music library configured";
+
+/// Play music from the local library
+#[poise::command(
+    slash_command,
+    subcommands("search", "play", "rescan"),
+    subcommand_required
+)]
+pub async fn library(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Search the local library
+#[poise::command(slash_command)]
+pub async fn search(
+    ctx: Context<'_>,
+    #[description = "Title, artist, album or file name"] query: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    if ctx.data().config.get().library_path.is_none() {
+        ctx.say(NO_LIBRARY_ERR).await?;
+        return Ok(());
+    }
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    let tracks = library_tracks_search(&mut conn, &query, MAX_SEARCH_RESULTS).await?;
+
+    if tracks.is_empty() {
+        ctx.say(format!("I couldn't find anything for \"{query}\""))
+            .await?;
+        return Ok(());
+    }
+
+    let lines = tracks
+        .iter()
+        .map(|track| {
+            let album = track
+                .album
+                .as_ref()
+                .map(|v| format!(" *({v})*"))
+                .unwrap_or_default();
+            let duration = track
+                .duration
+                .map(|v| format!(" `[{}]`", format_duration(v)))
+                .unwrap_or_default();
+
+            format!("- {}{album}{duration}", track.display_name())
+        })
+        .collect::<Vec<_>>();
+
+    ctx.say(format!(
+        "Results for \"{query}\" (play them via `/library play`):\n{}",
+        lines.join("\n")
+    ))
+    .await?;
+
+    Ok(())
+}
+
+async fn autocomplete_track(ctx: Context<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    let tracks = match ctx.data().database.get_connection().await {
+        Ok(mut conn) => library_tracks_search(&mut conn, partial, MAX_AUTOCOMPLETE_CHOICES).await,
+        Err(e) => Err(e),
+    };
+
+    match tracks {
+        Ok(tracks) => tracks
+            .into_iter()
+            .map(|track| {
+                // Discord limits choice names to 100 characters
+                let name = track.display_name().chars().take(100).collect::<String>();
+                AutocompleteChoice::new(name, track.id)
+            })
+            .collect(),
+        Err(e) => {
+            tracing::error!("error autocompleting library track \"{partial}\": {e:?}");
+            Vec::new()
+        }
+    }
+}
+
+/// Play a track from the local library
+#[poise::command(slash_command)]
+pub async fn play(
+    ctx: Context<'_>,
+    #[description = "The track to play"]
+    #[autocomplete = "autocomplete_track"]
+    track: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    if ctx.data().config.get().library_path.is_none() {
+        ctx.say(NO_LIBRARY_ERR).await?;
+        return Ok(());
+    }
+
+    let mut conn = ctx.data().database.get_connection().await?;
+
+    let Some(track) = library_track_get(&mut conn, &track).await? else {
+        ctx.say("I couldn't find that track. Pick one of the suggestions")
+            .await?;
+        return Ok(());
+    };
+    drop(conn);
+
+    if !track.path.is_file() {
+        ctx.say(format!(
+            "**{}** doesn't exist anymore. Maybe the library needs a `/library rescan`",
+            track.display_name()
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let track_info = TrackInfo {
+        url: track.path.display().to_string(),
+        title: Some(track.display_name()),
+        duration: track.duration,
+        live: false,
+        opus_passthrough: false,
+    };
+
+    play_or_enqueue(&ctx, File::new(track.path).into(), track_info).await?;
+
+    Ok(())
+}
+
+/// Re-index the local library
+#[poise::command(slash_command, owners_only)]
+pub async fn rescan(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let config = ctx.data().config.get();
+    let Some(library_path) = &config.library_path else {
+        ctx.say(NO_LIBRARY_ERR).await?;
+        return Ok(());
+    };
+
+    let stats = library::index(&ctx.data().database, library_path).await?;
+
+    ctx.say(format!(
+        "Indexed `{}` tracks, removed `{}` (`{}` couldn't be read)",
+        stats.indexed, stats.removed, stats.failed
+    ))
+    .await?;
+
+    Ok(())
+}
@@ -1,4 +1,13 @@
+pub mod admin;
 pub mod audio;
+pub mod credentials;
+pub mod domains;
+pub mod favorite;
+pub mod library;
+pub mod playback;
+pub mod podcast;
+pub mod queue;
+pub mod relay;
 
 use crate::discord::{Context, Error};
 
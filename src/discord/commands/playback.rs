@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use crate::discord::tracks::{format_duration, parse_timestamp, progress_bar, TrackInfo};
+use crate::discord::utils::{get_guild_id_or_error, get_songbird_or_error};
+use crate::discord::{Context, Error};
+
+const NOTHING_PLAYING_ERR: &str = "There is nothing playing right now";
+const LIVE_SEEK_ERR: &str = "This is a live stream, you can't seek in it";
+
+/// Show what is currently playing
+#[poise::command(slash_command, rename = "nowplaying")]
+pub async fn now_playing(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let songbird_mgr = get_songbird_or_error(&ctx).await?;
+
+    let Some(track_handle) = ctx.data().current_track(&songbird_mgr, guild_id).await else {
+        ctx.say(NOTHING_PLAYING_ERR).await?;
+        return Ok(());
+    };
+
+    let position = track_handle.get_info().await?.position;
+
+    let reply = match TrackInfo::of(&track_handle).await {
+        Some(info) => match info.duration {
+            Some(duration) => format!(
+                "Now playing **{}**\n{}",
+                info.display_name(),
+                progress_bar(position, duration)
+            ),
+            None if info.is_live() => format!(
+                "Now playing {}\n🔴 Live for `{}`",
+                info.display_name(),
+                format_duration(position)
+            ),
+            None => format!(
+                "Now playing **{}**\n`{}`",
+                info.display_name(),
+                format_duration(position)
+            ),
+        },
+        None => format!("Playing for `{}`", format_duration(position)),
+    };
+
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// Jump to a position in the current track
+#[poise::command(slash_command)]
+pub async fn seek(
+    ctx: Context<'_>,
+    #[description = "Position to jump to (e.g. `1:30`)"] position: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let Some(position) = parse_timestamp(&position) else {
+        ctx.say(format!(
+            "\"{position}\" is not a valid position. Try something like `1:30`"
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    seek_current_track(ctx, |_| Some(position)).await
+}
+
+/// Skip forward in the current track
+#[poise::command(slash_command)]
+pub async fn forward(
+    ctx: Context<'_>,
+    #[description = "Number of seconds to skip forward"] seconds: u64,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    seek_current_track(ctx, |current| {
+        current.checked_add(Duration::from_secs(seconds))
+    })
+    .await
+}
+
+/// Rewind the current track
+#[poise::command(slash_command)]
+pub async fn rewind(
+    ctx: Context<'_>,
+    #[description = "Number of seconds to rewind"] seconds: u64,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    seek_current_track(ctx, |current| {
+        Some(current.saturating_sub(Duration::from_secs(seconds)))
+    })
+    .await
+}
+
+/// Seeks the current track to the position returned by `target` (given the current position).
+///
+/// Live streams and positions past the end of the track are rejected.
+async fn seek_current_track(
+    ctx: Context<'_>,
+    target: impl FnOnce(Duration) -> Option<Duration>,
+) -> Result<(), Error> {
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let songbird_mgr = get_songbird_or_error(&ctx).await?;
+
+    let Some(track_handle) = ctx.data().current_track(&songbird_mgr, guild_id).await else {
+        ctx.say(NOTHING_PLAYING_ERR).await?;
+        return Ok(());
+    };
+
+    // Tracks without any info are started via `/play` and treated as live
+    let track_info = TrackInfo::of(&track_handle).await;
+    let Some(track_info) = track_info.filter(|v| !v.is_live()) else {
+        ctx.say(LIVE_SEEK_ERR).await?;
+        return Ok(());
+    };
+
+    let current = track_handle.get_info().await?.position;
+
+    let Some(position) = target(current) else {
+        ctx.say("That's not a valid position").await?;
+        return Ok(());
+    };
+
+    if let Some(duration) = track_info.duration.filter(|v| position >= *v) {
+        ctx.say(format!(
+            "That's past the end of the track (`{}`)",
+            format_duration(duration)
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    if let Err(e) = track_handle.seek_async(position).await {
+        tracing::warn!("couldn't seek track {:?}: {e:?}", track_handle.uuid());
+        ctx.say("This track can't be seeked").await?;
+        return Ok(());
+    }
+
+    let reply = match track_info.duration {
+        Some(duration) => format!("Jumped to {}", progress_bar(position, duration)),
+        None => format!("Jumped to `{}`", format_duration(position)),
+    };
+    ctx.say(reply).await?;
+
+    Ok(())
+}
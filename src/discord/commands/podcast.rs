@@ -0,0 +1,370 @@
+use std::time::Duration;
+
+use poise::serenity_prelude::{AutocompleteChoice, GuildChannel, GuildId};
+use songbird::input::HttpRequest;
+use songbird::{Event, TrackEvent};
+use url::Url;
+
+use crate::database::actions::{
+    podcast_delete, podcast_insert, podcast_progress_get, podcasts_get_by_guild,
+};
+use crate::database::PodcastRow;
+use crate::discord::commands::queue::play_or_enqueue;
+use crate::discord::tracks::{format_duration, TrackInfo};
+use crate::discord::utils::{blocked_reply, check_url_or_reply, get_guild_id_or_error};
+use crate::discord::voice::PodcastProgressTracker;
+use crate::discord::{Context, Error};
+use crate::podcast::{fetch_feed, PodcastFeed};
+use crate::url_policy::blocked_reason;
+
+/// Number of episodes shown by `/podcast episodes`
+const LISTED_EPISODES: usize = 10;
+/// How often the playback position of an episode is saved
+const PROGRESS_SAVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Listen to podcasts
+#[poise::command(
+    slash_command,
+    subcommands("subscribe", "unsubscribe", "episodes", "play"),
+    subcommand_required,
+    guild_only
+)]
+pub async fn podcast(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Subscribe this server to a podcast feed
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+pub async fn subscribe(
+    ctx: Context<'_>,
+    #[description = "URL of the RSS/Atom feed"] feed_url: String,
+    #[description = "Channel where new episodes are announced"]
+    #[channel_types("Text")]
+    announce_channel: Option<GuildChannel>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let Ok(feed_url) = Url::parse(&feed_url) else {
+        ctx.say(format!(
+            "Error parsing URL \"{}\". Are you sure it's correct?",
+            feed_url
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    if !check_url_or_reply(&ctx, &feed_url).await? {
+        return Ok(());
+    }
+
+    let Some(feed) = fetch_feed_or_reply(&ctx, guild_id, feed_url.as_str(), || {
+        format!("I couldn't read a podcast feed from <{feed_url}>")
+    })
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    let inserted = podcast_insert(
+        &mut conn,
+        guild_id,
+        feed_url.as_str(),
+        &feed.title,
+        announce_channel.as_ref().map(|v| v.id),
+        feed.episodes.first().map(|v| v.id.as_str()),
+    )
+    .await?;
+
+    if !inserted {
+        ctx.say(format!(
+            "This server is already subscribed to **{}**",
+            feed.title
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let announce = announce_channel
+        .map(|v| format!(". New episodes will be announced in <#{}>", v.id))
+        .unwrap_or_default();
+    ctx.say(format!(
+        "Subscribed to **{}** (`{}` episodes){announce}",
+        feed.title,
+        feed.episodes.len()
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Unsubscribe this server from a podcast feed
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+pub async fn unsubscribe(
+    ctx: Context<'_>,
+    #[description = "The podcast to unsubscribe from"]
+    #[autocomplete = "autocomplete_podcast"]
+    podcast: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let mut conn = ctx.data().database.get_connection().await?;
+
+    if !podcast_delete(&mut conn, guild_id, &podcast).await? {
+        ctx.say("I couldn't find that podcast. Pick one of the suggestions")
+            .await?;
+        return Ok(());
+    }
+
+    ctx.say("Unsubscribed!").await?;
+
+    Ok(())
+}
+
+/// List the latest episodes of a podcast
+#[poise::command(slash_command)]
+pub async fn episodes(
+    ctx: Context<'_>,
+    #[description = "The podcast (can be omitted if there is only one)"]
+    #[autocomplete = "autocomplete_podcast"]
+    podcast: Option<String>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let Some(podcast) = resolve_podcast(&ctx, guild_id, podcast).await? else {
+        return Ok(());
+    };
+
+    let Some(feed) = fetch_feed_or_reply(&ctx, guild_id, &podcast.feed_url, || {
+        format!("I couldn't fetch the feed of **{}**", podcast.title)
+    })
+    .await?
+    else {
+        return Ok(());
+    };
+
+    if feed.episodes.is_empty() {
+        ctx.say(format!("**{}** doesn't have any episodes yet", feed.title))
+            .await?;
+        return Ok(());
+    }
+
+    let mut conn = ctx.data().database.get_connection().await?;
+
+    let mut lines = vec![format!("**{}**", feed.title)];
+    for (i, episode) in feed.episodes.iter().take(LISTED_EPISODES).enumerate() {
+        let published = episode
+            .published
+            .map(|v| format!(" ({})", v.format("%Y-%m-%d")))
+            .unwrap_or_default();
+        let duration = episode
+            .duration
+            .map(|v| format!(" `[{}]`", format_duration(v)))
+            .unwrap_or_default();
+        let progress = podcast_progress_get(&mut conn, guild_id, &episode.url)
+            .await?
+            .map(|v| format!(" ⏸ `{}`", format_duration(v)))
+            .unwrap_or_default();
+
+        lines.push(format!(
+            "`{}.` {}{published}{duration}{progress}",
+            i + 1,
+            episode.title
+        ));
+    }
+
+    ctx.say(lines.join("\n")).await?;
+
+    Ok(())
+}
+
+/// Play an episode of a podcast
+#[poise::command(slash_command)]
+pub async fn play(
+    ctx: Context<'_>,
+    #[description = "Number of the episode (see `/podcast episodes`), 1 is the latest"]
+    #[min = 1]
+    episode: usize,
+    #[description = "The podcast (can be omitted if there is only one)"]
+    #[autocomplete = "autocomplete_podcast"]
+    podcast: Option<String>,
+    #[description = "Continue where the episode was left off (default: true)"] resume: Option<bool>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let Some(podcast) = resolve_podcast(&ctx, guild_id, podcast).await? else {
+        return Ok(());
+    };
+
+    let Some(feed) = fetch_feed_or_reply(&ctx, guild_id, &podcast.feed_url, || {
+        format!("I couldn't fetch the feed of **{}**", podcast.title)
+    })
+    .await?
+    else {
+        return Ok(());
+    };
+
+    let Some(episode) = episode.checked_sub(1).and_then(|i| feed.episodes.get(i)) else {
+        ctx.say(format!(
+            "**{}** only has `{}` episodes",
+            feed.title,
+            feed.episodes.len()
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    // The feed passed the policy, but its enclosures can point anywhere
+    let Ok(episode_url) = Url::parse(&episode.url) else {
+        ctx.say(format!(
+            "The episode **{}** has a broken URL, blame the podcast",
+            episode.title
+        ))
+        .await?;
+        return Ok(());
+    };
+    let policy = ctx.data().url_policy(Some(guild_id)).await?;
+    if let Err(blocked) = policy.check(&episode_url).await {
+        tracing::info!("blocked podcast episode \"{episode_url}\": {blocked}");
+        ctx.say(blocked_reply(&episode_url, &blocked)).await?;
+        return Ok(());
+    }
+
+    let resume_position = if resume.unwrap_or(true) {
+        let mut conn = ctx.data().database.get_connection().await?;
+        podcast_progress_get(&mut conn, guild_id, &episode.url).await?
+    } else {
+        None
+    };
+
+    let track_info = TrackInfo {
+        url: episode.url.clone(),
+        title: Some(format!("{} - {}", feed.title, episode.title)),
+        duration: episode.duration,
+        live: false,
+        opus_passthrough: false,
+    };
+    let input = HttpRequest::new(ctx.data().outbound.guarded(), episode.url.clone());
+
+    let Some(track_handle) = play_or_enqueue(&ctx, input.into(), track_info).await? else {
+        return Ok(());
+    };
+
+    let tracker = PodcastProgressTracker {
+        database: ctx.data().database.clone(),
+        guild_id,
+        episode_url: episode.url.clone(),
+    };
+    track_handle.add_event(
+        Event::Periodic(PROGRESS_SAVE_INTERVAL, None),
+        tracker.clone(),
+    )?;
+    track_handle.add_event(Event::Track(TrackEvent::End), tracker)?;
+
+    if let Some(position) = resume_position {
+        // The seek is applied as soon as the track is ready, even if it's still in the queue
+        drop(track_handle.seek(position));
+
+        ctx.say(format!("Resuming at `{}`", format_duration(position)))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Finds the podcast with the id `podcast`, or the only subscribed podcast if `None`.
+///
+/// Returns `Ok(None)` if the user was already told why there is no podcast.
+async fn resolve_podcast(
+    ctx: &Context<'_>,
+    guild_id: GuildId,
+    podcast: Option<String>,
+) -> Result<Option<PodcastRow>, Error> {
+    let mut conn = ctx.data().database.get_connection().await?;
+    let mut podcasts = podcasts_get_by_guild(&mut conn, guild_id).await?;
+
+    if podcasts.is_empty() {
+        ctx.say("This server isn't subscribed to any podcasts. Use `/podcast subscribe` first")
+            .await?;
+        return Ok(None);
+    }
+
+    let Some(id) = podcast else {
+        if podcasts.len() == 1 {
+            return Ok(podcasts.pop());
+        }
+
+        ctx.say("This server is subscribed to multiple podcasts, please pick one")
+            .await?;
+        return Ok(None);
+    };
+
+    let podcast = podcasts.into_iter().find(|v| v.id == id);
+    if podcast.is_none() {
+        ctx.say("I couldn't find that podcast. Pick one of the suggestions")
+            .await?;
+    }
+
+    Ok(podcast)
+}
+
+async fn autocomplete_podcast(ctx: Context<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Vec::new();
+    };
+
+    let podcasts = match ctx.data().database.get_connection().await {
+        Ok(mut conn) => podcasts_get_by_guild(&mut conn, guild_id).await,
+        Err(e) => Err(e),
+    };
+
+    match podcasts {
+        Ok(podcasts) => podcasts
+            .into_iter()
+            .filter(|v| v.title.to_lowercase().contains(&partial.to_lowercase()))
+            .take(25)
+            .map(|v| {
+                // Discord limits choice names to 100 characters
+                let name = v.title.chars().take(100).collect::<String>();
+                AutocompleteChoice::new(name, v.id)
+            })
+            .collect(),
+        Err(e) => {
+            tracing::error!("error autocompleting podcast \"{partial}\": {e:?}");
+            Vec::new()
+        }
+    }
+}
+
+/// Fetches a feed with the URL policy of the guild, replying with `failure` (or why the feed is
+/// blocked) if that didn't work
+async fn fetch_feed_or_reply(
+    ctx: &Context<'_>,
+    guild_id: GuildId,
+    feed_url: &str,
+    failure: impl FnOnce() -> String,
+) -> Result<Option<PodcastFeed>, Error> {
+    let policy = ctx.data().url_policy(Some(guild_id)).await?;
+
+    match fetch_feed(&ctx.data().outbound.guarded(), &policy, feed_url).await {
+        Ok(v) => Ok(Some(v)),
+        Err(e) => {
+            tracing::warn!("couldn't fetch podcast feed \"{feed_url}\": {e}");
+
+            let reply = match (blocked_reason(&*e), Url::parse(feed_url)) {
+                (Some(blocked), Ok(url)) => blocked_reply(&url, blocked),
+                _ => failure(),
+            };
+            ctx.say(reply).await?;
+
+            Ok(None)
+        }
+    }
+}
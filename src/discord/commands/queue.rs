@@ -0,0 +1,539 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use poise::serenity_prelude::GuildId;
+use rand::seq::SliceRandom;
+use songbird::input::{Compose, Input};
+use songbird::tracks::{LoopState, Track, TrackHandle};
+use url::Url;
+
+use crate::database::actions::volume_get_or_insert_default;
+use crate::discord::player::INITIAL_DEFAULT_VOLUME;
+use crate::discord::tracks::{format_duration, TrackInfo};
+use crate::discord::utils::{
+    check_url_or_reply, confirm, get_guild_id_or_error, get_or_join_voice_handler,
+    get_songbird_or_error,
+};
+use crate::discord::{Context, Error};
+use crate::metrics;
+use crate::ytdl::{is_playlist_url, Playlist};
+
+/// Maximum number of entries shown by `/queue list`
+const MAX_LISTED_ENTRIES: usize = 15;
+
+/// How long before the end of a track the next one is loaded
+const PRELOAD_BEFORE_END: Duration = Duration::from_secs(5);
+
+/// Playlists with more entries than this need to be confirmed before queueing
+const PLAYLIST_CONFIRM_THRESHOLD: usize = 25;
+
+/// How many playlist entries are checked against the URL policy at once
+const PLAYLIST_CHECK_CONCURRENCY: usize = 16;
+
+const NOTHING_PLAYING_ERR: &str = "There is nothing playing right now";
+
+/// Manage the track queue
+#[poise::command(slash_command, subcommands("add", "list"), subcommand_required)]
+pub async fn queue(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Add a track to the queue. Live radio streams replace the queue instead
+#[poise::command(slash_command)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "URL of the track or playlist"] url: String,
+    #[description = "Shuffle the tracks of a playlist"] shuffle: Option<bool>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let Ok(url) = Url::parse(&url) else {
+        ctx.say(format!(
+            "Error parsing URL \"{}\". Are you sure it's correct?",
+            url
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    if !check_url_or_reply(&ctx, &url).await? {
+        return Ok(());
+    }
+
+    if let Some(playlist) = try_get_playlist(&ctx, &url).await {
+        return enqueue_playlist(&ctx, playlist, shuffle.unwrap_or(false)).await;
+    }
+
+    let mut input = ctx.data().ytdl.input(url.as_str());
+    let metadata = match metrics::time_ytdl("metadata", input.aux_metadata()).await {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("couldn't get metadata for \"{url}\": {e:?}");
+            ctx.say(format!("I couldn't find anything to play at <{url}>"))
+                .await?;
+            return Ok(());
+        }
+    };
+    let track_info = TrackInfo::from_aux_metadata(url.to_string(), &metadata);
+
+    play_or_enqueue(&ctx, input.into(), track_info).await?;
+
+    Ok(())
+}
+
+/// Plays live streams immediately (replacing the queue) and adds everything else to the queue.
+///
+/// Returns `Ok(None)` if the bot couldn't join a voice channel.
+pub(super) async fn play_or_enqueue(
+    ctx: &Context<'_>,
+    input: Input,
+    track_info: TrackInfo,
+) -> Result<Option<TrackHandle>, Error> {
+    let mut conn = ctx.data().database.get_connection().await?;
+
+    let guild_id = get_guild_id_or_error(ctx)?;
+    let songbird_mgr = get_songbird_or_error(ctx).await?;
+
+    let Some(voice_handler) = get_or_join_voice_handler(ctx, &songbird_mgr).await? else {
+        return Ok(None);
+    };
+
+    let vol = volume_get_or_insert_default(&mut conn, guild_id, INITIAL_DEFAULT_VOLUME).await?;
+
+    // convert 0-100 to 0.0-1.0
+    let vol: f32 = vol as f32 / 100.0;
+
+    let mut voice_handler_lock = voice_handler.lock().await;
+
+    let relays = &ctx.data().relays;
+    relays.set_volume(guild_id, vol);
+    let input = relays.tap(guild_id, input, &track_info);
+
+    let track = Track::from(input).volume(vol);
+    let reply = format!("Playing **{}**", track_info.display_name());
+
+    if track_info.is_live() {
+        // Live streams never end, so queueing them makes no sense
+        voice_handler_lock.queue().stop();
+        let track_handle = voice_handler_lock.play_only(track);
+        track_info.attach(&track_handle).await;
+
+        ctx.data()
+            .guild_tracks
+            .write()
+            .await
+            .insert(guild_id, track_handle.clone());
+
+        ctx.say(reply).await?;
+        return Ok(Some(track_handle));
+    }
+
+    stop_live_track(ctx, guild_id).await;
+
+    let position = voice_handler_lock.queue().len();
+    let preload_time = track_info
+        .duration
+        .map(|d| d.saturating_sub(PRELOAD_BEFORE_END));
+    let track_handle = voice_handler_lock.enqueue_with_preload(track, preload_time);
+
+    let reply = if position == 0 {
+        reply
+    } else {
+        format!(
+            "Added **{}** to the queue at position `{position}`",
+            track_info.display_name()
+        )
+    };
+    track_info.attach(&track_handle).await;
+
+    ctx.say(reply).await?;
+
+    Ok(Some(track_handle))
+}
+
+/// Expands `url` into its playlist entries, if it points to a playlist
+pub(super) async fn try_get_playlist(ctx: &Context<'_>, url: &Url) -> Option<Playlist> {
+    if !is_playlist_url(url) {
+        return None;
+    }
+
+    match ctx.data().ytdl.playlist(url.as_str()).await {
+        Ok(playlist) => playlist,
+        Err(e) => {
+            // Let yt-dlp try to resolve it as a single input instead
+            tracing::warn!("couldn't expand playlist \"{url}\": {e:?}");
+            None
+        }
+    }
+}
+
+/// Adds the entries of `playlist` to the queue, asking for confirmation if there are a lot of them
+pub(super) async fn enqueue_playlist(
+    ctx: &Context<'_>,
+    playlist: Playlist,
+    shuffle: bool,
+) -> Result<(), Error> {
+    let mut conn = ctx.data().database.get_connection().await?;
+
+    let guild_id = get_guild_id_or_error(ctx)?;
+    let songbird_mgr = get_songbird_or_error(ctx).await?;
+
+    let title = playlist.title.as_deref().unwrap_or("the playlist");
+    let max_entries = ctx.data().config.get().max_playlist_entries;
+
+    // The playlist URL passed the policy, but its entries can point anywhere
+    let policy = ctx.data().url_policy(Some(guild_id)).await?;
+    let urls = playlist
+        .entries
+        .iter()
+        .map(|v| v.url.clone())
+        .collect::<Vec<_>>();
+    let allowed = futures::stream::iter(urls)
+        .map(|url| {
+            let policy = policy.clone();
+            async move {
+                let url = Url::parse(&url?).ok()?;
+                match policy.check(&url).await {
+                    Ok(()) => Some(true),
+                    Err(e) => {
+                        tracing::info!("blocked playlist entry \"{url}\": {e}");
+                        Some(false)
+                    }
+                }
+            }
+        })
+        .buffered(PLAYLIST_CHECK_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+    let blocked = allowed.iter().filter(|v| **v == Some(false)).count();
+
+    let mut entries = playlist
+        .entries
+        .iter()
+        .zip(allowed)
+        .filter(|(_, allowed)| *allowed == Some(true))
+        .map(|(entry, _)| entry)
+        .collect::<Vec<_>>();
+    let total = entries.len();
+
+    if entries.is_empty() {
+        let reply = if blocked > 0 {
+            format!("Everything in **{title}** is off limits here 🚫")
+        } else {
+            format!("**{title}** doesn't contain anything i can play")
+        };
+        ctx.say(reply).await?;
+        return Ok(());
+    }
+
+    if total > PLAYLIST_CONFIRM_THRESHOLD {
+        let question = if total > max_entries {
+            format!("**{title}** has `{total}` tracks, but only the first `{max_entries}` can be queued. Add them anyway?")
+        } else {
+            format!("**{title}** has `{total}` tracks. Add all of them to the queue?")
+        };
+
+        if !confirm(ctx, &question).await? {
+            ctx.say("Okay, not adding anything").await?;
+            return Ok(());
+        }
+    }
+
+    entries.truncate(max_entries);
+    if shuffle {
+        entries.shuffle(&mut rand::thread_rng());
+    }
+
+    let Some(voice_handler) = get_or_join_voice_handler(ctx, &songbird_mgr).await? else {
+        return Ok(());
+    };
+
+    let vol = volume_get_or_insert_default(&mut conn, guild_id, INITIAL_DEFAULT_VOLUME).await?;
+
+    // convert 0-100 to 0.0-1.0
+    let vol: f32 = vol as f32 / 100.0;
+
+    stop_live_track(ctx, guild_id).await;
+    ctx.data().relays.set_volume(guild_id, vol);
+
+    let mut voice_handler_lock = voice_handler.lock().await;
+
+    for entry in &entries {
+        let Some(url) = entry.url.as_deref() else {
+            continue;
+        };
+
+        let track_info = TrackInfo {
+            url: url.to_string(),
+            title: entry.title.clone(),
+            duration: entry.duration(),
+            live: false,
+            opus_passthrough: false,
+        };
+        let input = ctx
+            .data()
+            .relays
+            .tap(guild_id, ctx.data().ytdl.input(url).into(), &track_info);
+        let track = Track::from(input).volume(vol);
+
+        // Querying the duration for each entry would spawn yt-dlp once per track,
+        // so the preload time is derived from the playlist entry instead
+        let preload_time = entry
+            .duration()
+            .map(|d| d.saturating_sub(PRELOAD_BEFORE_END));
+        let track_handle = voice_handler_lock.enqueue_with_preload(track, preload_time);
+
+        track_info.attach(&track_handle).await;
+    }
+
+    let shuffled = if shuffle { " in random order" } else { "" };
+    let skipped = if blocked > 0 {
+        format!(" (skipped `{blocked}` which are off limits 🚫)")
+    } else {
+        String::new()
+    };
+    ctx.say(format!(
+        "Added `{}` tracks from **{title}** to the queue{shuffled}{skipped}",
+        entries.len()
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// A live stream started via `/play` isn't part of the queue and would play alongside it
+async fn stop_live_track(ctx: &Context<'_>, guild_id: GuildId) {
+    if let Some(track_handle) = ctx.data().guild_tracks.write().await.remove(&guild_id) {
+        track_handle.stop().ok();
+    }
+}
+
+/// List the tracks in the queue
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let songbird_mgr = get_songbird_or_error(&ctx).await?;
+
+    let queue = match songbird_mgr.get(guild_id) {
+        Some(handler) => handler.lock().await.queue().current_queue(),
+        None => Vec::new(),
+    };
+
+    if queue.is_empty() {
+        ctx.say("The queue is empty").await?;
+        return Ok(());
+    }
+
+    let mut lines = Vec::with_capacity(queue.len().min(MAX_LISTED_ENTRIES) + 1);
+    for (i, track_handle) in queue.iter().take(MAX_LISTED_ENTRIES).enumerate() {
+        let (name, duration) = match TrackInfo::of(track_handle).await {
+            Some(info) => (
+                info.display_name().to_string(),
+                info.duration.map(format_duration),
+            ),
+            None => ("<unknown>".to_string(), None),
+        };
+        let duration = duration.map(|v| format!(" `[{v}]`")).unwrap_or_default();
+
+        if i == 0 {
+            lines.push(format!("**Now playing:** {name}{duration}"));
+        } else {
+            lines.push(format!("`{i}.` {name}{duration}"));
+        }
+    }
+
+    if queue.len() > MAX_LISTED_ENTRIES {
+        lines.push(format!(
+            "*...and {} more*",
+            queue.len() - MAX_LISTED_ENTRIES
+        ));
+    }
+
+    ctx.say(lines.join("\n")).await?;
+
+    Ok(())
+}
+
+/// Skip the current track in the queue
+#[poise::command(slash_command)]
+pub async fn skip(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let songbird_mgr = get_songbird_or_error(&ctx).await?;
+
+    let Some(handler) = songbird_mgr.get(guild_id) else {
+        ctx.say(NOTHING_PLAYING_ERR).await?;
+        return Ok(());
+    };
+
+    let handler_lock = handler.lock().await;
+    let queue = handler_lock.queue();
+
+    if queue.is_empty() {
+        ctx.say("The queue is empty, there is nothing to skip")
+            .await?;
+        return Ok(());
+    }
+
+    queue.skip()?;
+
+    let reply = match queue.current_queue().get(1) {
+        Some(next) => match TrackInfo::of(next).await {
+            Some(info) => format!("Skipped! Up next: **{}**", info.display_name()),
+            None => "Skipped!".to_string(),
+        },
+        None => "Skipped! That was the last track in the queue".to_string(),
+    };
+
+    ctx.say(reply).await?;
+
+    Ok(())
+}
+
+/// Shuffle the upcoming tracks in the queue
+#[poise::command(slash_command)]
+pub async fn shuffle(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let songbird_mgr = get_songbird_or_error(&ctx).await?;
+
+    let Some(handler) = songbird_mgr.get(guild_id) else {
+        ctx.say("The queue is empty").await?;
+        return Ok(());
+    };
+
+    let shuffled = handler.lock().await.queue().modify_queue(|queue| {
+        // Keep the currently playing track at the front
+        let upcoming = queue.make_contiguous().get_mut(1..).unwrap_or_default();
+        upcoming.shuffle(&mut rand::thread_rng());
+        upcoming.len()
+    });
+
+    if shuffled < 2 {
+        ctx.say("There is nothing to shuffle").await?;
+        return Ok(());
+    }
+
+    ctx.say(format!("Shuffled `{shuffled}` tracks")).await?;
+
+    Ok(())
+}
+
+/// Toggle looping of the current track
+#[poise::command(slash_command, rename = "loop")]
+pub async fn loop_track(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let songbird_mgr = get_songbird_or_error(&ctx).await?;
+
+    let Some(handler) = songbird_mgr.get(guild_id) else {
+        ctx.say(NOTHING_PLAYING_ERR).await?;
+        return Ok(());
+    };
+
+    let Some(track_handle) = handler.lock().await.queue().current() else {
+        ctx.say(NOTHING_PLAYING_ERR).await?;
+        return Ok(());
+    };
+
+    let is_looping = matches!(track_handle.get_info().await?.loops, LoopState::Infinite);
+
+    let res = if is_looping {
+        track_handle.disable_loop()
+    } else {
+        track_handle.enable_loop()
+    };
+
+    if let Err(e) = res {
+        tracing::warn!(
+            "couldn't toggle loop of track {:?}: {e:?}",
+            track_handle.uuid()
+        );
+        ctx.say("This track can't be looped").await?;
+        return Ok(());
+    }
+
+    if is_looping {
+        ctx.say("Stopped looping the current track").await?;
+    } else {
+        ctx.say("Looping the current track").await?;
+    }
+
+    Ok(())
+}
+
+/// Remove a track from the queue
+#[poise::command(slash_command)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "Position in the queue (see `/queue list`)"]
+    #[min = 1]
+    position: usize,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let songbird_mgr = get_songbird_or_error(&ctx).await?;
+
+    let Some(handler) = songbird_mgr.get(guild_id) else {
+        ctx.say("The queue is empty").await?;
+        return Ok(());
+    };
+
+    if position == 0 {
+        ctx.say("Use `/skip` to remove the currently playing track")
+            .await?;
+        return Ok(());
+    }
+
+    let Some(queued) = handler.lock().await.queue().dequeue(position) else {
+        ctx.say(format!("There is no track at position `{position}`"))
+            .await?;
+        return Ok(());
+    };
+
+    // Dequeued tracks still exist in the driver and have to be stopped
+    queued.stop().ok();
+
+    let name = TrackInfo::of(&queued.handle())
+        .await
+        .map(|v| v.display_name().to_string())
+        .unwrap_or_else(|| "the track".to_string());
+
+    ctx.say(format!("Removed **{name}** from the queue"))
+        .await?;
+
+    Ok(())
+}
+
+/// Remove all upcoming tracks from the queue
+#[poise::command(slash_command)]
+pub async fn clear(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let songbird_mgr = get_songbird_or_error(&ctx).await?;
+
+    let Some(handler) = songbird_mgr.get(guild_id) else {
+        ctx.say("The queue is empty").await?;
+        return Ok(());
+    };
+
+    let removed = handler.lock().await.queue().modify_queue(|queue| {
+        let upcoming: Vec<_> = queue.drain(1.min(queue.len())..).collect();
+        for queued in &upcoming {
+            queued.stop().ok();
+        }
+        upcoming.len()
+    });
+
+    ctx.say(format!("Removed `{removed}` tracks from the queue"))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use poise::CreateReply;
+use rand::distributions::{Alphanumeric, DistString};
+
+use crate::database::actions::{relay_token_delete, relay_token_insert_or_update};
+use crate::discord::utils::get_guild_id_or_error;
+use crate::discord::{Context, Error};
+
+const TOKEN_LEN: usize = 32;
+
+/// Listen along outside of Discord
+#[poise::command(
+    slash_command,
+    subcommands("enable", "disable"),
+    subcommand_required,
+    guild_only
+)]
+pub async fn relay(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Enable the relay of this server (or replace its token) and get the stream URL
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+pub async fn enable(ctx: Context<'_>) -> Result<(), Error> {
+    // The reply contains the token, so only the caller gets to see it
+    ctx.defer_ephemeral().await?;
+
+    if ctx.data().config.get().http_bind.is_none() {
+        ctx.say("The relay isn't available, the HTTP server is disabled")
+            .await?;
+        return Ok(());
+    }
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+    let token = Alphanumeric.sample_string(&mut rand::thread_rng(), TOKEN_LEN);
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    relay_token_insert_or_update(&mut conn, guild_id, &token).await?;
+    ctx.data().relays.enable(guild_id, token.clone())?;
+
+    let url = format!(
+        "{}/guild/{guild_id}/stream?token={token}",
+        ctx.data().config.get().public_url
+    );
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Listen along at <{url}>\nThe relay starts with the next track, anyone with this link can listen. Use `/relay enable` again to replace it"
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Disable the relay of this server and disconnect all listeners
+#[poise::command(slash_command, required_permissions = "MANAGE_GUILD")]
+pub async fn disable(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let guild_id = get_guild_id_or_error(&ctx)?;
+
+    let mut conn = ctx.data().database.get_connection().await?;
+    let deleted = relay_token_delete(&mut conn, guild_id).await?;
+    let disabled = ctx.data().relays.disable(guild_id);
+
+    if !deleted && !disabled {
+        ctx.say("The relay isn't enabled").await?;
+        return Ok(());
+    }
+
+    ctx.say("Disabled the relay").await?;
+
+    Ok(())
+}
@@ -3,16 +3,69 @@ use std::sync::Arc;
 
 use poise::serenity_prelude::GuildId;
 use songbird::tracks::TrackHandle;
+use songbird::Songbird;
 use tokio::sync::RwLock;
 
-use crate::{config::Config, database::DatabaseContext};
+use crate::{
+    config::ConfigHandle, credentials::Credentials, database::DatabaseContext,
+    discord::events::GuildEvents, discord::Error, hub::StreamHub, outbound::Outbound,
+    relay::Relays, url_policy::UrlPolicy, ytdl::YtDlp,
+};
 
-/// Data shared by discord-related code
+/// Data shared by discord-related code.
+///
+/// Cloning is cheap, all clones share the same state (e.g. with the HTTP API).
+#[derive(Clone)]
 pub struct Data {
-    /// Reference to the application config
-    pub config: Arc<Config>,
+    /// The application config, which can be reloaded at runtime
+    pub config: ConfigHandle,
 
     pub database: DatabaseContext,
 
-    pub guild_tracks: RwLock<HashMap<GuildId, TrackHandle>>,
+    pub guild_tracks: Arc<RwLock<HashMap<GuildId, TrackHandle>>>,
+
+    pub ytdl: YtDlp,
+
+    /// The HTTP client for everything fetched from elsewhere
+    pub outbound: Outbound,
+
+    /// Headers and logins of private streams
+    pub credentials: Credentials,
+
+    /// Live streams shared between guilds
+    pub stream_hub: StreamHub,
+
+    /// Relays of guilds which enabled them
+    pub relays: Relays,
+
+    /// Playback changes of all guilds
+    pub events: GuildEvents,
+}
+
+impl Data {
+    /// Gets the track that is currently playing in the guild, either started via `/play` or from
+    /// the queue
+    pub async fn current_track(
+        &self,
+        songbird_mgr: &Songbird,
+        guild_id: GuildId,
+    ) -> Option<TrackHandle> {
+        if let Some(track_handle) = self.guild_tracks.read().await.get(&guild_id) {
+            return Some(track_handle.clone());
+        }
+
+        let handler = songbird_mgr.get(guild_id)?;
+        let current = handler.lock().await.queue().current();
+        current
+    }
+
+    /// The URL policy of the guild, or the global one outside of guilds
+    pub async fn url_policy(&self, guild_id: Option<GuildId>) -> Result<UrlPolicy, Error> {
+        let config = self.config.get();
+
+        match guild_id {
+            Some(guild_id) => UrlPolicy::for_guild(&config, &self.database, guild_id).await,
+            None => Ok(UrlPolicy::global(&config)),
+        }
+    }
 }
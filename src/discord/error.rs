@@ -1,6 +1,7 @@
 use poise::FrameworkError;
 
 use crate::discord::Data;
+use crate::metrics;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 
@@ -14,6 +15,10 @@ pub enum VoiceChannelJoinError {
 }
 
 pub async fn on_error(error: FrameworkError<'_, Data, Error>) {
+    if let Some(ctx) = error.ctx() {
+        metrics::COMMAND_ERRORS.inc(&ctx.command().qualified_name);
+    }
+
     match error {
         FrameworkError::Setup { error, .. } => panic!("Failed to start bot: {error:?}"),
         FrameworkError::Command { error, ctx, .. } => {
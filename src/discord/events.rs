@@ -0,0 +1,162 @@
+use poise::async_trait;
+use poise::serenity_prelude::GuildId;
+use serde::Serialize;
+use songbird::{Event, EventContext, EventHandler};
+use tokio::sync::broadcast;
+
+use crate::database::actions::{history_get_by_guild, history_insert};
+use crate::database::DatabaseContext;
+use crate::discord::tracks::TrackInfo;
+use crate::discord::Error;
+
+/// Number of events buffered per subscriber
+const EVENT_BUFFER: usize = 64;
+
+/// Playback changes in a guild, e.g. pushed to clients of the HTTP API
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GuildEvent {
+    /// A track started (or resumed) playing
+    TrackStart {
+        guild_id: GuildId,
+        url: Option<String>,
+        title: Option<String>,
+        /// Length in seconds, if known
+        duration: Option<u64>,
+        live: bool,
+    },
+    /// The current track ended or was stopped
+    TrackEnd {
+        guild_id: GuildId,
+    },
+    Volume {
+        guild_id: GuildId,
+        volume: u32,
+    },
+}
+
+impl GuildEvent {
+    pub fn guild_id(&self) -> GuildId {
+        match self {
+            Self::TrackStart { guild_id, .. }
+            | Self::TrackEnd { guild_id }
+            | Self::Volume { guild_id, .. } => *guild_id,
+        }
+    }
+}
+
+/// Broadcasts [`GuildEvent`]s to everyone interested in them
+#[derive(Clone)]
+pub struct GuildEvents {
+    tx: broadcast::Sender<GuildEvent>,
+}
+
+impl Default for GuildEvents {
+    fn default() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUFFER);
+        Self { tx }
+    }
+}
+
+impl GuildEvents {
+    pub fn send(&self, event: GuildEvent) {
+        // Nobody listening is fine
+        self.tx.send(event).ok();
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<GuildEvent> {
+        self.tx.subscribe()
+    }
+}
+
+/// Turns track events of a voice handler into [`GuildEvent`]s.
+///
+/// Should be registered as a global event for [`songbird::TrackEvent::Play`] and
+/// [`songbird::TrackEvent::End`].
+pub struct TrackEventNotifier {
+    pub guild_id: GuildId,
+    pub events: GuildEvents,
+}
+
+#[async_trait]
+impl EventHandler for TrackEventNotifier {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(track_list) = ctx else {
+            return None;
+        };
+
+        for (state, handle) in *track_list {
+            let event = if state.playing.is_done() {
+                GuildEvent::TrackEnd {
+                    guild_id: self.guild_id,
+                }
+            } else {
+                // Songbird only passes Opus frames through for the only live track at volume 1.0
+                tracing::debug!(
+                    "track started in guild {}: {:?} at volume {}, {} track(s) live",
+                    self.guild_id,
+                    state.playing,
+                    state.volume,
+                    track_list.len(),
+                );
+
+                let info = TrackInfo::of(handle).await;
+                GuildEvent::TrackStart {
+                    guild_id: self.guild_id,
+                    url: info.as_ref().map(|v| v.url.clone()),
+                    title: info.as_ref().and_then(|v| v.title.clone()),
+                    duration: info.as_ref().and_then(|v| v.duration).map(|v| v.as_secs()),
+                    live: info.map(|v| v.live).unwrap_or(true),
+                }
+            };
+
+            self.events.send(event);
+        }
+
+        None
+    }
+}
+
+/// Saves every track which starts playing to the history of its guild
+pub fn spawn_history_recorder(database: DatabaseContext, events: &GuildEvents) {
+    let mut rx = events.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(v) => v,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("history recorder missed {n} events");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+
+            if let Err(e) = record(&database, event).await {
+                tracing::error!("couldn't save play history: {e}");
+            }
+        }
+    });
+}
+
+async fn record(database: &DatabaseContext, event: GuildEvent) -> Result<(), Error> {
+    let GuildEvent::TrackStart {
+        guild_id,
+        url: Some(url),
+        title,
+        ..
+    } = event
+    else {
+        return Ok(());
+    };
+
+    let mut conn = database.get_connection().await?;
+
+    // Resuming a paused track fires another start event
+    let latest = history_get_by_guild(&mut conn, guild_id, 1).await?;
+    if latest.first().is_some_and(|v| v.url == url) {
+        return Ok(());
+    }
+
+    history_insert(&mut conn, guild_id, &url, title.as_deref()).await
+}
@@ -1,6 +1,10 @@
 mod commands;
 mod data;
 mod error;
+pub mod events;
+pub mod player;
+pub mod registration;
+pub mod tracks;
 mod utils;
 mod voice;
 
@@ -9,65 +13,129 @@ use std::sync::Arc;
 
 pub use data::Data;
 pub use error::Error;
+use events::GuildEvents;
 
-use poise::serenity_prelude::{self as serenity, Client, GuildId};
-use songbird::SerenityInit;
+use poise::serenity_prelude::{self as serenity, Client};
+use songbird::{SerenityInit, Songbird};
 use tokio::signal::unix::SignalKind;
 use tokio::sync::RwLock;
 
-use crate::{config::Config, database::DatabaseContext};
+use crate::{
+    config::ConfigHandle,
+    credentials::Credentials,
+    database::DatabaseContext,
+    health::{self, Health},
+    http,
+    hub::StreamHub,
+    metrics,
+    outbound::Outbound,
+    podcast,
+    relay::Relays,
+    schedule,
+    ytdl::YtDlp,
+};
 
 type Context<'a> = poise::Context<'a, Data, Error>;
 
-pub async fn start(config: Config, db: DatabaseContext) -> Result<(), Error> {
-    let token = config.discord_token.clone();
+/// All commands of the bot
+pub fn commands() -> Vec<poise::Command<Data, Error>> {
+    vec![
+        commands::echo(),
+        commands::admin::reload(),
+        commands::admin::status(),
+        commands::audio::volume(),
+        commands::audio::play(),
+        commands::audio::probe(),
+        commands::audio::pause(),
+        commands::audio::stop(),
+        commands::audio::join(),
+        commands::audio::disconnect(),
+        commands::queue::queue(),
+        commands::queue::skip(),
+        commands::queue::shuffle(),
+        commands::queue::loop_track(),
+        commands::queue::remove(),
+        commands::queue::clear(),
+        commands::playback::now_playing(),
+        commands::playback::seek(),
+        commands::playback::forward(),
+        commands::playback::rewind(),
+        commands::library::library(),
+        commands::podcast::podcast(),
+        commands::relay::relay(),
+        commands::favorite::favorite(),
+        commands::credentials::credentials(),
+        commands::domains::domains(),
+    ]
+}
+
+pub async fn start(
+    config: ConfigHandle,
+    db: DatabaseContext,
+    ytdl: YtDlp,
+    outbound: Outbound,
+    credentials: Credentials,
+) -> Result<(), Error> {
+    let token = config.get().discord_token.expose().to_string();
+
+    let relays = Relays::load(&db).await?;
+
+    let data = Data {
+        ytdl,
+        outbound,
+        credentials,
+        config,
+        database: db,
+        guild_tracks: Arc::new(RwLock::new(HashMap::new())),
+        stream_hub: StreamHub::new(),
+        relays,
+        events: GuildEvents::default(),
+    };
+    let songbird = Songbird::serenity();
 
     let options = poise::FrameworkOptions {
-        commands: vec![
-            commands::echo(),
-            commands::audio::volume(),
-            commands::audio::play(),
-            commands::audio::pause(),
-            commands::audio::stop(),
-            commands::audio::join(),
-            commands::audio::disconnect(),
-        ],
+        commands: commands(),
         on_error: |error| Box::pin(error::on_error(error)),
         pre_command: |ctx| {
             Box::pin(async move {
                 tracing::debug!("executing command \"{}\"...", ctx.command().qualified_name,);
+                metrics::COMMANDS.inc(&ctx.command().qualified_name);
             })
         },
         ..Default::default()
     };
 
+    let setup_data = data.clone();
+    let setup_songbird = songbird.clone();
     let framework = poise::Framework::builder()
         .setup(move |ctx, ready, framework| {
             Box::pin(async move {
+                let data = setup_data;
+                let config = data.config.get();
                 tracing::info!("logged in as {}", ready.user.name);
 
-                if config.is_debug {
-                    let Some(dev_guild) = config.debug_guild else {
-                        panic!("config.debug_guild is None, event though config.is_debug == true");
-                    };
-
-                    tracing::info!("registering commands in guild {dev_guild}");
-                    poise::builtins::register_in_guild(
-                        ctx,
-                        &framework.options().commands,
-                        GuildId::new(dev_guild),
-                    )
-                    .await?;
-                } else if config.should_publish_global {
-                    tracing::info!("registering commands globally");
-                    poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                registration::sync_on_startup(
+                    &ctx.http,
+                    &data.database,
+                    &config,
+                    &framework.options().commands,
+                )
+                .await?;
+
+                if let Some(interval) = config.podcast_refresh_interval {
+                    podcast::spawn_feed_refresh(
+                        ctx.http.clone(),
+                        data.database.clone(),
+                        data.config.clone(),
+                        data.outbound.guarded(),
+                        interval,
+                    );
                 }
 
-                Ok(Data {
-                    config: Arc::new(config),
-                    database: db,
-                    guild_tracks: RwLock::new(HashMap::new()),
-                })
+                events::spawn_history_recorder(data.database.clone(), &data.events);
+                schedule::spawn_scheduler(data.clone(), setup_songbird);
+
+                Ok(data)
             })
         })
         .options(options)
@@ -81,9 +149,28 @@ pub async fn start(config: Config, db: DatabaseContext) -> Result<(), Error> {
 
     let mut client = serenity::ClientBuilder::new(token, intents)
         .framework(framework)
-        .register_songbird()
+        .register_songbird_with(songbird.clone())
         .await?;
 
+    // Started before connecting to the gateway, so the health endpoints are available right away
+    let health = Health::new(data.database.clone(), client.shard_manager.clone());
+    health::spawn_monitor(health.clone());
+
+    setup_reload_signal(data.config.clone(), songbird.clone());
+
+    if let Some(bind) = data.config.get().http_bind {
+        http::spawn_server(
+            bind,
+            http::AppState {
+                data,
+                songbird,
+                cache: client.cache.clone(),
+                sessions: http::Sessions::default(),
+                health,
+            },
+        );
+    }
+
     setup_graceful_shutdown(&mut client).await;
 
     client.start().await?;
@@ -100,6 +187,7 @@ async fn setup_graceful_shutdown(client: &mut Client) {
                 .expect("couldn't register CTRL+C handler");
 
             tracing::warn!("received CTRL+C event, shutting down...");
+            health::notify_stopping();
             shartman.shutdown_all().await;
         });
     }
@@ -110,7 +198,30 @@ async fn setup_graceful_shutdown(client: &mut Client) {
             stream.recv().await;
 
             tracing::warn!("received UNIX terminate signal, shutting down...");
+            health::notify_stopping();
             shartman.shutdown_all().await;
         });
     }
 }
+
+/// Reloads the config on `SIGHUP`
+fn setup_reload_signal(config: ConfigHandle, songbird: Arc<Songbird>) {
+    tokio::spawn(async move {
+        let mut stream = tokio::signal::unix::signal(SignalKind::hangup()).unwrap();
+
+        while stream.recv().await.is_some() {
+            tracing::info!("received UNIX hangup signal, reloading config...");
+
+            match config.reload() {
+                Ok(report) => {
+                    tracing::info!("reloaded config: {report}");
+
+                    if report.applied.contains(&"SELF_DEAF") {
+                        player::deafen_all(&songbird, config.get().self_deaf).await;
+                    }
+                }
+                Err(e) => tracing::error!("couldn't reload config, keeping the old one: {e}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,295 @@
+//! Playback actions shared by the slash commands and the HTTP API
+
+use std::sync::Arc;
+
+use poise::serenity_prelude::{ChannelId, GuildId};
+use reqwest::header::HeaderMap;
+use songbird::input::{Compose, HttpRequest, Input};
+use songbird::tracks::{Track, TrackHandle};
+use songbird::{Call, Songbird, TrackEvent};
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::database::actions::{volume_get_or_insert_default, volume_insert_or_update};
+use crate::discord::events::{GuildEvent, TrackEventNotifier};
+use crate::discord::tracks::TrackInfo;
+use crate::discord::voice::TrackErrorNotifier;
+use crate::discord::{Data, Error};
+use crate::hls::{is_hls_url, HlsInput};
+use crate::stream::CountedInput;
+use crate::url_policy::{blocked_reason, Blocked};
+use crate::{metrics, stream};
+
+pub const INITIAL_DEFAULT_VOLUME: i32 = 100;
+
+/// Registers the global event handlers every voice handler should have.
+///
+/// Must only be called once per guild, when its voice handler is created.
+pub fn add_global_events(handler: &mut Call, data: &Data, guild_id: GuildId) {
+    handler.add_global_event(
+        TrackEvent::Error.into(),
+        TrackErrorNotifier {
+            ytdl: data.ytdl.clone(),
+        },
+    );
+
+    for event in [TrackEvent::Play, TrackEvent::End] {
+        handler.add_global_event(
+            event.into(),
+            TrackEventNotifier {
+                guild_id,
+                events: data.events.clone(),
+            },
+        );
+    }
+}
+
+/// Joins a voice channel, setting up the voice handler if the guild didn't have one yet
+pub async fn join_channel(
+    data: &Data,
+    songbird_mgr: &Songbird,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Result<Arc<Mutex<Call>>, Error> {
+    let is_new = songbird_mgr.get(guild_id).is_none();
+    let handler = songbird_mgr.join(guild_id, channel_id).await?;
+
+    let mut handler_lock = handler.lock().await;
+    if is_new {
+        add_global_events(&mut handler_lock, data, guild_id);
+    }
+    handler_lock.deafen(data.config.get().self_deaf).await?;
+    drop(handler_lock);
+
+    Ok(handler)
+}
+
+/// Applies `self_deaf` to every voice connection, e.g. after `SELF_DEAF` was reloaded
+pub async fn deafen_all(songbird_mgr: &Songbird, self_deaf: bool) {
+    let calls = songbird_mgr.iter().collect::<Vec<_>>();
+
+    for (guild_id, call) in calls {
+        if let Err(e) = call.lock().await.deafen(self_deaf).await {
+            tracing::warn!("couldn't apply SELF_DEAF in guild {}: {e}", guild_id.0);
+        }
+    }
+}
+
+/// Plays `input` in the guild, replacing whatever is playing (including the queue)
+pub async fn play_only(
+    data: &Data,
+    guild_id: GuildId,
+    voice_handler: &Mutex<Call>,
+    input: Input,
+    track_info: TrackInfo,
+) -> Result<TrackHandle, Error> {
+    let mut conn = data.database.get_connection().await?;
+
+    let vol = volume_get_or_insert_default(&mut conn, guild_id, INITIAL_DEFAULT_VOLUME).await?;
+
+    // convert 0-100 to 0.0-1.0
+    let vol: f32 = vol as f32 / 100.0;
+
+    data.relays.set_volume(guild_id, vol);
+    let input = data.relays.tap(guild_id, input, &track_info);
+
+    let mut voice_handler_lock = voice_handler.lock().await;
+
+    voice_handler_lock.queue().stop();
+    let track_handle = voice_handler_lock.play_only(Track::from(input).volume(vol));
+    track_info.attach(&track_handle).await;
+
+    data.guild_tracks
+        .write()
+        .await
+        .insert(guild_id, track_handle.clone());
+
+    Ok(track_handle)
+}
+
+/// The result of [`webradio_input`]
+pub enum WebradioInput {
+    Playable(Input, TrackInfo),
+    /// The URL points to an audio stream which can't be played, for the given reason
+    Unsupported(String),
+    /// The URL policy doesn't allow playing the URL
+    Blocked(Blocked),
+}
+
+/// Creates the input for `url`.
+///
+/// HLS playlists and direct audio streams are played natively, which starts a lot faster than going
+/// through yt-dlp. Everything else (e.g. websites) is handed to yt-dlp.
+///
+/// The URL has to pass the URL policy of the guild. The credentials of the guild for `url` (or the
+/// favorite it's played as) are sent with every request.
+pub async fn webradio_input(
+    data: &Data,
+    guild_id: GuildId,
+    url: &Url,
+    favorite_id: Option<&str>,
+) -> Result<WebradioInput, Error> {
+    let policy = data.url_policy(Some(guild_id)).await?;
+    if let Err(e) = policy.check(url).await {
+        tracing::info!("blocked \"{url}\": {e}");
+        return Ok(WebradioInput::Blocked(e));
+    }
+
+    let auth = data
+        .credentials
+        .find(&data.database, guild_id, url, favorite_id)
+        .await?;
+    let headers = match &auth {
+        Some(auth) => auth.header_map()?,
+        None => HeaderMap::new(),
+    };
+    let client = data.outbound.guarded();
+
+    if is_hls_url(url) {
+        let input = CountedInput::new(HlsInput::new(
+            client,
+            policy,
+            headers,
+            url.clone(),
+            data.config.get().hls_max_bitrate,
+        ));
+        let track_info = TrackInfo {
+            url: url.to_string(),
+            title: None,
+            duration: None,
+            live: true,
+            opus_passthrough: false,
+        };
+
+        return Ok(WebradioInput::Playable(input.into(), track_info));
+    }
+
+    match stream::probe(&client, &policy, url, &headers).await {
+        Ok(Some(info)) => {
+            if let Some(reason) = info.unsupported {
+                return Ok(WebradioInput::Unsupported(reason));
+            }
+
+            let opus_passthrough = match info.opus_passthrough() {
+                Ok(()) => {
+                    tracing::debug!("\"{url}\" is eligible for Opus passthrough");
+                    true
+                }
+                Err(reason) => {
+                    tracing::debug!("no Opus passthrough for \"{url}\": {reason}");
+                    false
+                }
+            };
+
+            // Opus passthrough is even cheaper than decoding once for everyone. Streams with
+            // credentials aren't shared, as other guilds shouldn't be able to listen along.
+            let share =
+                data.config.get().share_streams && info.live && !opus_passthrough && auth.is_none();
+
+            let input = if share {
+                let request =
+                    CountedInput::new(HttpRequest::new(client.clone(), info.url.to_string()));
+                match data
+                    .stream_hub
+                    .subscribe(info.url.as_str(), move || request.into())
+                    .await
+                {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::warn!("couldn't share stream \"{url}\": {e}");
+                        CountedInput::new(HttpRequest::new(client, url.to_string())).into()
+                    }
+                }
+            } else if auth.is_some() {
+                // Credentials are only ever sent to the URL they were saved for
+                CountedInput::new(HttpRequest::new_with_headers(
+                    client,
+                    url.to_string(),
+                    headers,
+                ))
+                .into()
+            } else {
+                // The URL the probe ended up at passed the policy of the guild, the client would
+                // only check another redirect against the global one
+                CountedInput::new(HttpRequest::new(client, info.url.to_string())).into()
+            };
+
+            let track_info = TrackInfo {
+                url: url.to_string(),
+                title: info.name,
+                duration: None,
+                live: true,
+                opus_passthrough,
+            };
+
+            return Ok(WebradioInput::Playable(input, track_info));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            // yt-dlp would follow the redirect as well
+            if let Some(blocked) = blocked_reason(&*e) {
+                tracing::info!("blocked a redirect of \"{url}\": {blocked}");
+                return Ok(WebradioInput::Blocked(blocked.clone()));
+            }
+            tracing::debug!("couldn't probe \"{url}\", falling back to yt-dlp: {e}");
+        }
+    }
+
+    let mut input = match &auth {
+        Some(auth) => data.ytdl.input_with_args(url.as_str(), auth.ytdl_args()?),
+        None => data.ytdl.input(url.as_str()),
+    };
+
+    // Without metadata the track is treated like a live stream (e.g. it can't be seeked)
+    let track_info = match metrics::time_ytdl("metadata", input.aux_metadata()).await {
+        Ok(metadata) => TrackInfo::from_aux_metadata(url.to_string(), &metadata),
+        Err(e) => {
+            tracing::debug!("couldn't get metadata for \"{url}\": {e:?}");
+            TrackInfo {
+                url: url.to_string(),
+                title: None,
+                duration: None,
+                live: true,
+                opus_passthrough: false,
+            }
+        }
+    };
+
+    Ok(WebradioInput::Playable(input.into(), track_info))
+}
+
+/// Stops the current track and clears the queue
+pub async fn stop_all(data: &Data, guild_id: GuildId, voice_handler: &mut Call) {
+    data.guild_tracks.write().await.remove(&guild_id);
+    voice_handler.queue().stop();
+    voice_handler.stop();
+}
+
+/// Applies `volume` (0-100) to everything playing in the guild and saves it.
+///
+/// The caller has to make sure it doesn't exceed the maximum volume.
+pub async fn set_volume(
+    data: &Data,
+    songbird_mgr: &Songbird,
+    guild_id: GuildId,
+    volume: u32,
+) -> Result<(), Error> {
+    let mut conn = data.database.get_connection().await?;
+
+    if let Some(voice_handler) = songbird_mgr.get(guild_id) {
+        if let Some(track_handle) = data.guild_tracks.read().await.get(&guild_id) {
+            track_handle.set_volume(volume as f32 / 100.0).ok();
+        }
+
+        for track_handle in voice_handler.lock().await.queue().current_queue() {
+            track_handle.set_volume(volume as f32 / 100.0).ok();
+        }
+    }
+
+    data.relays.set_volume(guild_id, volume as f32 / 100.0);
+    volume_insert_or_update(&mut conn, guild_id, volume as i32).await?;
+
+    data.events.send(GuildEvent::Volume { guild_id, volume });
+
+    Ok(())
+}
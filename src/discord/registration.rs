@@ -0,0 +1,256 @@
+//! Keeps the slash commands registered with Discord in sync with the ones of the bot.
+//!
+//! The registered commands are fetched and compared with the local ones, so only commands which
+//! changed are sent to Discord (which limits how often commands can be created).
+
+use std::fmt::{self, Display};
+
+use poise::serenity_prelude::{Command, GuildId, Http};
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::database::{actions, DatabaseContext};
+use crate::discord::{Data, Error};
+
+/// Where commands are registered
+#[derive(Debug, Clone, Copy)]
+pub enum Scope {
+    /// Available in every guild, it can take a while until changes show up
+    Global,
+    /// Only available in one guild (e.g. for testing), changes show up immediately
+    Guild(GuildId),
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Global => write!(f, "globally"),
+            Self::Guild(guild_id) => write!(f, "in guild {guild_id}"),
+        }
+    }
+}
+
+/// What [`sync`] changed, by command name
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.created.is_empty() && self.updated.is_empty() && self.deleted.is_empty() {
+            return write!(f, "all {} commands are up to date", self.unchanged);
+        }
+
+        let mut parts = Vec::new();
+        for (verb, names) in [
+            ("created", &self.created),
+            ("updated", &self.updated),
+            ("deleted", &self.deleted),
+        ] {
+            if !names.is_empty() {
+                parts.push(format!("{verb} {}", names.join(", ")));
+            }
+        }
+        parts.push(format!("{} unchanged", self.unchanged));
+
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+/// Creates, updates and deletes registered commands until they match `commands`
+pub async fn sync(
+    http: &Http,
+    commands: &[poise::Command<Data, Error>],
+    scope: Scope,
+) -> Result<SyncReport, Error> {
+    let registered = match scope {
+        Scope::Global => http.get_global_commands_with_localizations().await?,
+        Scope::Guild(guild_id) => http.get_guild_commands_with_localizations(guild_id).await?,
+    };
+    let mut registered = registered
+        .into_iter()
+        .map(|v| Ok((normalize(serde_json::to_value(&v)?), v)))
+        .collect::<Result<Vec<(Value, Command)>, Error>>()?;
+
+    let mut report = SyncReport::default();
+
+    for command in poise::builtins::create_application_commands(commands) {
+        let value = normalize(serde_json::to_value(&command)?);
+        let name = value["name"].as_str().unwrap_or_default().to_string();
+
+        let existing = registered
+            .iter()
+            .position(|(v, _)| v["name"] == value["name"] && v["type"] == value["type"]);
+        let changes = match existing {
+            Some(i) => {
+                let (existing, _) = registered.swap_remove(i);
+                if existing == value {
+                    report.unchanged += 1;
+                    continue;
+                }
+                &mut report.updated
+            }
+            None => &mut report.created,
+        };
+
+        // Creating a command with the name of a registered one replaces it
+        match scope {
+            Scope::Global => http.create_global_command(&command).await?,
+            Scope::Guild(guild_id) => http.create_guild_command(guild_id, &command).await?,
+        };
+        changes.push(name);
+    }
+
+    for (_, command) in registered {
+        match scope {
+            Scope::Global => http.delete_global_command(command.id).await?,
+            Scope::Guild(guild_id) => http.delete_guild_command(guild_id, command.id).await?,
+        };
+        report.deleted.push(command.name);
+    }
+
+    Ok(report)
+}
+
+/// Brings a command into a form in which registered and local ones can be compared: Discord
+/// fills in IDs and defaults, while the builders leave out everything that isn't set
+fn normalize(mut value: Value) -> Value {
+    if let Value::Object(map) = &mut value {
+        for key in [
+            "id",
+            "application_id",
+            "guild_id",
+            "version",
+            "name_localized",
+            "description_localized",
+            "integration_types",
+            "contexts",
+        ] {
+            map.remove(key);
+        }
+
+        // Commands can be used in DMs unless they're guild only
+        let dm_permission = map
+            .remove("dm_permission")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+        if !dm_permission {
+            map.insert("guild_only".to_string(), Value::Bool(true));
+        }
+
+        // Slash commands
+        map.entry("type").or_insert(Value::from(1));
+    }
+
+    strip_defaults(&mut value);
+    value
+}
+
+fn strip_defaults(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.values_mut().for_each(strip_defaults);
+            map.retain(|_, v| !is_default(v));
+        }
+        Value::Array(values) => values.iter_mut().for_each(strip_defaults),
+        _ => {}
+    }
+}
+
+fn is_default(value: &Value) -> bool {
+    match value {
+        Value::Null | Value::Bool(false) => true,
+        Value::String(v) => v.is_empty(),
+        Value::Array(v) => v.is_empty(),
+        Value::Object(v) => v.is_empty(),
+        _ => false,
+    }
+}
+
+/// Syncs the commands on startup: In debug mode only in the debug guild, otherwise globally.
+/// Commands left in other guilds (e.g. the debug guild after debug mode was turned off) are
+/// removed.
+pub async fn sync_on_startup(
+    http: &Http,
+    database: &DatabaseContext,
+    config: &Config,
+    commands: &[poise::Command<Data, Error>],
+) -> Result<(), Error> {
+    let debug_guild = config
+        .debug_guild
+        .filter(|_| config.is_debug)
+        .map(GuildId::new);
+
+    let scope = match debug_guild {
+        Some(guild_id) => Scope::Guild(guild_id),
+        None => Scope::Global,
+    };
+    let report = sync(http, commands, scope).await?;
+    tracing::info!("synced commands {scope}: {report}");
+
+    let mut conn = database.get_connection().await?;
+    if let Some(guild_id) = debug_guild {
+        actions::command_guild_insert(&mut conn, guild_id).await?;
+    }
+
+    for guild_id in actions::command_guilds_get_all(&mut conn).await? {
+        if Some(guild_id) == debug_guild {
+            continue;
+        }
+
+        match sync(http, &[], Scope::Guild(guild_id)).await {
+            Ok(report) => {
+                tracing::info!("removed stale commands in guild {guild_id}: {report}");
+                actions::command_guild_delete(&mut conn, guild_id).await?;
+            }
+            Err(e) => tracing::warn!("couldn't remove stale commands in guild {guild_id}: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Registers the commands of the bot without starting it (`discomfort-fm register`)
+pub async fn register(
+    token: &str,
+    database: &DatabaseContext,
+    scope: Scope,
+) -> Result<SyncReport, Error> {
+    let report = sync(&http(token).await?, &super::commands(), scope).await?;
+
+    if let Scope::Guild(guild_id) = scope {
+        let mut conn = database.get_connection().await?;
+        actions::command_guild_insert(&mut conn, guild_id).await?;
+    }
+
+    Ok(report)
+}
+
+/// Removes all registered commands (`discomfort-fm unregister`)
+pub async fn unregister(
+    token: &str,
+    database: &DatabaseContext,
+    scope: Scope,
+) -> Result<SyncReport, Error> {
+    let report = sync(&http(token).await?, &[], scope).await?;
+
+    if let Scope::Guild(guild_id) = scope {
+        let mut conn = database.get_connection().await?;
+        actions::command_guild_delete(&mut conn, guild_id).await?;
+    }
+
+    Ok(report)
+}
+
+/// A client for the REST API, which knows the ID of the application
+async fn http(token: &str) -> Result<Http, Error> {
+    let http = Http::new(token);
+    let application = http.get_current_application_info().await?;
+    http.set_application_id(application.id);
+
+    Ok(http)
+}
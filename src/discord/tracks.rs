@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use poise::serenity_prelude::prelude::TypeMapKey;
+use songbird::input::AuxMetadata;
+use songbird::tracks::TrackHandle;
+
+/// Information about a track, attached to the typemap of its [`TrackHandle`]
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    /// The URL the track was requested with
+    pub url: String,
+    pub title: Option<String>,
+    /// Length of the track, if known
+    pub duration: Option<Duration>,
+    /// Live streams (e.g. webradio) never end and can't be seeked
+    pub live: bool,
+    /// The track is an Ogg/Opus stream whose frames songbird can pass through to Discord, see
+    /// [`crate::stream::StreamInfo::opus_passthrough`]
+    pub opus_passthrough: bool,
+}
+
+impl TypeMapKey for TrackInfo {
+    type Value = TrackInfo;
+}
+
+impl TrackInfo {
+    pub fn from_aux_metadata(url: String, metadata: &AuxMetadata) -> Self {
+        Self {
+            url,
+            title: metadata.title.clone(),
+            duration: metadata.duration,
+            // yt-dlp doesn't report a duration for live streams
+            live: metadata.duration.is_none(),
+            opus_passthrough: false,
+        }
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.live
+    }
+
+    /// The title of the track, or the URL if no title is known
+    pub fn display_name(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.url)
+    }
+
+    pub async fn attach(self, track_handle: &TrackHandle) {
+        track_handle
+            .typemap()
+            .write()
+            .await
+            .insert::<TrackInfo>(self);
+    }
+
+    pub async fn of(track_handle: &TrackHandle) -> Option<TrackInfo> {
+        track_handle
+            .typemap()
+            .read()
+            .await
+            .get::<TrackInfo>()
+            .cloned()
+    }
+}
+
+/// Number of characters in the progress bar
+const PROGRESS_BAR_WIDTH: usize = 20;
+
+/// Parses timestamps like `90`, `1:30` or `1:02:30` into a duration
+pub fn parse_timestamp(s: &str) -> Option<Duration> {
+    let parts = s.trim().split(':').collect::<Vec<_>>();
+    if parts.len() > 3 {
+        return None;
+    }
+
+    let mut secs = 0u64;
+    for (i, part) in parts.iter().enumerate() {
+        let value = part.parse::<u64>().ok()?;
+
+        // Minutes and seconds after the first component can't overflow into the next unit
+        if i > 0 && value >= 60 {
+            return None;
+        }
+
+        secs = secs.checked_mul(60)?.checked_add(value)?;
+    }
+
+    Some(Duration::from_secs(secs))
+}
+
+/// Renders a progress bar like `▬▬▬▬🔘▬▬▬▬▬ 01:23 / 03:45`
+pub fn progress_bar(position: Duration, duration: Duration) -> String {
+    let progress = if duration.is_zero() {
+        1.0
+    } else {
+        (position.as_secs_f64() / duration.as_secs_f64()).clamp(0.0, 1.0)
+    };
+    let knob = ((PROGRESS_BAR_WIDTH - 1) as f64 * progress).round() as usize;
+
+    let bar = (0..PROGRESS_BAR_WIDTH)
+        .map(|i| if i == knob { "🔘" } else { "▬" })
+        .collect::<String>();
+
+    format!(
+        "{bar} `{} / {}`",
+        format_duration(position.min(duration)),
+        format_duration(duration)
+    )
+}
+
+/// Formats a duration as `mm:ss` (or `h:mm:ss` for longer durations)
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, mins, secs) = (secs / 3600, (secs / 60) % 60, secs % 60);
+
+    if hours > 0 {
+        format!("{hours}:{mins:02}:{secs:02}")
+    } else {
+        format!("{mins:02}:{secs:02}")
+    }
+}
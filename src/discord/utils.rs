@@ -1,14 +1,44 @@
 use crate::discord::error::VoiceChannelJoinError;
+use crate::discord::player::add_global_events;
 use crate::discord::{Context, Error};
-use poise::serenity_prelude::{ChannelId, GuildId, UserId};
+use crate::url_policy::Blocked;
+use poise::serenity_prelude::{
+    ButtonStyle, ChannelId, CreateActionRow, CreateButton, CreateInteractionResponse, GuildId,
+    UserId,
+};
+use poise::CreateReply;
 use songbird::{Call, Songbird};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
+use url::Url;
+
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub fn get_guild_id_or_error(ctx: &Context<'_>) -> Result<GuildId, Error> {
     ctx.guild_id().ok_or_else(|| "couldn't get guild_id".into())
 }
 
+/// The reply when `url` is blocked by the URL policy
+pub fn blocked_reply(url: &Url, blocked: &Blocked) -> String {
+    format!("🚫 <{url}> is off limits: {blocked}")
+}
+
+/// Checks `url` against the URL policy of the guild, telling the user why if it's blocked.
+///
+/// Returns `false` if the URL is blocked.
+pub async fn check_url_or_reply(ctx: &Context<'_>, url: &Url) -> Result<bool, Error> {
+    let policy = ctx.data().url_policy(ctx.guild_id()).await?;
+
+    if let Err(e) = policy.check(url).await {
+        tracing::info!("blocked \"{url}\": {e}");
+        ctx.say(blocked_reply(url, &e)).await?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
 pub async fn get_songbird_or_error(ctx: &Context<'_>) -> Result<Arc<Songbird>, Error> {
     songbird::get(ctx.serenity_context())
         .await
@@ -44,3 +74,115 @@ pub async fn try_join_user_voice_channel(
         .await
         .map_err(|e| VoiceChannelJoinError::Other(e.into()))
 }
+
+/// Gets the voice handler of the current guild, joining the voice channel of the calling user
+/// if the bot isn't connected yet.
+///
+/// Returns `Ok(None)` if the user was already told why the bot couldn't join.
+pub async fn get_or_join_voice_handler(
+    ctx: &Context<'_>,
+    songbird_mgr: &Songbird,
+) -> Result<Option<Arc<Mutex<Call>>>, Error> {
+    let guild_id = get_guild_id_or_error(ctx)?;
+
+    let voice_handler = match songbird_mgr.get(guild_id) {
+        Some(v) => v,
+        None => match try_join_user_voice_channel(ctx, songbird_mgr).await {
+            Ok(handler) => {
+                let mut handler_lock = handler.lock().await;
+                add_global_events(&mut handler_lock, ctx.data(), guild_id);
+
+                handler_lock
+                    .deafen(ctx.data().config.get().self_deaf)
+                    .await?;
+
+                drop(handler_lock);
+                handler
+            }
+            Err(VoiceChannelJoinError::UserNotInVoiceChannel) => {
+                ctx.say("I'm not in a voice channel and it seems you are not in a voice channel i can access...").await?;
+                return Ok(None);
+            }
+            Err(VoiceChannelJoinError::Other(e)) => {
+                ctx.say("There was an error joining your voice channel...")
+                    .await?;
+                return Err(e);
+            }
+        },
+    };
+
+    {
+        let voice_handler_lock = voice_handler.lock().await;
+
+        // A voice handle can still exist for a guild, even though the bot isn't connected to any VC
+        if voice_handler_lock.current_connection().is_none() {
+            // Prevent deadlock. This could be handled better, but i don't care :3
+            drop(voice_handler_lock);
+
+            let user_vc = match try_get_user_voice_channel(ctx, &ctx.author().id).await {
+                Ok(v) => v,
+                Err(VoiceChannelJoinError::UserNotInVoiceChannel) => {
+                    ctx.say("I'm not in a voice channel and it seems you are not in a voice channel i can access").await?;
+                    return Ok(None);
+                }
+                Err(VoiceChannelJoinError::Other(e)) => {
+                    return Err(e);
+                }
+            };
+
+            songbird_mgr.join(guild_id, user_vc).await?;
+        }
+    }
+
+    Ok(Some(voice_handler))
+}
+
+/// Asks the calling user to confirm something via buttons.
+///
+/// Returns `false` if the user declined or didn't respond in time.
+pub async fn confirm(ctx: &Context<'_>, question: &str) -> Result<bool, Error> {
+    let confirm_id = format!("confirm-{}", ctx.id());
+    let cancel_id = format!("cancel-{}", ctx.id());
+
+    let reply =
+        ctx.send(CreateReply::default().content(question).components(vec![
+            CreateActionRow::Buttons(vec![
+                    CreateButton::new(&confirm_id)
+                        .label("Yes")
+                        .style(ButtonStyle::Primary),
+                    CreateButton::new(&cancel_id)
+                        .label("No")
+                        .style(ButtonStyle::Secondary),
+                ]),
+        ]))
+        .await?;
+
+    let interaction = reply
+        .message()
+        .await?
+        .await_component_interaction(ctx)
+        .author_id(ctx.author().id)
+        .custom_ids(vec![confirm_id.clone(), cancel_id])
+        .timeout(CONFIRM_TIMEOUT)
+        .await;
+
+    let confirmed = match interaction {
+        Some(interaction) => {
+            interaction
+                .create_response(ctx, CreateInteractionResponse::Acknowledge)
+                .await?;
+            interaction.data.custom_id == confirm_id
+        }
+        None => false,
+    };
+
+    // Remove the buttons, so they can't be clicked again
+    reply
+        .edit(
+            *ctx,
+            CreateReply::default().content(question).components(vec![]),
+        )
+        .await?;
+
+    Ok(confirmed)
+}
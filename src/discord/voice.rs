@@ -1,7 +1,19 @@
 use poise::async_trait;
+use poise::serenity_prelude::GuildId;
+use songbird::tracks::{PlayMode, TrackState};
 use songbird::{Event, EventContext, EventHandler};
 
-pub struct TrackErrorNotifier;
+use crate::database::actions::{podcast_progress_delete, podcast_progress_insert_or_update};
+use crate::database::DatabaseContext;
+use crate::discord::tracks::TrackInfo;
+use crate::discord::Error;
+use crate::ytdl::YtDlp;
+
+/// Logs tracks which failed and forgets their cached resolution, so they're resolved again the
+/// next time
+pub struct TrackErrorNotifier {
+    pub ytdl: YtDlp,
+}
 
 #[async_trait]
 impl EventHandler for TrackErrorNotifier {
@@ -13,9 +25,65 @@ impl EventHandler for TrackErrorNotifier {
                     handle.uuid(),
                     state.playing
                 );
+
+                if let Some(info) = TrackInfo::of(handle).await {
+                    self.ytdl.invalidate(&info.url).await;
+                }
             }
         }
 
         None
     }
 }
+
+/// Saves the playback position of a podcast episode, so it can be resumed later.
+///
+/// Should be registered as a periodic event and for [`songbird::TrackEvent::End`].
+#[derive(Clone)]
+pub struct PodcastProgressTracker {
+    pub database: DatabaseContext,
+    pub guild_id: GuildId,
+    pub episode_url: String,
+}
+
+#[async_trait]
+impl EventHandler for PodcastProgressTracker {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(track_list) = ctx {
+            for (state, handle) in *track_list {
+                if let Err(e) = self.save(state).await {
+                    tracing::error!(
+                        "couldn't save podcast progress of track {:?}: {e}",
+                        handle.uuid()
+                    );
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl PodcastProgressTracker {
+    async fn save(&self, state: &TrackState) -> Result<(), Error> {
+        let mut conn = self.database.get_connection().await?;
+
+        match state.playing {
+            // The episode was listened to completely, start from the beginning next time
+            PlayMode::End => {
+                podcast_progress_delete(&mut conn, self.guild_id, &self.episode_url).await
+            }
+            PlayMode::Errored(_) => Ok(()),
+            _ if state.position.is_zero() => Ok(()),
+            _ => {
+                podcast_progress_insert_or_update(
+                    &mut conn,
+                    self.guild_id,
+                    &self.episode_url,
+                    state.position,
+                )
+                .await
+            }
+        }
+    }
+}
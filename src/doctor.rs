@@ -0,0 +1,175 @@
+//! Self-check of the things playback depends on, which otherwise only fail once someone uses
+//! `/play`.
+//!
+//! Runs on startup (results are logged), via `discomfort-fm doctor` and `/status`.
+
+use std::fmt::{self, Display};
+use std::io::Cursor;
+
+use audiopus::coder::Encoder;
+use audiopus::{Application, Channels, SampleRate};
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::database::DatabaseContext;
+use crate::discord::Error;
+use crate::ytdl::YtDlp;
+
+/// Samples of the generated test audio (0.1s at 48kHz)
+const TEST_SAMPLES: usize = 4800;
+
+/// Result of one check, with some details if it passed
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub result: Result<String, String>,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|v| v.result.is_ok())
+    }
+
+    /// Logs every check, failed ones as errors
+    pub fn log(&self) {
+        for check in &self.checks {
+            match &check.result {
+                Ok(details) => tracing::info!("doctor: {} ok ({details})", check.name),
+                Err(e) => tracing::error!("doctor: {} failed: {e}", check.name),
+            }
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            match &check.result {
+                Ok(details) => writeln!(f, "✅ {}: {details}", check.name)?,
+                Err(e) => writeln!(f, "❌ {}: {e}", check.name)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs all checks
+pub async fn run(ytdl: &YtDlp, database: &DatabaseContext) -> Report {
+    let checks = vec![
+        Check {
+            name: "yt-dlp",
+            result: ytdl.version().await.map_err(|e| e.to_string()),
+        },
+        Check {
+            name: "opus",
+            result: check_opus().map_err(|e| e.to_string()),
+        },
+        Check {
+            name: "database",
+            result: check_database(database).await.map_err(|e| e.to_string()),
+        },
+        Check {
+            name: "symphonia",
+            result: check_symphonia().map_err(|e| e.to_string()),
+        },
+    ];
+
+    Report { checks }
+}
+
+/// Encodes a frame of silence, which fails if libopus is missing or broken
+fn check_opus() -> Result<String, Error> {
+    let encoder = Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio)?;
+
+    // 20ms of stereo audio
+    let input = [0i16; 960 * 2];
+    let mut output = [0u8; 4000];
+    let len = encoder.encode(&input, &mut output)?;
+
+    Ok(format!("encoded a test frame ({len} bytes)"))
+}
+
+async fn check_database(database: &DatabaseContext) -> Result<String, Error> {
+    database.check_writable().await?;
+
+    let migrations = database.migrations().await?;
+    let pending = migrations.iter().filter(|v| !v.applied).count();
+    if pending > 0 {
+        return Err(format!("{pending} of {} migrations are pending", migrations.len()).into());
+    }
+
+    Ok(format!(
+        "writable, all {} migrations applied",
+        migrations.len()
+    ))
+}
+
+/// Decodes a generated WAV file
+fn check_symphonia() -> Result<String, Error> {
+    let source = MediaSourceStream::new(Box::new(Cursor::new(test_wav())), Default::default());
+
+    let mut hint = Hint::new();
+    hint.with_extension("wav");
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+
+    let track = format.default_track().ok_or("the test file has no track")?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut decoded = 0;
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() == track_id {
+            decoded += decoder.decode(&packet)?.frames();
+        }
+    }
+
+    if decoded != TEST_SAMPLES {
+        return Err(format!("decoded {decoded} of {TEST_SAMPLES} samples").into());
+    }
+
+    Ok(format!("decoded {decoded} samples of a test file"))
+}
+
+/// A mono 16 bit WAV file with a 440Hz sine
+fn test_wav() -> Vec<u8> {
+    let data_len = (TEST_SAMPLES * 2) as u32;
+
+    let mut wav = Vec::with_capacity(44 + TEST_SAMPLES * 2);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // channels
+    wav.extend_from_slice(&48000u32.to_le_bytes()); // sample rate
+    wav.extend_from_slice(&(48000u32 * 2).to_le_bytes()); // byte rate
+    wav.extend_from_slice(&2u16.to_le_bytes()); // block align
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+
+    for i in 0..TEST_SAMPLES {
+        let t = i as f32 / 48000.0;
+        let sample = (t * 440.0 * std::f32::consts::TAU).sin() * i16::MAX as f32 * 0.5;
+        wav.extend_from_slice(&(sample as i16).to_le_bytes());
+    }
+
+    wav
+}
@@ -0,0 +1,218 @@
+//! Liveness and readiness of the bot, for container orchestration and systemd.
+//!
+//! A heartbeat task ticks every second, if it falls behind the event loop is stalled. The bot is
+//! ready once all gateway shards are connected and the database is migrated. If the bot runs as
+//! a systemd service (`Type=notify`), readiness and watchdog pings are sent via `sd_notify`.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use poise::serenity_prelude::{ConnectionStage, ShardManager};
+use sd_notify::NotifyState;
+use serde::Serialize;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::Config;
+use crate::database::DatabaseContext;
+use crate::discord::Error;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// The bot isn't live anymore if the heartbeat didn't tick for this long
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+/// Checking the database shouldn't hold up the heartbeat for too long
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct Health {
+    database: DatabaseContext,
+    shard_manager: Arc<ShardManager>,
+    last_heartbeat: Arc<Mutex<Instant>>,
+}
+
+/// Result of the readiness checks, each one is `"ok"` or the reason it failed
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub shards: String,
+    pub database: String,
+}
+
+impl Health {
+    pub fn new(database: DatabaseContext, shard_manager: Arc<ShardManager>) -> Self {
+        Self {
+            database,
+            shard_manager,
+            last_heartbeat: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Checks whether the event loop is still running, returning the reason if it isn't
+    pub fn check_live(&self) -> Result<(), String> {
+        let elapsed = self.last_heartbeat.lock().elapsed();
+        if elapsed > STALL_TIMEOUT {
+            return Err(format!(
+                "the event loop is stalled (no heartbeat for {}s)",
+                elapsed.as_secs()
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub async fn readiness(&self) -> Readiness {
+        let shards = self.check_shards().await;
+        let database =
+            match tokio::time::timeout(DATABASE_TIMEOUT, self.database.check_ready()).await {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err("the database didn't respond in time".to_string()),
+            };
+
+        Readiness {
+            ready: shards.is_ok() && database.is_ok(),
+            shards: shards.err().unwrap_or_else(|| "ok".to_string()),
+            database: database.err().unwrap_or_else(|| "ok".to_string()),
+        }
+    }
+
+    async fn check_shards(&self) -> Result<(), String> {
+        let runners = self.shard_manager.runners.lock().await;
+        let connected = runners
+            .values()
+            .filter(|v| v.stage == ConnectionStage::Connected)
+            .count();
+
+        if runners.is_empty() {
+            return Err("not connected to the gateway yet".to_string());
+        }
+        if connected < runners.len() {
+            return Err(format!(
+                "{connected} of {} shards are connected",
+                runners.len()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the heartbeat and notifies systemd (if the bot runs under it) about readiness and
+/// liveness
+pub fn spawn_monitor(health: Health) {
+    tokio::spawn(async move {
+        let mut watchdog_usec = 0;
+        let watchdog = sd_notify::watchdog_enabled(false, &mut watchdog_usec)
+            .then(|| Duration::from_micros(watchdog_usec));
+        if let Some(timeout) = watchdog {
+            tracing::info!("systemd watchdog enabled (timeout {timeout:?})");
+        }
+
+        // systemd expects a ping at least every half of the watchdog timeout
+        let period = watchdog
+            .map(|v| (v / 2).min(HEARTBEAT_INTERVAL))
+            .unwrap_or(HEARTBEAT_INTERVAL);
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut notified_ready = false;
+
+        loop {
+            interval.tick().await;
+
+            let now = Instant::now();
+            let previous = std::mem::replace(&mut *health.last_heartbeat.lock(), now);
+            let lag = now.saturating_duration_since(previous);
+            if lag > period + HEARTBEAT_INTERVAL {
+                tracing::warn!("the event loop was stalled for {:?}", lag - period);
+            }
+
+            if watchdog.is_some() {
+                notify(NotifyState::Watchdog);
+            }
+
+            if !notified_ready && health.readiness().await.ready {
+                tracing::info!("ready");
+                notify(NotifyState::Ready);
+                notified_ready = true;
+            }
+        }
+    });
+}
+
+/// Tells systemd that the bot is shutting down
+pub fn notify_stopping() {
+    notify(NotifyState::Stopping);
+}
+
+fn notify(state: NotifyState) {
+    // Does nothing if the bot doesn't run under systemd
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        tracing::warn!("couldn't notify systemd: {e}");
+    }
+}
+
+/// Asks the HTTP server of a running bot whether it's live, for `discomfort-fm healthcheck`
+pub async fn run_healthcheck(config: &Config) -> Result<(), Error> {
+    let bind = config
+        .http_bind
+        .ok_or("HTTP_BIND isn't set, so there's nothing to check")?;
+
+    check_healthz(bind).await
+}
+
+async fn check_healthz(bind: SocketAddr) -> Result<(), Error> {
+    // The server most likely listens on all interfaces, but it has to be reached via one of them
+    let ip = match bind.ip() {
+        IpAddr::V4(v) if v.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(v) if v.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        v => v,
+    };
+    let url = format!("http://{}/healthz", SocketAddr::new(ip, bind.port()));
+
+    let res = reqwest::Client::builder()
+        .timeout(STALL_TIMEOUT)
+        .build()?
+        .get(&url)
+        .send()
+        .await?;
+
+    if !res.status().is_success() {
+        return Err(format!("{url} returned {}: {}", res.status(), res.text().await?).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::Router;
+
+    use super::*;
+
+    async fn serve(status: StatusCode) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new().route("/healthz", get(move || async move { status }));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn healthcheck() {
+        // The server of the Docker image listens on all interfaces
+        let addr = serve(StatusCode::OK).await;
+        let bind = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), addr.port());
+        check_healthz(bind).await.unwrap();
+
+        let addr = serve(StatusCode::SERVICE_UNAVAILABLE).await;
+        let e = check_healthz(addr).await.unwrap_err().to_string();
+        assert!(e.contains("503"), "{e}");
+    }
+}
@@ -0,0 +1,1145 @@
+//! Native HLS (`.m3u8`) input.
+//!
+//! Segments are downloaded in order by a background task, which keeps refreshing the playlist of
+//! live streams. MPEG-TS segments are demuxed into their audio elementary stream, so symphonia
+//! only ever sees a plain ADTS/MP3 stream.
+
+use std::collections::HashMap;
+use std::io::{ErrorKind as IoErrorKind, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use songbird::input::{
+    AsyncAdapterStream, AsyncMediaSource, AudioStream, AudioStreamError, Compose, Input,
+};
+use symphonia::core::io::MediaSource;
+use symphonia::core::probe::Hint;
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::sync::mpsc;
+use url::Url;
+
+use crate::discord::Error;
+use crate::metrics;
+use crate::url_policy::UrlPolicy;
+
+/// Number of downloaded segments buffered ahead of playback
+const SEGMENT_BUFFER: usize = 3;
+/// Live streams start this many segments before the end of the playlist
+const LIVE_START_SEGMENTS: usize = 3;
+/// How often a playlist refresh may fail in a row before the stream is ended
+const MAX_REFRESH_FAILURES: u32 = 5;
+/// Used if a playlist doesn't specify `#EXT-X-TARGETDURATION`
+const DEFAULT_TARGET_DURATION: Duration = Duration::from_secs(6);
+/// Maximum size of a media segment
+const MAX_SEGMENT_SIZE: usize = 32 * 1024 * 1024;
+/// Maximum size of a playlist document
+const MAX_PLAYLIST_SIZE: usize = 4 * 1024 * 1024;
+/// How long fetching a playlist or a segment may take, including the body
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+
+/// Lazy input for an HLS stream
+#[derive(Debug, Clone)]
+pub struct HlsInput {
+    client: reqwest::Client,
+    /// Variants and segments come from the playlists, so they're checked as well
+    policy: UrlPolicy,
+    /// Sent with every request, including the segments
+    headers: HeaderMap,
+    url: Url,
+    max_bitrate: Option<u64>,
+}
+
+/// Checks whether `url` points to an HLS playlist
+pub fn is_hls_url(url: &Url) -> bool {
+    url.path().to_lowercase().ends_with(".m3u8")
+}
+
+impl HlsInput {
+    /// `max_bitrate` (bits/s) limits which variant of a master playlist is picked
+    pub fn new(
+        client: reqwest::Client,
+        policy: UrlPolicy,
+        headers: HeaderMap,
+        url: Url,
+        max_bitrate: Option<u64>,
+    ) -> Self {
+        Self {
+            client,
+            policy,
+            headers,
+            url,
+            max_bitrate,
+        }
+    }
+
+    async fn create_stream(&self) -> Result<(HlsStream, Hint), Error> {
+        let (url, playlist) = self.resolve_media_playlist().await?;
+
+        if playlist.segments.is_empty() {
+            return Err(format!("HLS playlist \"{url}\" doesn't contain any segments").into());
+        }
+
+        // Start a few segments before the live edge, or at the beginning of a finished stream
+        let start = if playlist.ended {
+            0
+        } else {
+            playlist.segments.len().saturating_sub(LIVE_START_SEGMENTS)
+        };
+        let first = &playlist.segments[start];
+
+        // The first segment is fetched upfront, its content determines the hint for symphonia
+        let mut demuxer = SegmentDemuxer::default();
+        let data = fetch_bytes(&self.client, &self.policy, &self.headers, &first.url).await?;
+        let data = demuxer.push(&first.url, &data)?;
+
+        let mut hint = Hint::new();
+        hint.with_extension(demuxer.format.extension());
+
+        let (tx, rx) = mpsc::channel(SEGMENT_BUFFER);
+        tx.send(data).await?;
+
+        let fetcher = SegmentFetcher {
+            client: self.client.clone(),
+            policy: self.policy.clone(),
+            headers: self.headers.clone(),
+            url,
+            demuxer,
+            next_sequence: first.sequence + 1,
+            tx,
+        };
+        tokio::spawn(fetcher.run(playlist));
+
+        let stream = HlsStream {
+            rx,
+            buf: Vec::new(),
+            pos: 0,
+        };
+
+        Ok((stream, hint))
+    }
+
+    /// Follows a master playlist to the media playlist of the chosen variant
+    async fn resolve_media_playlist(&self) -> Result<(Url, MediaPlaylist), Error> {
+        let body = fetch_text(&self.client, &self.policy, &self.headers, &self.url).await?;
+
+        if !is_master_playlist(&body) {
+            let playlist = MediaPlaylist::parse(&self.url, &body)?;
+            return Ok((self.url.clone(), playlist));
+        }
+
+        let variants = parse_master_playlist(&self.url, &body)?;
+        let variant = choose_variant(&variants, self.max_bitrate)
+            .ok_or_else(|| format!("HLS playlist \"{}\" doesn't have any variants", self.url))?;
+
+        tracing::debug!(
+            "picked HLS variant \"{}\" ({:?} bits/s) of \"{}\"",
+            variant.url,
+            variant.bandwidth,
+            self.url
+        );
+
+        let body = fetch_text(&self.client, &self.policy, &self.headers, &variant.url).await?;
+        if is_master_playlist(&body) {
+            return Err(
+                format!("HLS variant \"{}\" is another master playlist", variant.url).into(),
+            );
+        }
+
+        let playlist = MediaPlaylist::parse(&variant.url, &body)?;
+        Ok((variant.url.clone(), playlist))
+    }
+}
+
+#[async_trait]
+impl Compose for HlsInput {
+    fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        Err(AudioStreamError::Unsupported)
+    }
+
+    async fn create_async(
+        &mut self,
+    ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        let (stream, hint) = self.create_stream().await.map_err(AudioStreamError::Fail)?;
+
+        let stream = AsyncAdapterStream::new(Box::new(stream), 64 * 1024);
+
+        Ok(AudioStream {
+            input: Box::new(stream) as Box<dyn MediaSource>,
+            hint: Some(hint),
+        })
+    }
+
+    fn should_create_async(&self) -> bool {
+        true
+    }
+}
+
+impl From<HlsInput> for Input {
+    fn from(val: HlsInput) -> Self {
+        Input::Lazy(Box::new(val))
+    }
+}
+
+/// Downloads segments in order and refreshes the playlist until it ends
+struct SegmentFetcher {
+    client: reqwest::Client,
+    policy: UrlPolicy,
+    headers: HeaderMap,
+    url: Url,
+    demuxer: SegmentDemuxer,
+    next_sequence: u64,
+    tx: mpsc::Sender<Vec<u8>>,
+}
+
+impl SegmentFetcher {
+    async fn run(mut self, mut playlist: MediaPlaylist) {
+        let mut refresh_failures = 0;
+
+        loop {
+            let segments = new_segments(&playlist, self.next_sequence);
+            if segments
+                .first()
+                .is_some_and(|v| v.sequence < self.next_sequence)
+            {
+                tracing::info!(
+                    "media sequence of HLS stream \"{}\" restarted, continuing at the live edge",
+                    self.url
+                );
+                self.next_sequence = segments[0].sequence;
+            }
+
+            let mut found_new = false;
+            for segment in segments {
+                found_new = true;
+
+                if segment.sequence > self.next_sequence {
+                    tracing::warn!(
+                        "HLS stream \"{}\" skipped {} segments",
+                        self.url,
+                        segment.sequence - self.next_sequence
+                    );
+                }
+                self.next_sequence = segment.sequence + 1;
+
+                let data = match fetch_bytes(
+                    &self.client,
+                    &self.policy,
+                    &self.headers,
+                    &segment.url,
+                )
+                .await
+                {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::warn!("couldn't fetch HLS segment \"{}\": {e}", segment.url);
+                        continue;
+                    }
+                };
+
+                let data = match self.demuxer.push(&segment.url, &data) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::warn!("couldn't demux HLS segment \"{}\": {e}", segment.url);
+                        continue;
+                    }
+                };
+
+                // The receiver is gone once the track stopped
+                if self.tx.send(data).await.is_err() {
+                    return;
+                }
+            }
+
+            if playlist.ended {
+                return;
+            }
+
+            // Without new segments, retry sooner than a full target duration
+            let wait = if found_new {
+                playlist.target_duration
+            } else {
+                playlist.target_duration / 2
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {},
+                _ = self.tx.closed() => return,
+            }
+
+            match self.refresh().await {
+                Ok(v) => {
+                    playlist = v;
+                    refresh_failures = 0;
+                }
+                Err(e) => {
+                    tracing::warn!("couldn't refresh HLS playlist \"{}\": {e}", self.url);
+                    metrics::STREAM_RECONNECTS.inc();
+
+                    refresh_failures += 1;
+                    if refresh_failures >= MAX_REFRESH_FAILURES {
+                        tracing::error!("giving up on HLS stream \"{}\"", self.url);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn refresh(&self) -> Result<MediaPlaylist, Error> {
+        let body = fetch_text(&self.client, &self.policy, &self.headers, &self.url).await?;
+        MediaPlaylist::parse(&self.url, &body)
+    }
+}
+
+/// Audio bytes handed to symphonia, as they are received from the [`SegmentFetcher`]
+struct HlsStream {
+    rx: mpsc::Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl AsyncRead for HlsStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while self.pos >= self.buf.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(data)) => {
+                    self.buf = data;
+                    self.pos = 0;
+                }
+                // The stream ended, signal EOF
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let len = buf.remaining().min(self.buf.len() - self.pos);
+        buf.put_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for HlsStream {
+    fn start_seek(self: Pin<&mut Self>, _position: SeekFrom) -> std::io::Result<()> {
+        Err(IoErrorKind::Unsupported.into())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Err(IoErrorKind::Unsupported.into()))
+    }
+}
+
+#[async_trait]
+impl AsyncMediaSource for HlsStream {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    async fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+async fn fetch_bytes(
+    client: &reqwest::Client,
+    policy: &UrlPolicy,
+    headers: &HeaderMap,
+    url: &Url,
+) -> Result<Vec<u8>, Error> {
+    fetch_limited(client, policy, headers, url, MAX_SEGMENT_SIZE).await
+}
+
+async fn fetch_text(
+    client: &reqwest::Client,
+    policy: &UrlPolicy,
+    headers: &HeaderMap,
+    url: &Url,
+) -> Result<String, Error> {
+    let body = fetch_limited(client, policy, headers, url, MAX_PLAYLIST_SIZE).await?;
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Fetches the body at `url`, giving up once it's larger than `limit` or takes longer than
+/// [`REQUEST_TIMEOUT`].
+async fn fetch_limited(
+    client: &reqwest::Client,
+    policy: &UrlPolicy,
+    headers: &HeaderMap,
+    url: &Url,
+    limit: usize,
+) -> Result<Vec<u8>, Error> {
+    let fetch = async {
+        let mut res = policy.get(client, url, headers).await?;
+
+        if res.content_length().is_some_and(|v| v > limit as u64) {
+            return Err(format!("\"{url}\" is too large").into());
+        }
+
+        // The announced length can't be trusted (or is missing), so stop reading once it's too much
+        let mut body = Vec::new();
+        while let Some(chunk) = res.chunk().await? {
+            if body.len() + chunk.len() > limit {
+                return Err(format!("\"{url}\" is too large").into());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    };
+
+    tokio::time::timeout(REQUEST_TIMEOUT, fetch)
+        .await
+        .map_err(|_| format!("fetching \"{url}\" timed out"))?
+}
+
+#[derive(Debug, Clone)]
+struct Variant {
+    url: Url,
+    bandwidth: Option<u64>,
+    /// Whether the variant is known to contain nothing but audio
+    audio_only: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Segment {
+    url: Url,
+    sequence: u64,
+}
+
+#[derive(Debug, Clone)]
+struct MediaPlaylist {
+    target_duration: Duration,
+    segments: Vec<Segment>,
+    /// `#EXT-X-ENDLIST` is present, no segments will be added anymore
+    ended: bool,
+}
+
+/// The segments of a refreshed playlist which weren't fetched yet.
+///
+/// If the media sequence went back further than a stale playlist could explain (the encoder
+/// restarted or the counter was reset), playback continues at the live edge.
+fn new_segments(playlist: &MediaPlaylist, next_sequence: u64) -> &[Segment] {
+    let Some(last) = playlist.segments.last() else {
+        return &[];
+    };
+
+    let behind = next_sequence.saturating_sub(last.sequence + 1);
+    if behind > playlist.segments.len() as u64 {
+        let start = playlist.segments.len().saturating_sub(LIVE_START_SEGMENTS);
+        return &playlist.segments[start..];
+    }
+
+    let start = playlist
+        .segments
+        .iter()
+        .position(|v| v.sequence >= next_sequence)
+        .unwrap_or(playlist.segments.len());
+    &playlist.segments[start..]
+}
+
+fn is_master_playlist(body: &str) -> bool {
+    body.lines()
+        .any(|line| line.starts_with("#EXT-X-STREAM-INF"))
+}
+
+/// Parses the variants of a master playlist.
+///
+/// Audio renditions (`#EXT-X-MEDIA:TYPE=AUDIO`) are treated as audio-only variants.
+fn parse_master_playlist(base: &Url, body: &str) -> Result<Vec<Variant>, Error> {
+    check_header(body)?;
+
+    let mut variants = Vec::new();
+    let mut pending: Option<HashMap<String, String>> = None;
+
+    for line in body.lines().map(str::trim).filter(|v| !v.is_empty()) {
+        if let Some(attrs) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            pending = Some(parse_attributes(attrs));
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let attrs = parse_attributes(attrs);
+            if attrs.get("TYPE").map(String::as_str) != Some("AUDIO") {
+                continue;
+            }
+            let Some(uri) = attrs.get("URI") else {
+                continue;
+            };
+
+            variants.push(Variant {
+                url: base.join(uri)?,
+                bandwidth: None,
+                audio_only: true,
+            });
+        } else if !line.starts_with('#') {
+            let Some(attrs) = pending.take() else {
+                continue;
+            };
+
+            let audio_only = attrs
+                .get("CODECS")
+                .map(|codecs| codecs.split(',').all(is_audio_codec))
+                .unwrap_or(false);
+
+            variants.push(Variant {
+                url: base.join(line)?,
+                bandwidth: attrs.get("BANDWIDTH").and_then(|v| v.parse().ok()),
+                audio_only,
+            });
+        }
+    }
+
+    Ok(variants)
+}
+
+/// Picks the variant with the highest bandwidth up to `max_bitrate`, or the lowest one if none
+/// fits. Audio-only variants are preferred.
+fn choose_variant(variants: &[Variant], max_bitrate: Option<u64>) -> Option<&Variant> {
+    let audio_only = variants.iter().filter(|v| v.audio_only).collect::<Vec<_>>();
+    let candidates = if audio_only.is_empty() {
+        variants.iter().collect()
+    } else {
+        audio_only
+    };
+
+    let fits = |v: &&Variant| match (max_bitrate, v.bandwidth) {
+        (Some(max), Some(bandwidth)) => bandwidth <= max,
+        _ => true,
+    };
+
+    candidates
+        .iter()
+        .copied()
+        .filter(fits)
+        .max_by_key(|v| v.bandwidth)
+        .or_else(|| candidates.iter().copied().min_by_key(|v| v.bandwidth))
+}
+
+impl MediaPlaylist {
+    fn parse(base: &Url, body: &str) -> Result<Self, Error> {
+        check_header(body)?;
+
+        let mut target_duration = DEFAULT_TARGET_DURATION;
+        let mut sequence = 0;
+        let mut segments = Vec::new();
+        let mut ended = false;
+
+        for line in body.lines().map(str::trim).filter(|v| !v.is_empty()) {
+            if let Some(v) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+                target_duration = Duration::from_secs(v.parse()?);
+            } else if let Some(v) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+                sequence = v.parse()?;
+            } else if line == "#EXT-X-ENDLIST" {
+                ended = true;
+            } else if line.starts_with("#EXT-X-MAP") {
+                return Err("fragmented MP4 HLS streams aren't supported".into());
+            } else if let Some(attrs) = line.strip_prefix("#EXT-X-KEY:") {
+                let attrs = parse_attributes(attrs);
+                if attrs.get("METHOD").map(String::as_str) != Some("NONE") {
+                    return Err("encrypted HLS streams aren't supported".into());
+                }
+            } else if !line.starts_with('#') {
+                segments.push(Segment {
+                    url: base.join(line)?,
+                    sequence,
+                });
+                sequence += 1;
+            }
+        }
+
+        Ok(Self {
+            target_duration,
+            segments,
+            ended,
+        })
+    }
+}
+
+fn check_header(body: &str) -> Result<(), Error> {
+    if body.trim_start().starts_with("#EXTM3U") {
+        Ok(())
+    } else {
+        Err("not an HLS playlist (missing #EXTM3U)".into())
+    }
+}
+
+fn is_audio_codec(codec: &str) -> bool {
+    let codec = codec.trim();
+    codec.starts_with("mp4a") || codec == "mp3" || codec == "ac-3" || codec == "ec-3"
+}
+
+/// Parses an attribute list like `BANDWIDTH=128000,CODECS="mp4a.40.2"`
+fn parse_attributes(attrs: &str) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    let mut rest = attrs;
+
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, remaining) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let remaining = quoted.get(end + 1..).unwrap_or_default();
+            (&quoted[..end], remaining)
+        } else {
+            value.split_once(',').map_or((value, ""), |(v, r)| (v, r))
+        };
+
+        result.insert(key.trim().to_string(), value.to_string());
+        rest = remaining.trim_start_matches(',');
+    }
+
+    result
+}
+
+/// Format of the audio handed to symphonia
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum AudioFormat {
+    #[default]
+    Unknown,
+    Adts,
+    Mp3,
+}
+
+impl AudioFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Unknown | Self::Adts => "aac",
+            Self::Mp3 => "mp3",
+        }
+    }
+}
+
+/// Extracts the audio elementary stream from segments.
+///
+/// MPEG-TS segments are demuxed, other segments (packed audio) are passed through.
+#[derive(Debug, Default)]
+struct SegmentDemuxer {
+    format: AudioFormat,
+    pmt_pid: Option<u16>,
+    audio_pid: Option<u16>,
+}
+
+impl SegmentDemuxer {
+    fn push(&mut self, url: &Url, data: &[u8]) -> Result<Vec<u8>, Error> {
+        if !is_transport_stream(data) {
+            if self.format == AudioFormat::Unknown {
+                self.format = if url.path().to_lowercase().ends_with(".mp3") {
+                    AudioFormat::Mp3
+                } else {
+                    AudioFormat::Adts
+                };
+            }
+            return Ok(data.to_vec());
+        }
+
+        let mut audio = Vec::with_capacity(data.len());
+
+        for packet in data.chunks_exact(TS_PACKET_SIZE) {
+            if packet[0] != TS_SYNC_BYTE {
+                return Err("lost MPEG-TS sync".into());
+            }
+
+            let payload_start = packet[1] & 0x40 != 0;
+            let pid = u16::from_be_bytes([packet[1] & 0x1F, packet[2]]);
+            let adaptation_control = (packet[3] >> 4) & 0x03;
+
+            let payload = match adaptation_control {
+                0b01 => &packet[4..],
+                0b11 => {
+                    let adaptation_len = packet[4] as usize;
+                    match packet.get(5 + adaptation_len..) {
+                        Some(v) => v,
+                        None => continue,
+                    }
+                }
+                // No payload
+                _ => continue,
+            };
+
+            if pid == 0 {
+                self.pmt_pid = parse_pat(payload, payload_start).or(self.pmt_pid);
+            } else if Some(pid) == self.pmt_pid {
+                if let Some((audio_pid, format)) = parse_pmt(payload, payload_start) {
+                    self.audio_pid = Some(audio_pid);
+                    self.format = format;
+                }
+            } else if Some(pid) == self.audio_pid {
+                if payload_start {
+                    audio.extend_from_slice(strip_pes_header(payload).unwrap_or_default());
+                } else {
+                    audio.extend_from_slice(payload);
+                }
+            }
+        }
+
+        if self.audio_pid.is_none() {
+            return Err("MPEG-TS segment doesn't contain a supported audio stream".into());
+        }
+
+        Ok(audio)
+    }
+}
+
+fn is_transport_stream(data: &[u8]) -> bool {
+    data.len() >= TS_PACKET_SIZE
+        && data[0] == TS_SYNC_BYTE
+        && data
+            .get(TS_PACKET_SIZE)
+            .map(|v| *v == TS_SYNC_BYTE)
+            .unwrap_or(true)
+}
+
+/// Returns the section of a PSI payload (skipping the pointer field)
+fn psi_section(payload: &[u8], payload_start: bool) -> Option<&[u8]> {
+    if !payload_start {
+        return None;
+    }
+
+    let pointer = *payload.first()? as usize;
+    payload.get(1 + pointer..)
+}
+
+/// Returns the PID of the first program map table
+fn parse_pat(payload: &[u8], payload_start: bool) -> Option<u16> {
+    let section = psi_section(payload, payload_start)?;
+    let section_len = (u16::from_be_bytes([*section.get(1)? & 0x0F, *section.get(2)?])) as usize;
+    // Program entries follow the 8 byte header, the section ends with a 4 byte CRC
+    let programs = section.get(8..(3 + section_len).checked_sub(4)?)?;
+
+    programs.chunks_exact(4).find_map(|program| {
+        let number = u16::from_be_bytes([program[0], program[1]]);
+        // Program 0 points to the network information table
+        (number != 0).then(|| u16::from_be_bytes([program[2] & 0x1F, program[3]]))
+    })
+}
+
+/// Returns the PID and format of the first supported audio stream in a program map table
+fn parse_pmt(payload: &[u8], payload_start: bool) -> Option<(u16, AudioFormat)> {
+    let section = psi_section(payload, payload_start)?;
+    let section_len = (u16::from_be_bytes([*section.get(1)? & 0x0F, *section.get(2)?])) as usize;
+    let program_info_len =
+        (u16::from_be_bytes([*section.get(10)? & 0x0F, *section.get(11)?])) as usize;
+
+    let mut streams = section.get(12 + program_info_len..(3 + section_len).checked_sub(4)?)?;
+
+    while streams.len() >= 5 {
+        let stream_type = streams[0];
+        let pid = u16::from_be_bytes([streams[1] & 0x1F, streams[2]]);
+        let info_len = (u16::from_be_bytes([streams[3] & 0x0F, streams[4]])) as usize;
+
+        let format = match stream_type {
+            0x0F => Some(AudioFormat::Adts),
+            0x03 | 0x04 => Some(AudioFormat::Mp3),
+            _ => None,
+        };
+        if let Some(format) = format {
+            return Some((pid, format));
+        }
+
+        streams = streams.get(5 + info_len..)?;
+    }
+
+    None
+}
+
+/// Returns the elementary stream data of the first packet of a PES packet
+fn strip_pes_header(payload: &[u8]) -> Option<&[u8]> {
+    if payload.get(..3)? != [0x00, 0x00, 0x01] {
+        return None;
+    }
+
+    let header_len = *payload.get(8)? as usize;
+    payload.get(9 + header_len..)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://radio.example.com/live/index.m3u8").unwrap()
+    }
+
+    #[test]
+    fn attributes() {
+        let attrs = parse_attributes(
+            r#"BANDWIDTH=128000,CODECS="mp4a.40.2,avc1.4d401f",NAME="Main, HQ",RESOLUTION=640x360"#,
+        );
+
+        assert_eq!(attrs["BANDWIDTH"], "128000");
+        assert_eq!(attrs["CODECS"], "mp4a.40.2,avc1.4d401f");
+        assert_eq!(attrs["NAME"], "Main, HQ");
+        assert_eq!(attrs["RESOLUTION"], "640x360");
+        assert_eq!(attrs.len(), 4);
+
+        let attrs = parse_attributes(r#"TYPE=AUDIO,URI="audio/64k.m3u8""#);
+        assert_eq!(attrs["URI"], "audio/64k.m3u8");
+    }
+
+    #[test]
+    fn media_playlist() {
+        let playlist = MediaPlaylist::parse(
+            &base(),
+            "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:4711
+#EXT-X-KEY:METHOD=NONE
+#EXTINF:10.0,
+segment-4711.ts
+#EXTINF:10.0,
+/other/segment-4712.aac
+
+#EXTINF:10.0,
+https://cdn.example.net/segment-4713.ts
+#EXT-X-ENDLIST
+",
+        )
+        .unwrap();
+
+        assert_eq!(playlist.target_duration, Duration::from_secs(10));
+        assert!(playlist.ended);
+        let segments = playlist
+            .segments
+            .iter()
+            .map(|v| (v.sequence, v.url.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            segments,
+            [
+                (4711, "https://radio.example.com/live/segment-4711.ts"),
+                (4712, "https://radio.example.com/other/segment-4712.aac"),
+                (4713, "https://cdn.example.net/segment-4713.ts"),
+            ]
+        );
+
+        let playlist = MediaPlaylist::parse(&base(), "#EXTM3U\na.ts\n").unwrap();
+        assert_eq!(playlist.target_duration, DEFAULT_TARGET_DURATION);
+        assert_eq!(playlist.segments[0].sequence, 0);
+        assert!(!playlist.ended);
+    }
+
+    #[test]
+    fn unsupported_media_playlists() {
+        assert!(MediaPlaylist::parse(&base(), "a.ts\n").is_err());
+        assert!(MediaPlaylist::parse(
+            &base(),
+            "#EXTM3U\n#EXT-X-KEY:METHOD=AES-128,URI=\"key\"\na.ts\n"
+        )
+        .is_err());
+        assert!(
+            MediaPlaylist::parse(&base(), "#EXTM3U\n#EXT-X-MAP:URI=\"init.mp4\"\na.m4s\n").is_err()
+        );
+    }
+
+    fn playlist(sequences: std::ops::Range<u64>) -> MediaPlaylist {
+        MediaPlaylist {
+            target_duration: DEFAULT_TARGET_DURATION,
+            segments: sequences
+                .map(|sequence| Segment {
+                    url: base().join(&format!("{sequence}.ts")).unwrap(),
+                    sequence,
+                })
+                .collect(),
+            ended: false,
+        }
+    }
+
+    fn sequences(segments: &[Segment]) -> Vec<u64> {
+        segments.iter().map(|v| v.sequence).collect()
+    }
+
+    #[test]
+    fn media_sequence() {
+        // The window moved on by two segments
+        assert_eq!(sequences(new_segments(&playlist(12..18), 16)), [16, 17]);
+        // Nothing new yet, or a slightly stale playlist from a CDN
+        assert!(new_segments(&playlist(10..16), 16).is_empty());
+        assert!(new_segments(&playlist(8..14), 16).is_empty());
+        // The next segments aren't in the window anymore, they're skipped
+        assert_eq!(sequences(new_segments(&playlist(20..23), 16)), [20, 21, 22]);
+
+        // The sequence started over, e.g. because the encoder restarted
+        assert_eq!(sequences(new_segments(&playlist(0..6), 4711)), [3, 4, 5]);
+        assert_eq!(sequences(new_segments(&playlist(0..2), u64::MAX)), [0, 1]);
+
+        assert!(new_segments(&playlist(0..0), 16).is_empty());
+    }
+
+    #[test]
+    fn master_playlist() {
+        let body = r#"#EXTM3U
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID="subs",NAME="English",URI="subs.m3u8"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aac",NAME="Radio, stereo",URI="audio/aac.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS="avc1.4d401f,mp4a.40.2"
+video/800k.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS="mp4a.40.5"
+https://cdn.example.net/64k.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=192000,CODECS="mp4a.40.2"
+192k.m3u8
+"#;
+        assert!(is_master_playlist(body));
+        assert!(!is_master_playlist("#EXTM3U\n#EXTINF:10,\na.ts\n"));
+
+        let variants = parse_master_playlist(&base(), body).unwrap();
+        let variants = variants
+            .iter()
+            .map(|v| (v.url.as_str(), v.bandwidth, v.audio_only))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            variants,
+            [
+                ("https://radio.example.com/live/audio/aac.m3u8", None, true),
+                (
+                    "https://radio.example.com/live/video/800k.m3u8",
+                    Some(800_000),
+                    false
+                ),
+                ("https://cdn.example.net/64k.m3u8", Some(64_000), true),
+                (
+                    "https://radio.example.com/live/192k.m3u8",
+                    Some(192_000),
+                    true
+                ),
+            ]
+        );
+    }
+
+    fn variant(name: &str, bandwidth: Option<u64>, audio_only: bool) -> Variant {
+        Variant {
+            url: base().join(name).unwrap(),
+            bandwidth,
+            audio_only,
+        }
+    }
+
+    #[test]
+    fn variant_choice() {
+        let chosen = |variants: &[Variant], max_bitrate| {
+            choose_variant(variants, max_bitrate).map(|v| v.url.path().to_string())
+        };
+
+        let variants = [
+            variant("video", Some(800_000), false),
+            variant("low", Some(64_000), true),
+            variant("high", Some(192_000), true),
+        ];
+        // Audio-only variants win, even with a lower bandwidth
+        assert_eq!(chosen(&variants, None).as_deref(), Some("/live/high"));
+        assert_eq!(
+            chosen(&variants, Some(128_000)).as_deref(),
+            Some("/live/low")
+        );
+        // Nothing fits, so the lowest one is used
+        assert_eq!(
+            chosen(&variants, Some(32_000)).as_deref(),
+            Some("/live/low")
+        );
+
+        let variants = [
+            variant("sd", Some(400_000), false),
+            variant("hd", Some(2_000_000), false),
+        ];
+        assert_eq!(chosen(&variants, None).as_deref(), Some("/live/hd"));
+        assert_eq!(
+            chosen(&variants, Some(1_000_000)).as_deref(),
+            Some("/live/sd")
+        );
+
+        assert_eq!(chosen(&[], None), None);
+    }
+
+    const PMT_PID: u16 = 0x100;
+    const AUDIO_PID: u16 = 0x101;
+
+    /// A 188 byte TS packet, padded with an adaptation field
+    fn ts_packet(pid: u16, payload_start: bool, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![
+            TS_SYNC_BYTE,
+            (payload_start as u8) << 6 | (pid >> 8) as u8,
+            pid as u8,
+            0x30, // adaptation field and payload
+        ];
+        let stuffing = TS_PACKET_SIZE - 5 - payload.len();
+        packet.push(stuffing as u8);
+        if stuffing > 0 {
+            packet.push(0x00); // adaptation field flags
+            packet.resize(5 + stuffing, 0xFF);
+        }
+        packet.extend_from_slice(payload);
+
+        assert_eq!(packet.len(), TS_PACKET_SIZE);
+        packet
+    }
+
+    /// A PSI section with a pointer field and a dummy CRC
+    fn psi(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let len = body.len() + 4;
+        let mut payload = vec![0x00, table_id, 0xB0 | (len >> 8) as u8, len as u8];
+        payload.extend_from_slice(body);
+        payload.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        payload
+    }
+
+    fn pat() -> Vec<u8> {
+        psi(
+            0x00,
+            &[
+                0x00,
+                0x01,
+                0xC1,
+                0x00,
+                0x00, // transport stream ID, version, section numbers
+                0x00,
+                0x00,
+                0xE0,
+                0x10, // program 0 (network information table)
+                0x00,
+                0x01,
+                0xE0 | (PMT_PID >> 8) as u8,
+                PMT_PID as u8,
+            ],
+        )
+    }
+
+    fn pmt(stream_type: u8) -> Vec<u8> {
+        psi(
+            0x02,
+            &[
+                0x00,
+                0x01,
+                0xC1,
+                0x00,
+                0x00, // program number, version, section numbers
+                0xE1,
+                0x01, // PCR PID
+                0xF0,
+                0x00, // no program info
+                0x06,
+                0xE1,
+                0x02,
+                0xF0,
+                0x00, // private data (e.g. ID3), skipped
+                stream_type,
+                0xE0 | (AUDIO_PID >> 8) as u8,
+                AUDIO_PID as u8,
+                0xF0,
+                0x00,
+            ],
+        )
+    }
+
+    fn pes(data: &[u8]) -> Vec<u8> {
+        let mut payload = vec![
+            0x00, 0x00, 0x01, 0xC0, // start code, audio stream
+            0x00, 0x00, // unbounded length
+            0x80, 0x80, 0x05, // PTS only, 5 bytes of header data
+            0x21, 0x00, 0x01, 0x00, 0x01,
+        ];
+        payload.extend_from_slice(data);
+        payload
+    }
+
+    #[test]
+    fn transport_stream() {
+        let url = base().join("segment-1.ts").unwrap();
+
+        let mut segment = Vec::new();
+        segment.extend(ts_packet(0, true, &pat()));
+        segment.extend(ts_packet(PMT_PID, true, &pmt(0x0F)));
+        segment.extend(ts_packet(AUDIO_PID, true, &pes(b"first frame ")));
+        // Other streams are ignored
+        segment.extend(ts_packet(0x102, true, b"ID3 tag"));
+        segment.extend(ts_packet(AUDIO_PID, false, b"continued"));
+        assert!(is_transport_stream(&segment));
+
+        let mut demuxer = SegmentDemuxer::default();
+        let audio = demuxer.push(&url, &segment).unwrap();
+        assert_eq!(audio, b"first frame continued");
+        assert_eq!(demuxer.format, AudioFormat::Adts);
+
+        // The next segment can rely on the tables of the previous one
+        let segment = ts_packet(AUDIO_PID, true, &pes(b"second"));
+        assert_eq!(demuxer.push(&url, &segment).unwrap(), b"second");
+    }
+
+    #[test]
+    fn transport_stream_formats() {
+        let url = base().join("segment-1.ts").unwrap();
+
+        let mut segment = ts_packet(0, true, &pat());
+        segment.extend(ts_packet(PMT_PID, true, &pmt(0x03)));
+        let mut demuxer = SegmentDemuxer::default();
+        demuxer.push(&url, &segment).unwrap();
+        assert_eq!(demuxer.format, AudioFormat::Mp3);
+
+        // H.264 only
+        let mut segment = ts_packet(0, true, &pat());
+        segment.extend(ts_packet(PMT_PID, true, &pmt(0x1B)));
+        assert!(SegmentDemuxer::default().push(&url, &segment).is_err());
+
+        let mut segment = ts_packet(0, true, &pat());
+        segment.extend(ts_packet(PMT_PID, true, &pmt(0x0F)));
+        segment.extend(ts_packet(AUDIO_PID, true, &pes(b"lost")));
+        segment[2 * TS_PACKET_SIZE] = 0x00;
+        assert!(SegmentDemuxer::default().push(&url, &segment).is_err());
+    }
+
+    #[test]
+    fn packed_audio() {
+        let mut demuxer = SegmentDemuxer::default();
+        let url = base().join("segment-1.mp3").unwrap();
+        assert_eq!(
+            demuxer.push(&url, b"ID3 and frames").unwrap(),
+            b"ID3 and frames"
+        );
+        assert_eq!(demuxer.format, AudioFormat::Mp3);
+
+        let mut demuxer = SegmentDemuxer::default();
+        let url = base().join("segment-1.aac").unwrap();
+        demuxer.push(&url, &[0xFF, 0xF1, 0x50]).unwrap();
+        assert_eq!(demuxer.format, AudioFormat::Adts);
+    }
+
+    #[tokio::test]
+    async fn fetch_limit() {
+        use axum::body::Body;
+        use axum::routing::get;
+        use axum::Router;
+
+        // Chunked, so the size isn't known up front
+        let chunked = || async {
+            let chunks = (0..5).map(|_| Ok::<_, Error>(vec![b'#'; 1024 * 1024]));
+            Body::from_stream(futures::stream::iter(chunks))
+        };
+        let router = Router::new()
+            .route("/small.m3u8", get(|| async { "#EXTM3U\n" }))
+            .route(
+                "/announced.m3u8",
+                get(|| async { vec![b'#'; MAX_PLAYLIST_SIZE + 1] }),
+            )
+            .route("/chunked.m3u8", get(chunked));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let client = reqwest::Client::new();
+        let policy = UrlPolicy::allow_all();
+        let fetch = |path: &str| {
+            let url = Url::parse(&format!("http://{addr}{path}")).unwrap();
+            let (client, policy) = (client.clone(), policy.clone());
+            async move { fetch_text(&client, &policy, &HeaderMap::new(), &url).await }
+        };
+
+        assert_eq!(fetch("/small.m3u8").await.unwrap(), "#EXTM3U\n");
+        for path in ["/announced.m3u8", "/chunked.m3u8"] {
+            let e = fetch(path).await.unwrap_err().to_string();
+            assert!(e.contains("too large"), "{e}");
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Control API, e.g. for remote controls which don't go through Discord
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use poise::serenity_prelude::{ChannelId, ChannelType, GuildId};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use songbird::tracks::PlayMode;
+use tokio::sync::broadcast;
+use url::Url;
+
+use crate::database::actions::volume_get_or_insert_default;
+use crate::discord::events::GuildEvent;
+use crate::discord::player::{
+    join_channel, play_only, set_volume, stop_all, webradio_input, WebradioInput,
+    INITIAL_DEFAULT_VOLUME,
+};
+use crate::discord::tracks::TrackInfo;
+use crate::discord::Error;
+use crate::http::{request_token, tokens_match, AppState, TokenQuery};
+
+/// An error response like `{"error": "..."}`
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl<E: Into<Error>> From<E> for ApiError {
+    fn from(value: E) -> Self {
+        let e = value.into();
+        tracing::error!("error in API request: {e}");
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+/// Rejects requests without the API token, given either as `Authorization: Bearer <token>` or
+/// via the `token` query parameter (browsers can't set headers for WebSockets)
+pub async fn require_token(
+    State(state): State<AppState>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = match (
+        &state.data.config.get().api_token,
+        request_token(query, &headers),
+    ) {
+        (Some(expected), Some(token)) => tokens_match(expected.expose(), &token),
+        _ => false,
+    };
+
+    if !authorized {
+        return ApiError::new(StatusCode::UNAUTHORIZED, "invalid or missing token").into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Playback state of a guild
+#[derive(Debug, Serialize)]
+pub struct GuildState {
+    guild_id: GuildId,
+    name: String,
+    /// Voice channel the bot is connected to
+    channel_id: Option<ChannelId>,
+    channel_name: Option<String>,
+    url: Option<String>,
+    title: Option<String>,
+    /// 0-100, can go above 100 if the maximum volume allows it
+    volume: i32,
+    playing: bool,
+}
+
+/// `GET /api/guilds`: State of all guilds the bot is in
+pub async fn guilds(State(state): State<AppState>) -> Result<Json<Vec<GuildState>>, ApiError> {
+    let mut guilds = Vec::new();
+    for guild_id in state.cache.guilds() {
+        guilds.push(guild_state(&state, guild_id).await?);
+    }
+
+    Ok(Json(guilds))
+}
+
+/// `GET /api/guilds/<id>`
+pub async fn guild(
+    State(state): State<AppState>,
+    Path(guild_id): Path<u64>,
+) -> Result<Json<GuildState>, ApiError> {
+    let guild_id = known_guild(&state, guild_id)?;
+
+    Ok(Json(guild_state(&state, guild_id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlayRequest {
+    url: String,
+    /// Voice channel to join, defaults to the one the bot is connected to
+    channel_id: Option<ChannelId>,
+}
+
+/// `POST /api/guilds/<id>/play`: Plays a URL, replacing whatever is playing
+pub async fn play(
+    State(state): State<AppState>,
+    Path(guild_id): Path<u64>,
+    Json(body): Json<PlayRequest>,
+) -> Result<Json<GuildState>, ApiError> {
+    let guild_id = known_guild(&state, guild_id)?;
+
+    let Ok(url) = Url::parse(&body.url) else {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid URL"));
+    };
+
+    let (input, track_info) = match webradio_input(&state.data, guild_id, &url, None).await? {
+        WebradioInput::Playable(input, track_info) => (input, track_info),
+        WebradioInput::Unsupported(reason) => {
+            return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, reason));
+        }
+        WebradioInput::Blocked(blocked) => {
+            return Err(ApiError::new(StatusCode::FORBIDDEN, blocked.to_string()));
+        }
+    };
+
+    let voice_handler = match body.channel_id {
+        Some(channel_id) => {
+            let is_voice_channel = state
+                .cache
+                .guild(guild_id)
+                .and_then(|guild| guild.channels.get(&channel_id).map(|v| v.kind))
+                .is_some_and(|kind| matches!(kind, ChannelType::Voice | ChannelType::Stage));
+            if !is_voice_channel {
+                return Err(ApiError::new(
+                    StatusCode::NOT_FOUND,
+                    "unknown voice channel",
+                ));
+            }
+
+            join_channel(&state.data, &state.songbird, guild_id, channel_id).await?
+        }
+        None => match state.songbird.get(guild_id) {
+            Some(handler) if handler.lock().await.current_connection().is_some() => handler,
+            _ => {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    "not connected to a voice channel, pass a channel_id",
+                ))
+            }
+        },
+    };
+
+    play_only(&state.data, guild_id, &voice_handler, input, track_info).await?;
+
+    Ok(Json(guild_state(&state, guild_id).await?))
+}
+
+/// `POST /api/guilds/<id>/stop`: Stops the current track and clears the queue
+pub async fn stop(
+    State(state): State<AppState>,
+    Path(guild_id): Path<u64>,
+) -> Result<Json<GuildState>, ApiError> {
+    let guild_id = known_guild(&state, guild_id)?;
+
+    if let Some(handler) = state.songbird.get(guild_id) {
+        stop_all(&state.data, guild_id, &mut *handler.lock().await).await;
+    }
+
+    Ok(Json(guild_state(&state, guild_id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VolumeRequest {
+    volume: u32,
+}
+
+/// `PUT /api/guilds/<id>/volume`
+pub async fn volume(
+    State(state): State<AppState>,
+    Path(guild_id): Path<u64>,
+    Json(body): Json<VolumeRequest>,
+) -> Result<Json<GuildState>, ApiError> {
+    let guild_id = known_guild(&state, guild_id)?;
+
+    let max_volume = state.data.config.get().max_volume;
+    if body.volume > max_volume {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("volume is higher than the maximum ({max_volume})"),
+        ));
+    }
+
+    set_volume(&state.data, &state.songbird, guild_id, body.volume).await?;
+
+    Ok(Json(guild_state(&state, guild_id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    /// Only send events of this guild
+    guild_id: Option<u64>,
+}
+
+/// `GET /api/events`: WebSocket sending every [`GuildEvent`] as JSON text message
+pub async fn events(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let rx = state.data.events.subscribe();
+    let guild_id = query.guild_id.filter(|v| *v != 0).map(GuildId::new);
+
+    ws.on_upgrade(move |socket| forward_events(socket, rx, guild_id))
+}
+
+async fn forward_events(
+    mut socket: WebSocket,
+    mut rx: broadcast::Receiver<GuildEvent>,
+    guild_id: Option<GuildId>,
+) {
+    loop {
+        tokio::select! {
+            event = rx.recv() => {
+                let event = match event {
+                    Ok(v) => v,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::debug!("event socket lagged behind by {n} events");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+
+                if guild_id.is_some_and(|v| v != event.guild_id()) {
+                    continue;
+                }
+
+                let Ok(text) = serde_json::to_string(&event) else {
+                    continue;
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    return;
+                }
+            }
+            message = socket.recv() => match message {
+                // Clients aren't expected to send anything, but pings are answered by axum
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Guilds the bot isn't in are treated as not found
+fn known_guild(state: &AppState, guild_id: u64) -> Result<GuildId, ApiError> {
+    let guild_id = (guild_id != 0).then(|| GuildId::new(guild_id));
+
+    match guild_id {
+        Some(guild_id) if state.cache.guild(guild_id).is_some() => Ok(guild_id),
+        _ => Err(ApiError::new(StatusCode::NOT_FOUND, "unknown guild")),
+    }
+}
+
+async fn guild_state(state: &AppState, guild_id: GuildId) -> Result<GuildState, Error> {
+    let channel_id = match state.songbird.get(guild_id) {
+        Some(handler) => handler
+            .lock()
+            .await
+            .current_channel()
+            .map(|v| ChannelId::new(v.0.get())),
+        None => None,
+    };
+
+    let (name, channel_name) = match state.cache.guild(guild_id) {
+        Some(guild) => (
+            guild.name.clone(),
+            channel_id
+                .and_then(|v| guild.channels.get(&v))
+                .map(|v| v.name.clone()),
+        ),
+        None => (String::new(), None),
+    };
+
+    let (track_info, playing) = match state.data.current_track(&state.songbird, guild_id).await {
+        Some(track_handle) => {
+            let playing = match track_handle.get_info().await {
+                Ok(info) => matches!(info.playing, PlayMode::Play),
+                Err(_) => false,
+            };
+            (TrackInfo::of(&track_handle).await, playing)
+        }
+        None => (None, false),
+    };
+
+    let mut conn = state.data.database.get_connection().await?;
+    let volume = volume_get_or_insert_default(&mut conn, guild_id, INITIAL_DEFAULT_VOLUME).await?;
+
+    Ok(GuildState {
+        guild_id,
+        name,
+        channel_id,
+        channel_name,
+        url: track_info.as_ref().map(|v| v.url.clone()),
+        title: track_info.and_then(|v| v.title),
+        volume,
+        playing,
+    })
+}
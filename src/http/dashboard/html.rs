@@ -0,0 +1,94 @@
+//! Bits for rendering the pages of the dashboard
+
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use url::Url;
+
+const STYLE: &str = "
+body { font-family: sans-serif; max-width: 60rem; margin: 2rem auto; padding: 0 1rem; color: #222; }
+header { display: flex; justify-content: space-between; align-items: center; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 1rem; }
+th, td { text-align: left; padding: 0.3rem 0.5rem; border-bottom: 1px solid #ddd; }
+form.inline { display: inline; }
+section { margin-bottom: 2rem; }
+.muted { color: #777; }
+";
+
+/// Escapes text for use in HTML content and attribute values
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Links `text` to `url`, or just shows it if `url` isn't a web URL (e.g. a file of the library)
+pub fn link(url: &str, text: &str) -> String {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {
+            format!(r#"<a href="{}">{}</a>"#, escape(url), escape(text))
+        }
+        _ => escape(text),
+    }
+}
+
+/// Wraps `body` (which has to be escaped already) into a full page
+pub fn page(title: &str, username: Option<&str>, body: &str) -> Html<String> {
+    let user = match username {
+        Some(username) => format!(
+            r#"<span>{} <form class="inline" method="post" action="/dashboard/logout"><button>Log out</button></form></span>"#,
+            escape(username)
+        ),
+        None => String::new(),
+    };
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title} - discomfort.fm</title>
+<style>{STYLE}</style>
+</head>
+<body>
+<header><h1><a href="/dashboard">discomfort.fm</a></h1>{user}</header>
+{body}
+</body>
+</html>"#,
+        title = escape(title),
+    ))
+}
+
+/// A page showing an error message
+pub fn error_page(status: StatusCode, message: &str) -> Response {
+    let body = format!(
+        r#"<p>{}</p><p><a href="javascript:history.back()">Back</a></p>"#,
+        escape(message)
+    );
+
+    (status, page("Error", None, &body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links() {
+        assert_eq!(
+            link("https://radio.example.com/?a=1&b=2", "Radio"),
+            r#"<a href="https://radio.example.com/?a=1&amp;b=2">Radio</a>"#
+        );
+        assert_eq!(link("javascript:alert(1)", "<b>"), "&lt;b&gt;");
+        assert_eq!(link("/music/track.mp3", "track.mp3"), "track.mp3");
+    }
+}
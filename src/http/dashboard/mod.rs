@@ -0,0 +1,929 @@
+//! Server-rendered dashboard for guild admins, using Discord for logging in
+
+mod html;
+pub mod oauth;
+
+use axum::extract::{Path, Query, State};
+use axum::http::header::{COOKIE, SET_COOKIE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use axum::{Form, Router};
+use chrono::NaiveTime;
+use poise::serenity_prelude::{ChannelId, ChannelType, GuildId};
+use serde::Deserialize;
+use url::Url;
+
+use crate::config::OAuthConfig;
+use crate::database::actions::{
+    favorite_delete, favorite_insert, favorites_get_by_guild, history_get_by_guild,
+    schedule_delete, schedule_insert, schedules_get_by_guild, volume_get_or_insert_default,
+};
+use crate::discord::player::{set_volume, INITIAL_DEFAULT_VOLUME};
+use crate::discord::Error;
+use crate::http::AppState;
+use crate::schedule::WEEKDAYS;
+use html::{error_page, escape, link, page};
+use oauth::Session;
+
+const SESSION_COOKIE: &str = "discomfort_session";
+const STATE_COOKIE: &str = "discomfort_oauth_state";
+/// Number of entries shown in the history of a guild
+const HISTORY_ENTRIES: u32 = 25;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(index))
+        .route("/login", get(login))
+        .route("/callback", get(callback))
+        .route("/logout", post(logout))
+        .route("/guilds/:guild_id", get(guild))
+        .route("/guilds/:guild_id/settings", post(update_settings))
+        .route("/guilds/:guild_id/favorites", post(add_favorite))
+        .route(
+            "/guilds/:guild_id/favorites/:id/delete",
+            post(delete_favorite),
+        )
+        .route("/guilds/:guild_id/schedules", post(add_schedule))
+        .route(
+            "/guilds/:guild_id/schedules/:id/delete",
+            post(delete_schedule),
+        )
+}
+
+/// Renders an error page instead of the requested one
+pub struct PageError(Box<Response>);
+
+impl PageError {
+    fn new(response: Response) -> Self {
+        Self(Box::new(response))
+    }
+}
+
+impl<E: Into<Error>> From<E> for PageError {
+    fn from(value: E) -> Self {
+        let e = value.into();
+        tracing::error!("error in dashboard request: {e}");
+        Self::new(error_page(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Something went wrong, try again later",
+        ))
+    }
+}
+
+impl IntoResponse for PageError {
+    fn into_response(self) -> Response {
+        *self.0
+    }
+}
+
+type PageResult = Result<Response, PageError>;
+
+/// `GET /dashboard`: The guilds the user can manage
+async fn index(State(state): State<AppState>, headers: HeaderMap) -> PageResult {
+    let Some((_, session)) = session(&state, &headers) else {
+        let body = r#"<p>Manage favorites, schedules and settings of your servers.</p>
+<p><a href="/dashboard/login">Log in with Discord</a></p>"#;
+        return Ok(page("Dashboard", None, body).into_response());
+    };
+
+    let guilds = session
+        .guilds
+        .iter()
+        .filter_map(|guild_id| {
+            let guild = state.cache.guild(*guild_id)?;
+            Some(format!(
+                r#"<li><a href="/dashboard/guilds/{}">{}</a></li>"#,
+                guild_id,
+                escape(&guild.name)
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    let body = if guilds.is_empty() {
+        "<p>I'm not in any server you can manage.</p>".to_string()
+    } else {
+        format!("<h2>Your servers</h2><ul>{}</ul>", guilds.join(""))
+    };
+
+    Ok(page("Dashboard", Some(&session.username), &body).into_response())
+}
+
+/// `GET /dashboard/login`: Redirects to Discord
+async fn login(State(state): State<AppState>) -> PageResult {
+    let oauth = oauth_config(&state)?;
+    let oauth_state = oauth::random_token();
+
+    let url = oauth::authorize_url(&oauth, &redirect_uri(&state), &oauth_state);
+
+    Ok((
+        [(SET_COOKIE, cookie(&state, STATE_COOKIE, &oauth_state, 600))],
+        Redirect::to(&url),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+}
+
+/// `GET /dashboard/callback`: Discord redirects here after the user authorized the login
+async fn callback(
+    State(state): State<AppState>,
+    Query(query): Query<CallbackQuery>,
+    headers: HeaderMap,
+) -> PageResult {
+    let oauth = oauth_config(&state)?;
+
+    // The state has to match the cookie set in `login`, so nobody can log others into their account
+    let expected_state = cookie_value(&headers, STATE_COOKIE);
+    let (Some(code), Some(oauth_state), Some(expected_state)) =
+        (query.code, query.state, expected_state)
+    else {
+        return Err(PageError::new(error_page(
+            StatusCode::BAD_REQUEST,
+            "The login was cancelled or is invalid",
+        )));
+    };
+    if oauth_state != expected_state {
+        return Err(PageError::new(error_page(
+            StatusCode::BAD_REQUEST,
+            "The login expired, try again",
+        )));
+    }
+
+    let (user, guilds) = match oauth::login(
+        &state.data.outbound.client(),
+        &oauth,
+        &redirect_uri(&state),
+        &code,
+    )
+    .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("dashboard login failed: {e}");
+            return Err(PageError::new(error_page(
+                StatusCode::BAD_GATEWAY,
+                "Discord didn't accept the login, try again",
+            )));
+        }
+    };
+
+    tracing::info!("user {} logged into the dashboard", user.id);
+
+    let username = user.global_name.unwrap_or(user.username);
+    let session_id = state.sessions.create(user.id, username, guilds);
+
+    let mut res = Redirect::to("/dashboard").into_response();
+    res.headers_mut().append(
+        SET_COOKIE,
+        cookie(&state, SESSION_COOKIE, &session_id, 7 * 24 * 60 * 60)
+            .parse()
+            .expect("valid cookie"),
+    );
+    res.headers_mut().append(
+        SET_COOKIE,
+        cookie(&state, STATE_COOKIE, "", 0)
+            .parse()
+            .expect("valid cookie"),
+    );
+
+    Ok(res)
+}
+
+/// `POST /dashboard/logout`
+async fn logout(State(state): State<AppState>, headers: HeaderMap) -> PageResult {
+    if let Some((session_id, _)) = session(&state, &headers) {
+        state.sessions.remove(&session_id);
+    }
+
+    Ok((
+        [(SET_COOKIE, cookie(&state, SESSION_COOKIE, "", 0))],
+        Redirect::to("/dashboard"),
+    )
+        .into_response())
+}
+
+/// `GET /dashboard/guilds/<id>`: Settings, favorites, schedules and history of a guild
+async fn guild(
+    State(state): State<AppState>,
+    Path(guild_id): Path<u64>,
+    headers: HeaderMap,
+) -> PageResult {
+    let (session, guild_id) = authorize(&state, &headers, guild_id)?;
+
+    let (guild_name, voice_channels) = {
+        let Some(guild) = state.cache.guild(guild_id) else {
+            return Err(not_found());
+        };
+
+        let mut channels = guild
+            .channels
+            .values()
+            .filter(|v| matches!(v.kind, ChannelType::Voice | ChannelType::Stage))
+            .map(|v| (v.id, v.name.clone(), v.position))
+            .collect::<Vec<_>>();
+        channels.sort_by_key(|v| v.2);
+
+        (guild.name.clone(), channels)
+    };
+    let channel_name = |channel_id: ChannelId| {
+        voice_channels
+            .iter()
+            .find(|v| v.0 == channel_id)
+            .map(|v| v.1.clone())
+            .unwrap_or_else(|| channel_id.to_string())
+    };
+
+    let mut conn = state.data.database.get_connection().await?;
+    let volume = volume_get_or_insert_default(&mut conn, guild_id, INITIAL_DEFAULT_VOLUME).await?;
+    let favorites = favorites_get_by_guild(&mut conn, guild_id).await?;
+    let schedules = schedules_get_by_guild(&mut conn, guild_id).await?;
+    let history = history_get_by_guild(&mut conn, guild_id, HISTORY_ENTRIES).await?;
+    drop(conn);
+
+    let base = format!("/dashboard/guilds/{guild_id}");
+    let mut body = format!("<h2>{}</h2>", escape(&guild_name));
+
+    body.push_str(&format!(
+        r#"<section><h3>Settings</h3>
+<form method="post" action="{base}/settings">
+<label>Volume <input type="number" name="volume" min="0" max="{max}" value="{volume}"></label>
+<button>Save</button>
+</form></section>"#,
+        max = state.data.config.get().max_volume,
+    ));
+
+    body.push_str("<section><h3>Favorites</h3>");
+    if favorites.is_empty() {
+        body.push_str(r#"<p class="muted">No favorites yet</p>"#);
+    } else {
+        body.push_str("<table><tr><th>Title</th><th>URL</th><th>Added by</th><th></th></tr>");
+        for favorite in &favorites {
+            let added_by = state
+                .cache
+                .user(favorite.user_id)
+                .map(|v| v.name.clone())
+                .unwrap_or_else(|| favorite.user_id.to_string());
+
+            body.push_str(&format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td><form class="inline" method="post" action="{base}/favorites/{}/delete"><button>Delete</button></form></td></tr>"#,
+                escape(&favorite.title),
+                link(&favorite.uri, &favorite.uri),
+                escape(&added_by),
+                escape(&favorite.id),
+            ));
+        }
+        body.push_str("</table>");
+    }
+    body.push_str(&format!(
+        r#"<form method="post" action="{base}/favorites">
+<input name="title" placeholder="Title" required maxlength="100">
+<input name="uri" type="url" placeholder="https://..." required>
+<button>Add favorite</button>
+</form></section>"#
+    ));
+
+    body.push_str("<section><h3>Schedules</h3>");
+    if schedules.is_empty() {
+        body.push_str(r#"<p class="muted">No schedules yet</p>"#);
+    } else {
+        body.push_str(
+            "<table><tr><th>Time</th><th>Days</th><th>Channel</th><th>URL</th><th></th></tr>",
+        );
+        for schedule in &schedules {
+            let days = WEEKDAYS
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| schedule.weekdays & (1 << i) != 0)
+                .map(|(_, v)| *v)
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            body.push_str(&format!(
+                r#"<tr><td>{}</td><td>{days}</td><td>{}</td><td>{}</td><td><form class="inline" method="post" action="{base}/schedules/{}/delete"><button>Delete</button></form></td></tr>"#,
+                schedule.time.format("%H:%M"),
+                escape(&channel_name(schedule.channel_id)),
+                link(&schedule.url, &schedule.url),
+                escape(&schedule.id),
+            ));
+        }
+        body.push_str("</table>");
+    }
+    let channel_options = voice_channels
+        .iter()
+        .map(|(id, name, _)| format!(r#"<option value="{id}">{}</option>"#, escape(name)))
+        .collect::<String>();
+    let weekday_inputs = WEEKDAYS
+        .iter()
+        .map(|v| {
+            format!(
+                r#"<label><input type="checkbox" name="{}" checked>{v}</label> "#,
+                v.to_lowercase()
+            )
+        })
+        .collect::<String>();
+    body.push_str(&format!(
+        r#"<form method="post" action="{base}/schedules">
+<input name="time" type="time" required>
+<select name="channel_id" required>{channel_options}</select>
+<input name="url" type="url" placeholder="https://..." required>
+<br>{weekday_inputs}
+<button>Add schedule</button>
+</form>
+<p class="muted">Times are in the local time of the bot. At the given time, the bot joins the channel and starts playing.</p>
+</section>"#
+    ));
+
+    body.push_str("<section><h3>History</h3>");
+    if history.is_empty() {
+        body.push_str(r#"<p class="muted">Nothing was played yet</p>"#);
+    } else {
+        body.push_str("<table><tr><th>Played at (UTC)</th><th>Title</th></tr>");
+        for entry in &history {
+            body.push_str(&format!(
+                r#"<tr><td>{}</td><td>{}</td></tr>"#,
+                entry.played_at.format("%Y-%m-%d %H:%M"),
+                link(&entry.url, entry.display_name()),
+            ));
+        }
+        body.push_str("</table>");
+    }
+    body.push_str("</section>");
+
+    Ok(page(&guild_name, Some(&session.username), &body).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct SettingsForm {
+    volume: u32,
+}
+
+/// `POST /dashboard/guilds/<id>/settings`
+async fn update_settings(
+    State(state): State<AppState>,
+    Path(guild_id): Path<u64>,
+    headers: HeaderMap,
+    Form(form): Form<SettingsForm>,
+) -> PageResult {
+    let (_, guild_id) = authorize(&state, &headers, guild_id)?;
+
+    let max_volume = state.data.config.get().max_volume;
+    if form.volume > max_volume {
+        return Err(bad_request(&format!(
+            "The volume can't be higher than {max_volume}"
+        )));
+    }
+
+    set_volume(&state.data, &state.songbird, guild_id, form.volume).await?;
+
+    Ok(back_to(guild_id))
+}
+
+#[derive(Debug, Deserialize)]
+struct FavoriteForm {
+    title: String,
+    uri: String,
+}
+
+/// `POST /dashboard/guilds/<id>/favorites`
+async fn add_favorite(
+    State(state): State<AppState>,
+    Path(guild_id): Path<u64>,
+    headers: HeaderMap,
+    Form(form): Form<FavoriteForm>,
+) -> PageResult {
+    let (session, guild_id) = authorize(&state, &headers, guild_id)?;
+
+    let title = form.title.trim();
+    if title.is_empty() || title.chars().count() > 100 {
+        return Err(bad_request(
+            "The title has to be between 1 and 100 characters",
+        ));
+    }
+    let uri = checked_url(&state, guild_id, &form.uri).await?;
+
+    let mut conn = state.data.database.get_connection().await?;
+    favorite_insert(&mut conn, session.user_id, guild_id, title, uri.as_str()).await?;
+
+    Ok(back_to(guild_id))
+}
+
+/// `POST /dashboard/guilds/<id>/favorites/<id>/delete`
+async fn delete_favorite(
+    State(state): State<AppState>,
+    Path((guild_id, id)): Path<(u64, String)>,
+    headers: HeaderMap,
+) -> PageResult {
+    let (_, guild_id) = authorize(&state, &headers, guild_id)?;
+
+    let mut conn = state.data.database.get_connection().await?;
+    favorite_delete(&mut conn, guild_id, &id).await?;
+
+    Ok(back_to(guild_id))
+}
+
+#[derive(Debug, Deserialize)]
+struct ScheduleForm {
+    time: String,
+    channel_id: u64,
+    url: String,
+    mon: Option<String>,
+    tue: Option<String>,
+    wed: Option<String>,
+    thu: Option<String>,
+    fri: Option<String>,
+    sat: Option<String>,
+    sun: Option<String>,
+}
+
+impl ScheduleForm {
+    /// The checked days as bit set, bit 0 is monday
+    fn weekdays(&self) -> u8 {
+        [
+            &self.mon, &self.tue, &self.wed, &self.thu, &self.fri, &self.sat, &self.sun,
+        ]
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| v.is_some())
+        .fold(0, |acc, (i, _)| acc | (1 << i))
+    }
+}
+
+/// `POST /dashboard/guilds/<id>/schedules`
+async fn add_schedule(
+    State(state): State<AppState>,
+    Path(guild_id): Path<u64>,
+    headers: HeaderMap,
+    Form(form): Form<ScheduleForm>,
+) -> PageResult {
+    let (_, guild_id) = authorize(&state, &headers, guild_id)?;
+
+    let Some(time) = NaiveTime::parse_from_str(&form.time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(&form.time, "%H:%M:%S"))
+        .ok()
+    else {
+        return Err(bad_request("That's not a valid time"));
+    };
+
+    let weekdays = form.weekdays();
+    if weekdays == 0 {
+        return Err(bad_request("Pick at least one day"));
+    }
+
+    let channel_id = (form.channel_id != 0).then(|| ChannelId::new(form.channel_id));
+    let is_voice_channel = channel_id
+        .and_then(|channel_id| {
+            let guild = state.cache.guild(guild_id)?;
+            let kind = guild.channels.get(&channel_id)?.kind;
+            Some(matches!(kind, ChannelType::Voice | ChannelType::Stage))
+        })
+        .unwrap_or(false);
+    let (Some(channel_id), true) = (channel_id, is_voice_channel) else {
+        return Err(bad_request("That's not a voice channel of this server"));
+    };
+
+    let url = checked_url(&state, guild_id, &form.url).await?;
+
+    let mut conn = state.data.database.get_connection().await?;
+    schedule_insert(
+        &mut conn,
+        guild_id,
+        channel_id,
+        url.as_str(),
+        time,
+        weekdays,
+    )
+    .await?;
+
+    Ok(back_to(guild_id))
+}
+
+/// `POST /dashboard/guilds/<id>/schedules/<id>/delete`
+async fn delete_schedule(
+    State(state): State<AppState>,
+    Path((guild_id, id)): Path<(u64, String)>,
+    headers: HeaderMap,
+) -> PageResult {
+    let (_, guild_id) = authorize(&state, &headers, guild_id)?;
+
+    let mut conn = state.data.database.get_connection().await?;
+    schedule_delete(&mut conn, guild_id, &id).await?;
+
+    Ok(back_to(guild_id))
+}
+
+fn oauth_config(state: &AppState) -> Result<OAuthConfig, PageError> {
+    state
+        .data
+        .config
+        .get()
+        .dashboard_oauth
+        .clone()
+        .ok_or_else(|| {
+            PageError::new(error_page(
+                StatusCode::NOT_FOUND,
+                "The dashboard is disabled",
+            ))
+        })
+}
+
+fn redirect_uri(state: &AppState) -> String {
+    format!("{}/dashboard/callback", state.data.config.get().public_url)
+}
+
+/// The session of the request, with its ID
+fn session(state: &AppState, headers: &HeaderMap) -> Option<(String, Session)> {
+    let session_id = cookie_value(headers, SESSION_COOKIE)?;
+    let session = state.sessions.get(&session_id)?;
+
+    Some((session_id, session))
+}
+
+/// Makes sure the user is logged in and allowed to manage the guild
+fn authorize(
+    state: &AppState,
+    headers: &HeaderMap,
+    guild_id: u64,
+) -> Result<(Session, GuildId), PageError> {
+    let Some((_, session)) = session(state, headers) else {
+        return Err(PageError::new(
+            Redirect::to("/dashboard/login").into_response(),
+        ));
+    };
+
+    // Guilds without the bot can't be managed either
+    let guild_id = (guild_id != 0).then(|| GuildId::new(guild_id));
+    match guild_id {
+        Some(guild_id) if session.can_manage(guild_id) && state.cache.guild(guild_id).is_some() => {
+            Ok((session, guild_id))
+        }
+        _ => Err(not_found()),
+    }
+}
+
+/// Parses `url`, which has to pass the URL policy of the guild to be saved
+async fn checked_url(state: &AppState, guild_id: GuildId, url: &str) -> Result<Url, PageError> {
+    let url = match Url::parse(url.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => return Err(bad_request("That's not a valid URL")),
+    };
+
+    let policy = state.data.url_policy(Some(guild_id)).await?;
+    if let Err(e) = policy.check(&url).await {
+        tracing::info!("blocked \"{url}\" in the dashboard: {e}");
+        return Err(bad_request(&format!("{url} is off limits: {e}")));
+    }
+
+    Ok(url)
+}
+
+fn back_to(guild_id: GuildId) -> Response {
+    Redirect::to(&format!("/dashboard/guilds/{guild_id}")).into_response()
+}
+
+fn not_found() -> PageError {
+    PageError::new(error_page(
+        StatusCode::NOT_FOUND,
+        "I don't know that server or you can't manage it",
+    ))
+}
+
+fn bad_request(message: &str) -> PageError {
+    PageError::new(error_page(StatusCode::BAD_REQUEST, message))
+}
+
+/// Builds a `Set-Cookie` value. Cookies are `SameSite=Lax`, so forms can't be submitted from
+/// other sites with them
+fn cookie(state: &AppState, name: &str, value: &str, max_age_secs: u64) -> String {
+    let secure = if state.data.config.get().public_url.starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    };
+
+    format!(
+        "{name}={value}; Path=/dashboard; Max-Age={max_age_secs}; HttpOnly; SameSite=Lax{secure}"
+    )
+}
+
+fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|v| v.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+        .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, OnceLock};
+
+    use axum::http::header::AUTHORIZATION;
+    use axum::Json;
+    use poise::serenity_prelude::{
+        Cache, GatewayIntents, Guild, GuildCreateEvent, Http, ShardManager, ShardManagerOptions,
+    };
+    use reqwest::redirect::Policy;
+    use serde_json::json;
+    use songbird::Songbird;
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::{Config, ConfigHandle};
+    use crate::credentials::Credentials;
+    use crate::database::DatabaseContext;
+    use crate::discord::events::GuildEvents;
+    use crate::discord::Data;
+    use crate::health::Health;
+    use crate::http::Sessions;
+    use crate::hub::StreamHub;
+    use crate::outbound::Outbound;
+    use crate::relay::Relays;
+    use crate::ytdl::YtDlp;
+
+    const CODE: &str = "fake-code";
+    const ACCESS_TOKEN: &str = "fake-access-token";
+    /// The user can manage this guild and the bot is in it
+    const MANAGED_GUILD: u64 = 1;
+    /// The bot is in this guild, but the user can't manage it
+    const OTHER_GUILD: u64 = 2;
+    /// The user owns this guild, but the bot isn't in it
+    const GUILD_WITHOUT_BOT: u64 = 3;
+
+    async fn serve(router: Router) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        format!("http://{addr}")
+    }
+
+    fn bearer_matches(headers: &HeaderMap) -> bool {
+        headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok())
+            == Some(&format!("Bearer {ACCESS_TOKEN}"))
+    }
+
+    /// Stands in for the OAuth2 token endpoint and the API of Discord
+    fn fake_discord() -> Router {
+        Router::new()
+            .route(
+                "/oauth2/token",
+                post(|Form(form): Form<HashMap<String, String>>| async move {
+                    if form.get("code").map(String::as_str) != Some(CODE) {
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                    Ok(Json(json!({ "access_token": ACCESS_TOKEN })))
+                }),
+            )
+            .route(
+                "/users/@me",
+                get(|headers: HeaderMap| async move {
+                    if !bearer_matches(&headers) {
+                        return Err(StatusCode::UNAUTHORIZED);
+                    }
+                    Ok(Json(json!({
+                        "id": "42",
+                        "username": "listener",
+                        "global_name": "The Listener",
+                    })))
+                }),
+            )
+            .route(
+                "/users/@me/guilds",
+                get(|headers: HeaderMap| async move {
+                    if !bearer_matches(&headers) {
+                        return Err(StatusCode::UNAUTHORIZED);
+                    }
+                    Ok(Json(json!([
+                        // Manage Server
+                        { "id": MANAGED_GUILD.to_string(), "permissions": "32" },
+                        { "id": OTHER_GUILD.to_string(), "permissions": "0" },
+                        { "id": GUILD_WITHOUT_BOT.to_string(), "owner": true, "permissions": "0" },
+                    ])))
+                }),
+            )
+    }
+
+    fn cache_with_guilds(guild_ids: &[u64]) -> Arc<Cache> {
+        let cache = Arc::new(Cache::new());
+        for guild_id in guild_ids {
+            let mut guild = Guild::default();
+            guild.id = GuildId::new(*guild_id);
+            guild.name = format!("Guild {guild_id}");
+
+            let mut event: GuildCreateEvent =
+                serde_json::from_value(serde_json::to_value(guild).unwrap()).unwrap();
+            cache.update(&mut event);
+        }
+
+        cache
+    }
+
+    async fn app_state(dir: &TempDir, discord_url: &str) -> AppState {
+        let path = dir.path().join("config.toml");
+        let config = format!(
+            r#"
+[discord]
+token = "fake"
+
+[outbound]
+blocked_domains = ["blocked.example"]
+credentials_key = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+
+[database]
+url = "sqlite://{}?mode=rwc"
+
+[http]
+public_url = "http://localhost"
+
+[http.dashboard]
+client_id = "123"
+client_secret = "secret"
+token_url = "{discord_url}/oauth2/token"
+api_url = "{discord_url}"
+"#,
+            dir.path().join("data.db").display()
+        );
+        std::fs::write(&path, config).unwrap();
+        let config = Config::load(Some(&path)).unwrap();
+
+        let database = DatabaseContext::new(&config.database_path).await.unwrap();
+        database.init().await.unwrap();
+
+        let config = ConfigHandle::new(config, Some(path));
+        let outbound = Outbound::new(&config).unwrap();
+        let cache = cache_with_guilds(&[MANAGED_GUILD, OTHER_GUILD]);
+        let http = Arc::new(Http::new("fake"));
+
+        let (shard_manager, _) = ShardManager::new(ShardManagerOptions {
+            data: Arc::default(),
+            event_handlers: Vec::new(),
+            raw_event_handlers: Vec::new(),
+            framework: Arc::new(OnceLock::new()),
+            shard_index: 0,
+            shard_init: 0,
+            shard_total: 1,
+            voice_manager: None,
+            ws_url: Arc::default(),
+            cache: cache.clone(),
+            http,
+            intents: GatewayIntents::empty(),
+            presence: None,
+        });
+
+        let data = Data {
+            ytdl: YtDlp::new(&config.get().ytdl, database.clone(), outbound.guarded()),
+            credentials: Credentials::load(&config.get()).unwrap(),
+            config,
+            database: database.clone(),
+            guild_tracks: Arc::default(),
+            outbound,
+            stream_hub: StreamHub::new(),
+            relays: Relays::default(),
+            events: GuildEvents::default(),
+        };
+
+        AppState {
+            data,
+            songbird: Songbird::serenity(),
+            cache,
+            sessions: Sessions::default(),
+            health: Health::new(database, shard_manager),
+        }
+    }
+
+    /// The value of the `Set-Cookie` header for `name`
+    fn set_cookie(res: &reqwest::Response, name: &str) -> Option<String> {
+        res.headers()
+            .get_all("set-cookie")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .find_map(|v| v.strip_prefix(&format!("{name}=")))
+            .and_then(|v| v.split(';').next())
+            .map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn login_and_guild_authorization() {
+        let dir = tempfile::tempdir().unwrap();
+        let discord_url = serve(fake_discord()).await;
+        let state = app_state(&dir, &discord_url).await;
+        let sessions = state.sessions.clone();
+        let url = serve(Router::new().nest("/dashboard", router()).with_state(state)).await;
+
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .build()
+            .unwrap();
+
+        // Logging in sets the state cookie and redirects to Discord with the same state
+        let res = client
+            .get(format!("{url}/dashboard/login"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), StatusCode::SEE_OTHER.as_u16());
+        let oauth_state = set_cookie(&res, STATE_COOKIE).unwrap();
+        let location = Url::parse(res.headers()["location"].to_str().unwrap()).unwrap();
+        assert!(location
+            .query_pairs()
+            .any(|(k, v)| k == "state" && v == oauth_state));
+
+        // A callback without the state cookie is rejected
+        let res = client
+            .get(format!(
+                "{url}/dashboard/callback?code={CODE}&state={oauth_state}"
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), StatusCode::BAD_REQUEST.as_u16());
+        assert!(set_cookie(&res, SESSION_COOKIE).is_none());
+
+        let res = client
+            .get(format!(
+                "{url}/dashboard/callback?code={CODE}&state={oauth_state}"
+            ))
+            .header("cookie", format!("{STATE_COOKIE}={oauth_state}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), StatusCode::SEE_OTHER.as_u16());
+        let session_id = set_cookie(&res, SESSION_COOKIE).unwrap();
+
+        let session = sessions.get(&session_id).unwrap();
+        assert_eq!(session.username, "The Listener");
+        assert_eq!(
+            session.guilds,
+            [GuildId::new(MANAGED_GUILD), GuildId::new(GUILD_WITHOUT_BOT)]
+        );
+
+        let get_guild = |guild_id: u64, cookie: Option<String>| {
+            let mut req = client.get(format!("{url}/dashboard/guilds/{guild_id}"));
+            if let Some(cookie) = cookie {
+                req = req.header("cookie", cookie);
+            }
+            req.send()
+        };
+        let session_cookie = format!("{SESSION_COOKIE}={session_id}");
+
+        let res = get_guild(MANAGED_GUILD, Some(session_cookie.clone()))
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), StatusCode::OK.as_u16());
+        assert!(res.text().await.unwrap().contains("Guild 1"));
+
+        for guild_id in [OTHER_GUILD, GUILD_WITHOUT_BOT] {
+            let res = get_guild(guild_id, Some(session_cookie.clone()))
+                .await
+                .unwrap();
+            assert_eq!(
+                res.status().as_u16(),
+                StatusCode::NOT_FOUND.as_u16(),
+                "guild {guild_id}"
+            );
+        }
+
+        // Without a session, the user is sent to the login
+        let res = get_guild(MANAGED_GUILD, None).await.unwrap();
+        assert_eq!(res.status().as_u16(), StatusCode::SEE_OTHER.as_u16());
+        assert_eq!(res.headers()["location"], "/dashboard/login");
+        let res = get_guild(MANAGED_GUILD, Some(format!("{SESSION_COOKIE}=guessed")))
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), StatusCode::SEE_OTHER.as_u16());
+
+        // Favorites have to pass the URL policy
+        let add_favorite = |uri: &str| {
+            client
+                .post(format!("{url}/dashboard/guilds/{MANAGED_GUILD}/favorites"))
+                .header("cookie", session_cookie.clone())
+                .form(&[("title", "Radio"), ("uri", uri)])
+                .send()
+        };
+        for uri in [
+            "http://radio.blocked.example/live",
+            "http://127.0.0.1:8000/",
+        ] {
+            let res = add_favorite(uri).await.unwrap();
+            assert_eq!(
+                res.status().as_u16(),
+                StatusCode::BAD_REQUEST.as_u16(),
+                "{uri}"
+            );
+            assert!(res.text().await.unwrap().contains("off limits"));
+        }
+        let res = add_favorite("http://1.1.1.1/live").await.unwrap();
+        assert_eq!(res.status().as_u16(), StatusCode::SEE_OTHER.as_u16());
+    }
+}
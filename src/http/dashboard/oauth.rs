@@ -0,0 +1,169 @@
+//! Discord OAuth2 login and the sessions of logged in users
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::RwLock;
+use poise::serenity_prelude::{GuildId, Permissions, UserId};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Deserialize;
+use url::Url;
+
+use crate::config::OAuthConfig;
+use crate::discord::Error;
+
+/// Page the user is sent to for authorizing the application
+const AUTHORIZE_URL: &str = "https://discord.com/oauth2/authorize";
+const SCOPES: &str = "identify guilds";
+/// Sessions expire after this long, even if they're still used
+const SESSION_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A logged in user
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub user_id: UserId,
+    pub username: String,
+    /// Guilds the user is allowed to manage
+    pub guilds: Vec<GuildId>,
+    expires_at: Instant,
+}
+
+impl Session {
+    pub fn can_manage(&self, guild_id: GuildId) -> bool {
+        self.guilds.contains(&guild_id)
+    }
+}
+
+/// Sessions by their ID, which is stored in a cookie
+#[derive(Clone, Default)]
+pub struct Sessions {
+    sessions: Arc<RwLock<HashMap<String, Session>>>,
+}
+
+impl Sessions {
+    pub fn get(&self, id: &str) -> Option<Session> {
+        self.sessions
+            .read()
+            .get(id)
+            .filter(|v| v.expires_at > Instant::now())
+            .cloned()
+    }
+
+    /// Stores a new session, returning its ID
+    pub fn create(&self, user_id: UserId, username: String, guilds: Vec<GuildId>) -> String {
+        let id = random_token();
+        let now = Instant::now();
+
+        let mut sessions = self.sessions.write();
+        sessions.retain(|_, v| v.expires_at > now);
+        sessions.insert(
+            id.clone(),
+            Session {
+                user_id,
+                username,
+                guilds,
+                expires_at: now + SESSION_LIFETIME,
+            },
+        );
+
+        id
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.sessions.write().remove(id);
+    }
+}
+
+/// A random string, e.g. for session IDs and the OAuth2 `state`
+pub fn random_token() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 32)
+}
+
+/// URL the user is redirected to for logging in
+pub fn authorize_url(config: &OAuthConfig, redirect_uri: &str, state: &str) -> String {
+    let mut url = Url::parse(AUTHORIZE_URL).expect("valid authorize URL");
+    url.query_pairs_mut()
+        .append_pair("client_id", &config.client_id)
+        .append_pair("response_type", "code")
+        .append_pair("scope", SCOPES)
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("state", state);
+
+    url.to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct User {
+    pub id: UserId,
+    pub username: String,
+    pub global_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PartialGuild {
+    id: GuildId,
+    #[serde(default)]
+    owner: bool,
+    /// Permissions of the user in the guild, as a stringified bit set
+    #[serde(default)]
+    permissions: String,
+}
+
+impl PartialGuild {
+    fn can_manage(&self) -> bool {
+        let permissions = Permissions::from_bits_truncate(self.permissions.parse().unwrap_or(0));
+        self.owner || permissions.administrator() || permissions.manage_guild()
+    }
+}
+
+/// Exchanges the authorization code of the callback for the user and the guilds they can manage
+pub async fn login(
+    client: &reqwest::Client,
+    config: &OAuthConfig,
+    redirect_uri: &str,
+    code: &str,
+) -> Result<(User, Vec<GuildId>), Error> {
+    let token = client
+        .post(&config.token_url)
+        .basic_auth(&config.client_id, Some(config.client_secret.expose()))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<TokenResponse>()
+        .await?;
+
+    let user = client
+        .get(format!("{}/users/@me", config.api_url))
+        .bearer_auth(&token.access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<User>()
+        .await?;
+
+    let guilds = client
+        .get(format!("{}/users/@me/guilds", config.api_url))
+        .bearer_auth(&token.access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Vec<PartialGuild>>()
+        .await?
+        .into_iter()
+        .filter(PartialGuild::can_manage)
+        .map(|v| v.id)
+        .collect();
+
+    Ok((user, guilds))
+}
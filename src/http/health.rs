@@ -0,0 +1,31 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+
+use crate::http::AppState;
+
+/// `GET /healthz`: Whether the bot is still running, fails if the event loop is stalled
+pub async fn healthz(State(state): State<AppState>) -> Response {
+    match state.health.check_live() {
+        Ok(()) => Json(json!({ "status": "ok" })).into_response(),
+        Err(reason) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": reason })),
+        )
+            .into_response(),
+    }
+}
+
+/// `GET /readyz`: Whether all shards are connected and the database is usable
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    let readiness = state.health.readiness().await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness)).into_response()
+}
@@ -0,0 +1,42 @@
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use poise::serenity_prelude::GuildId;
+
+use crate::http::AppState;
+use crate::metrics::{self, Gauges};
+
+/// `GET /metrics`: All metrics in the Prometheus text format
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    if !state.data.config.get().metrics_enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let mut gauges = Gauges {
+        voice_connections: 0,
+        active_tracks: 0,
+    };
+
+    let handlers = state.songbird.iter().collect::<Vec<_>>();
+    for (guild_id, handler) in handlers {
+        let guild_id = GuildId::from(guild_id.0);
+
+        if handler.lock().await.current_connection().is_some() {
+            gauges.voice_connections += 1;
+        }
+
+        // Tracks which ended are still around until they're replaced
+        if let Some(track_handle) = state.data.current_track(&state.songbird, guild_id).await {
+            if track_handle.get_info().await.is_ok() {
+                gauges.active_tracks += 1;
+            }
+        }
+    }
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(gauges),
+    )
+        .into_response()
+}
@@ -0,0 +1,107 @@
+//! Embedded HTTP server
+
+mod api;
+mod dashboard;
+mod health;
+mod metrics;
+mod relay;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
+use axum::routing::{get, post, put};
+use axum::{middleware, Router};
+use poise::serenity_prelude::Cache;
+use serde::Deserialize;
+use songbird::Songbird;
+
+use crate::discord::{Data, Error};
+use crate::health::Health;
+pub use dashboard::oauth::Sessions;
+
+/// State shared by all HTTP handlers
+#[derive(Clone)]
+pub struct AppState {
+    pub data: Data,
+    pub songbird: Arc<Songbird>,
+    pub cache: Arc<Cache>,
+    /// Logged in users of the dashboard
+    pub sessions: Sessions,
+    pub health: Health,
+}
+
+/// Runs the HTTP server in the background
+pub fn spawn_server(bind: SocketAddr, state: AppState) {
+    tokio::spawn(async move {
+        if let Err(e) = serve(bind, state).await {
+            tracing::error!("HTTP server stopped: {e}");
+        }
+    });
+}
+
+async fn serve(bind: SocketAddr, state: AppState) -> Result<(), Error> {
+    let mut router = Router::new()
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        // Always routed, as metrics can be enabled by reloading the config
+        .route("/metrics", get(metrics::metrics))
+        .route("/guild/:guild_id/stream", get(relay::stream));
+
+    let config = state.data.config.get();
+
+    if config.api_token.is_some() {
+        let api = Router::new()
+            .route("/guilds", get(api::guilds))
+            .route("/guilds/:guild_id", get(api::guild))
+            .route("/guilds/:guild_id/play", post(api::play))
+            .route("/guilds/:guild_id/stop", post(api::stop))
+            .route("/guilds/:guild_id/volume", put(api::volume))
+            .route("/events", get(api::events))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                api::require_token,
+            ));
+
+        router = router.nest("/api", api);
+    }
+
+    if config.dashboard_oauth.is_some() {
+        router = router.nest("/dashboard", dashboard::router());
+    }
+
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    tracing::info!("HTTP server listening on {bind}");
+
+    axum::serve(listener, router.with_state(state)).await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Gets the token of a request, either from the `token` query parameter or the
+/// `Authorization: Bearer` header
+fn request_token(query: TokenQuery, headers: &HeaderMap) -> Option<String> {
+    query.token.or_else(|| {
+        headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::to_string)
+    })
+}
+
+/// Compares tokens in constant time, so they can't be guessed byte by byte
+pub fn tokens_match(expected: &str, token: &str) -> bool {
+    expected.len() == token.len()
+        && expected
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
@@ -0,0 +1,273 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use poise::serenity_prelude::GuildId;
+use tokio::sync::broadcast;
+
+use crate::http::{request_token, AppState, TokenQuery};
+use crate::metrics;
+use crate::relay::{GuildRelay, OggOpusWriter};
+
+/// Silence is sent if nothing was played for this long, so players don't give up
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Number of silent frames sent per `IDLE_TIMEOUT` (20 ms each)
+const IDLE_SILENT_FRAMES: usize = 50;
+/// An Opus packet containing 20 ms of silence
+const SILENT_FRAME: [u8; 3] = [0xF8, 0xFF, 0xFE];
+/// Bytes of audio between two ICY metadata blocks
+const ICY_METAINT: usize = 16_000;
+/// The length of a metadata block is announced in a single byte, in units of 16 bytes
+const MAX_ICY_METADATA_LEN: usize = 255 * 16;
+
+/// `GET /guild/<id>/stream`: The audio of a guild as Ogg/Opus.
+///
+/// The title of the current track is sent as a `TITLE` comment, see [`OggOpusWriter::chain`].
+/// Clients asking for ICY metadata (`Icy-MetaData: 1`) additionally get `StreamTitle` blocks every
+/// `icy-metaint` bytes, which they strip before handing the Ogg stream to the demuxer.
+pub async fn stream(
+    State(state): State<AppState>,
+    Path(guild_id): Path<u64>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let token = request_token(query, &headers);
+
+    let relay = (guild_id != 0)
+        .then(|| state.data.relays.get(GuildId::new(guild_id)))
+        .flatten();
+
+    // Guilds without a relay are indistinguishable from a wrong token
+    let (Some(relay), Some(token)) = (relay, token) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    if !relay.is_authorized(&token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let wants_metadata = headers
+        .get("icy-metadata")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim() == "1");
+
+    tracing::debug!("new relay listener for guild {guild_id}");
+
+    let listener = Listener {
+        rx: relay.subscribe(),
+        relay,
+        token,
+        ogg: OggOpusWriter::new(rand::random()),
+        started: false,
+        title: None,
+        icy: wants_metadata.then(IcyMetadata::default),
+    };
+    let body = futures::stream::unfold(listener, |mut listener| async move {
+        let chunk = listener.next_chunk().await?;
+        metrics::RELAY_BYTES.inc_by(chunk.len() as u64);
+        Some((Ok::<_, Infallible>(chunk), listener))
+    });
+
+    let mut res = Response::builder()
+        .header(CONTENT_TYPE, "audio/ogg")
+        .header(CACHE_CONTROL, "no-cache, no-store")
+        .header("icy-name", "discomfort.fm");
+    if wants_metadata {
+        res = res.header("icy-metaint", ICY_METAINT);
+    }
+
+    res.body(Body::from_stream(body))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+/// A single HTTP client listening to a relay
+struct Listener {
+    relay: Arc<GuildRelay>,
+    /// Token the listener authorized with, it's disconnected once the token changes
+    token: String,
+    rx: broadcast::Receiver<Arc<[u8]>>,
+    ogg: OggOpusWriter,
+    started: bool,
+    /// The title in the tags of the current logical stream
+    title: Option<String>,
+    icy: Option<IcyMetadata>,
+}
+
+impl Listener {
+    /// Returns the next chunk of the response, `None` ends it
+    async fn next_chunk(&mut self) -> Option<Vec<u8>> {
+        let data = self.next_ogg_chunk().await?;
+
+        Some(match &mut self.icy {
+            Some(icy) => icy.interleave(&data, self.title.as_deref()),
+            None => data,
+        })
+    }
+
+    async fn next_ogg_chunk(&mut self) -> Option<Vec<u8>> {
+        if !self.started {
+            self.started = true;
+            self.title = self.relay.title();
+            return Some(self.ogg.headers(self.title.as_deref()));
+        }
+
+        loop {
+            if !self.relay.is_authorized(&self.token) {
+                return None;
+            }
+
+            match tokio::time::timeout(IDLE_TIMEOUT, self.rx.recv()).await {
+                Ok(Ok(packet)) => return Some(self.write(&packet)),
+                Ok(Err(broadcast::error::RecvError::Lagged(n))) => {
+                    tracing::debug!("relay listener lagged behind by {n} packets");
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+                Err(_) => {
+                    let mut data = Vec::new();
+                    for _ in 0..IDLE_SILENT_FRAMES {
+                        data.extend(self.write(&SILENT_FRAME));
+                    }
+                    return Some(data);
+                }
+            }
+        }
+    }
+
+    /// Writes a packet, chaining a new logical stream if the title changed
+    fn write(&mut self, packet: &[u8]) -> Vec<u8> {
+        let title = self.relay.title();
+        if title == self.title {
+            return self.ogg.packet(packet);
+        }
+
+        let data = self.ogg.chain(packet, title.as_deref());
+        self.title = title;
+        data
+    }
+}
+
+/// Inserts ICY metadata blocks (`StreamTitle='...';`) every `ICY_METAINT` bytes
+struct IcyMetadata {
+    bytes_until_metadata: usize,
+    /// The title of the last block, `None` before the first one
+    last_title: Option<Option<String>>,
+}
+
+impl Default for IcyMetadata {
+    fn default() -> Self {
+        Self {
+            bytes_until_metadata: ICY_METAINT,
+            last_title: None,
+        }
+    }
+}
+
+impl IcyMetadata {
+    fn interleave(&mut self, mut data: &[u8], title: Option<&str>) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() + 1);
+
+        while !data.is_empty() {
+            let len = data.len().min(self.bytes_until_metadata);
+            out.extend_from_slice(&data[..len]);
+            data = &data[len..];
+            self.bytes_until_metadata -= len;
+
+            if self.bytes_until_metadata == 0 {
+                out.extend(self.block(title));
+                self.bytes_until_metadata = ICY_METAINT;
+            }
+        }
+
+        out
+    }
+
+    /// A metadata block, which is empty if the title didn't change
+    fn block(&mut self, title: Option<&str>) -> Vec<u8> {
+        if self.last_title.as_ref().map(Option::as_deref) == Some(title) {
+            return vec![0];
+        }
+        self.last_title = Some(title.map(str::to_string));
+
+        // Single quotes would end the title early
+        let mut text = format!(
+            "StreamTitle='{}';",
+            title.unwrap_or_default().replace('\'', "’")
+        );
+        if text.len() > MAX_ICY_METADATA_LEN {
+            let mut end = MAX_ICY_METADATA_LEN - "';".len();
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+            text.push_str("';");
+        }
+
+        let blocks = text.len().div_ceil(16);
+        let mut block = Vec::with_capacity(1 + blocks * 16);
+        block.push(blocks as u8);
+        block.extend_from_slice(text.as_bytes());
+        block.resize(1 + blocks * 16, 0);
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits an ICY stream into the audio and the metadata texts
+    fn split(mut data: &[u8]) -> (Vec<u8>, Vec<String>) {
+        let mut audio = Vec::new();
+        let mut metadata = Vec::new();
+
+        while !data.is_empty() {
+            let len = data.len().min(ICY_METAINT);
+            audio.extend_from_slice(&data[..len]);
+            data = &data[len..];
+            if data.is_empty() {
+                break;
+            }
+
+            let block_len = data[0] as usize * 16;
+            let text = String::from_utf8(data[1..1 + block_len].to_vec()).unwrap();
+            metadata.push(text.trim_end_matches('\0').to_string());
+            data = &data[1 + block_len..];
+        }
+
+        (audio, metadata)
+    }
+
+    #[test]
+    fn interleave() {
+        let mut icy = IcyMetadata::default();
+        let audio = (0..40_000).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut out = icy.interleave(&audio[..10_000], Some("It's a song"));
+        out.extend(icy.interleave(&audio[10_000..30_000], Some("It's a song")));
+        out.extend(icy.interleave(&audio[30_000..], None));
+
+        let (received, metadata) = split(&out);
+        assert_eq!(received, audio);
+        assert_eq!(metadata, ["StreamTitle='It’s a song';", "StreamTitle='';"]);
+        assert_eq!(icy.bytes_until_metadata, 3 * ICY_METAINT - audio.len());
+
+        // The title didn't change, so the block is empty
+        let out = icy.interleave(&audio[..icy.bytes_until_metadata], None);
+        assert_eq!(out.last(), Some(&0));
+    }
+
+    #[test]
+    fn long_titles() {
+        let mut icy = IcyMetadata::default();
+        let block = icy.block(Some(&"ü".repeat(5000)));
+
+        assert_eq!(block[0], 255);
+        assert_eq!(block.len(), 1 + MAX_ICY_METADATA_LEN);
+        let text = std::str::from_utf8(&block[1..]).unwrap();
+        assert!(text.trim_end_matches('\0').ends_with("ü';"));
+    }
+}
@@ -0,0 +1,287 @@
+//! Shares live streams between guilds.
+//!
+//! Every upstream is fetched and decoded once, the PCM samples are broadcast to all guilds
+//! listening to it. The upstream is closed as soon as the last guild stops listening.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind as IoErrorKind, SeekFrom};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use songbird::input::codecs::{CODEC_REGISTRY, PROBE};
+use songbird::input::{AsyncAdapterStream, AsyncMediaSource, Input, LiveInput, Parsed, RawAdapter};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::errors::Error as SymphoniaError;
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::discord::Error;
+
+/// Number of decoded packets buffered per listener (roughly 10-20 seconds of audio)
+const BROADCAST_CAPACITY: usize = 512;
+/// Number of recent packets handed to new listeners (roughly 2-4 seconds of audio).
+///
+/// This keeps every listener a bit behind the live edge, so reading rarely has to wait for the
+/// upstream.
+const HISTORY_PACKETS: usize = 100;
+/// Number of packets forwarded to a listener ahead of playback
+const LISTENER_BUFFER: usize = 16;
+
+type Streams = Arc<Mutex<HashMap<String, SharedStream>>>;
+
+/// Streams which are currently shared, keyed by their (resolved) URL
+#[derive(Clone, Default)]
+pub struct StreamHub {
+    streams: Streams,
+}
+
+#[derive(Clone)]
+struct SharedStream {
+    tx: broadcast::Sender<Arc<[u8]>>,
+    /// The most recently broadcast packets
+    history: Arc<Mutex<VecDeque<Arc<[u8]>>>>,
+    sample_rate: u32,
+    channels: u32,
+}
+
+impl StreamHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an input listening to the stream `key`.
+    ///
+    /// If nobody is listening to it yet, the upstream is opened via `make_input`.
+    pub async fn subscribe<F>(&self, key: &str, make_input: F) -> Result<Input, Error>
+    where
+        F: FnOnce() -> Input,
+    {
+        if let Some(stream) = self.streams.lock().get(key) {
+            tracing::debug!("sharing stream \"{key}\"");
+            return Ok(stream.subscribe());
+        }
+
+        let input = make_input()
+            .make_playable_async(&CODEC_REGISTRY, &PROBE)
+            .await?;
+        let Input::Live(LiveInput::Parsed(parsed), _) = input else {
+            return Err(format!("stream \"{key}\" couldn't be parsed").into());
+        };
+
+        let params = parsed.decoder.codec_params();
+        let sample_rate = params
+            .sample_rate
+            .ok_or_else(|| format!("stream \"{key}\" doesn't have a sample rate"))?;
+        let channels = params
+            .channels
+            .ok_or_else(|| format!("stream \"{key}\" doesn't have a channel layout"))?
+            .count() as u32;
+
+        let (tx, _) = broadcast::channel(BROADCAST_CAPACITY);
+        let stream = SharedStream {
+            tx,
+            history: Arc::new(Mutex::new(VecDeque::with_capacity(HISTORY_PACKETS))),
+            sample_rate,
+            channels,
+        };
+
+        let mut streams = self.streams.lock();
+
+        // Another guild might have opened the same stream in the meantime
+        if let Some(existing) = streams.get(key) {
+            return Ok(existing.subscribe());
+        }
+
+        let input = stream.subscribe();
+        streams.insert(key.to_string(), stream.clone());
+        drop(streams);
+
+        tracing::debug!("opened shared stream \"{key}\"");
+
+        let streams = self.streams.clone();
+        let key = key.to_string();
+        tokio::task::spawn_blocking(move || decode(parsed, stream, streams, key));
+
+        Ok(input)
+    }
+}
+
+impl SharedStream {
+    /// Has to be called from within the runtime, the packets are forwarded by a task.
+    fn subscribe(&self) -> Input {
+        // Subscribing while holding the lock makes sure no packet is missed or received twice
+        let history = self.history.lock();
+        let rx = self.tx.subscribe();
+        let pending = history.clone();
+        drop(history);
+
+        let (packet_tx, packet_rx) = mpsc::channel(LISTENER_BUFFER);
+        tokio::spawn(forward(rx, pending, packet_tx));
+
+        let receiver = StreamReceiver {
+            rx: packet_rx,
+            buf: Arc::from([]),
+            pos: 0,
+        };
+        let stream = AsyncAdapterStream::new(Box::new(receiver), 64 * 1024);
+
+        RawAdapter::new(stream, self.sample_rate, self.channels).into()
+    }
+}
+
+/// Decodes the upstream and broadcasts its samples as interleaved little-endian `f32`, until the
+/// upstream ends or nobody is listening anymore.
+fn decode(mut parsed: Parsed, stream: SharedStream, streams: Streams, key: String) {
+    let tx = &stream.tx;
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        if tx.receiver_count() == 0 {
+            // Checked again while locked, a guild could subscribe right now
+            let mut streams = streams.lock();
+            if tx.receiver_count() == 0 {
+                streams.remove(&key);
+                tracing::debug!("closed shared stream \"{key}\", nobody is listening anymore");
+                return;
+            }
+        }
+
+        let packet = match parsed.format.next_packet() {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::info!("shared stream \"{key}\" ended: {e}");
+                break;
+            }
+        };
+        if packet.track_id() != parsed.track_id {
+            continue;
+        }
+
+        let decoded = match parsed.decoder.decode(&packet) {
+            Ok(v) => v,
+            // Broken packets happen in radio streams, they are just skipped
+            Err(SymphoniaError::DecodeError(e)) => {
+                tracing::debug!("skipping broken packet in shared stream \"{key}\": {e}");
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!("couldn't decode shared stream \"{key}\": {e}");
+                break;
+            }
+        };
+
+        let samples_needed = decoded.capacity() * decoded.spec().channels.count();
+        let buf = match &mut sample_buf {
+            Some(buf) if buf.capacity() >= samples_needed => buf,
+            buf => buf.insert(SampleBuffer::new(
+                decoded.capacity() as u64,
+                *decoded.spec(),
+            )),
+        };
+        buf.copy_interleaved_ref(decoded);
+
+        let bytes = buf
+            .samples()
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Arc<[u8]>>();
+
+        let mut history = stream.history.lock();
+        if history.len() >= HISTORY_PACKETS {
+            history.pop_front();
+        }
+        history.push_back(bytes.clone());
+
+        // Sending only fails without listeners, which is handled above
+        drop(tx.send(bytes));
+    }
+
+    streams.lock().remove(&key);
+}
+
+/// Forwards the packets of a shared stream to a single listener, until the stream ends or the
+/// listener is gone.
+///
+/// The mixer reads inputs synchronously, so waiting for the upstream has to happen out here.
+async fn forward(
+    mut rx: broadcast::Receiver<Arc<[u8]>>,
+    pending: VecDeque<Arc<[u8]>>,
+    tx: mpsc::Sender<Arc<[u8]>>,
+) {
+    for packet in pending {
+        if tx.send(packet).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let packet = match rx.recv().await {
+            Ok(v) => v,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                tracing::debug!("listener of shared stream lagged behind by {n} packets");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if tx.send(packet).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// PCM of a shared stream, as received by a single guild
+struct StreamReceiver {
+    rx: mpsc::Receiver<Arc<[u8]>>,
+    buf: Arc<[u8]>,
+    pos: usize,
+}
+
+impl AsyncRead for StreamReceiver {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while self.pos >= self.buf.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(v)) => {
+                    self.buf = v;
+                    self.pos = 0;
+                }
+                // The stream ended, signal EOF
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let len = buf.remaining().min(self.buf.len() - self.pos);
+        buf.put_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for StreamReceiver {
+    fn start_seek(self: Pin<&mut Self>, _position: SeekFrom) -> std::io::Result<()> {
+        Err(IoErrorKind::Unsupported.into())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Err(IoErrorKind::Unsupported.into()))
+    }
+}
+
+#[async_trait]
+impl AsyncMediaSource for StreamReceiver {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    async fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
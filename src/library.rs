@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::Utc;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+use crate::database::actions::{library_track_insert_or_update, library_tracks_remove_stale};
+use crate::database::DatabaseContext;
+use crate::discord::Error;
+
+/// File extensions which are added to the library
+const SUPPORTED_EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "opus"];
+
+/// Tags read from an audio file
+#[derive(Debug, Default)]
+struct AudioTags {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    duration: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+pub struct IndexStats {
+    pub indexed: usize,
+    pub failed: usize,
+    pub removed: u64,
+}
+
+/// Indexes all supported audio files in `root` (recursively) into the database.
+///
+/// Tracks whose files no longer exist are removed from the library.
+pub async fn index(db: &DatabaseContext, root: &Path) -> Result<IndexStats, Error> {
+    let started_at = Utc::now().to_rfc3339();
+
+    let root = root.to_path_buf();
+    let files = tokio::task::spawn_blocking(move || find_audio_files(&root)).await??;
+
+    let mut conn = db.get_connection().await?;
+    let mut stats = IndexStats::default();
+
+    for path in files {
+        let tags = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || read_tags(&path)).await?
+        };
+
+        let tags = match tags {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::warn!("couldn't read tags of \"{}\": {e}", path.display());
+                stats.failed += 1;
+                continue;
+            }
+        };
+
+        library_track_insert_or_update(
+            &mut conn,
+            &path,
+            tags.title.as_deref(),
+            tags.artist.as_deref(),
+            tags.album.as_deref(),
+            tags.duration,
+        )
+        .await?;
+        stats.indexed += 1;
+    }
+
+    stats.removed = library_tracks_remove_stale(&mut conn, &started_at).await?;
+
+    Ok(stats)
+}
+
+fn find_audio_files(root: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    // Symlinked directories are followed, but each one is only read once, so loops end
+    let mut visited = HashSet::new();
+
+    while let Some(dir) = dirs.pop() {
+        if !visited.insert(dir.canonicalize()?) {
+            continue;
+        }
+
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_supported_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn is_supported_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|v| v.to_str())
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+fn read_tags(path: &Path) -> Result<AudioTags, Error> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|v| v.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let mut tags = AudioTags::default();
+
+    // Tags can be in front of the container (e.g. ID3v2) or inside of it
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|v| v.current()) {
+        apply_tags(&mut tags, revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        apply_tags(&mut tags, revision);
+    }
+
+    tags.duration = probed.format.default_track().and_then(|track| {
+        let params = &track.codec_params;
+        let time = params.time_base?.calc_time(params.n_frames?);
+
+        Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+    });
+
+    Ok(tags)
+}
+
+fn apply_tags(tags: &mut AudioTags, revision: &MetadataRevision) {
+    for tag in revision.tags() {
+        let target = match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => &mut tags.title,
+            Some(StandardTagKey::Artist) => &mut tags.artist,
+            Some(StandardTagKey::Album) => &mut tags.album,
+            _ => continue,
+        };
+
+        let value = tag.value.to_string();
+        if !value.trim().is_empty() {
+            *target = Some(value.trim().to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("album")).unwrap();
+        File::create(root.join("album/track.mp3")).unwrap();
+        File::create(root.join("cover.jpg")).unwrap();
+        std::os::unix::fs::symlink(root, root.join("album/loop")).unwrap();
+
+        let files = find_audio_files(root).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with("track.mp3"));
+    }
+}
@@ -1,4 +1,13 @@
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use std::sync::OnceLock;
+
+use tracing_subscriber::filter::{EnvFilter, Targets};
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, Layer, Registry};
+
+use crate::discord::Error;
+use crate::metrics::QueryDurationLayer;
+
+/// Swaps the filter of the printed logs when the config is reloaded
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
 
 /*
 #[cfg(not(debug_assertions))]
@@ -8,13 +17,18 @@ const MAX_LEVEL: tracing::Level = tracing::Level::INFO;
 const MAX_LEVEL: tracing::Level = tracing::Level::DEBUG;
 */
 
-pub fn setup_log() {
+/// Sets up logging with `filter` (validated when loading the config)
+pub fn setup_log(filter: &str) {
+    let (env_filter, handle) = reload::Layer::new(EnvFilter::new(filter));
+    let _ = FILTER_HANDLE.set(handle);
+
     tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(env_filter))
+        // sqlx logs every statement with its duration, independent of what is printed
         .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "debug,hyper=info,h2=info,rustls=info,reqwest=info".into()),
+            QueryDurationLayer
+                .with_filter(Targets::new().with_target("sqlx::query", tracing::Level::DEBUG)),
         )
-        .with(tracing_subscriber::fmt::layer())
         .init();
 
     /*
@@ -23,3 +37,11 @@ pub fn setup_log() {
         .init();
     */
 }
+
+/// Replaces the filter passed to [`setup_log`]
+pub fn set_filter(filter: &str) -> Result<(), Error> {
+    let handle = FILTER_HANDLE.get().ok_or("logging isn't set up")?;
+    handle.reload(EnvFilter::try_new(filter)?)?;
+
+    Ok(())
+}
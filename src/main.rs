@@ -1,27 +1,337 @@
-use crate::{config::Config, database::DatabaseContext};
+use std::path::{Path, PathBuf};
+
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use poise::serenity_prelude::GuildId;
+
+use crate::{
+    config::{Config, ConfigHandle},
+    credentials::Credentials,
+    database::{DatabaseContext, Dump},
+    discord::registration::{self, Scope},
+    outbound::Outbound,
+    ytdl::YtDlp,
+};
 
 mod config;
+mod credentials;
 mod database;
 mod discord;
+mod doctor;
+mod health;
+mod hls;
+mod http;
+mod hub;
+mod library;
 mod logger;
+mod metrics;
+mod outbound;
+mod podcast;
+mod relay;
+mod schedule;
+mod stream;
+mod url_policy;
+mod ytdl;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Config file (TOML or YAML), defaults to `config.toml` in the config directory.
+    /// Environment variables override its values.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Print the effective config (with secrets redacted) and exit
+    #[arg(long)]
+    print_config: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the bot (the default)
+    Run,
+    /// Apply all pending database migrations
+    Migrate {
+        /// Only list the migrations and whether they were applied
+        #[arg(long)]
+        list: bool,
+    },
+    /// Register the slash commands with Discord
+    Register(ScopeArgs),
+    /// Remove all registered slash commands
+    Unregister(ScopeArgs),
+    /// Validate the config and exit
+    CheckConfig,
+    /// Check that yt-dlp, Opus, the database and decoding work
+    Doctor,
+    /// Export or import the contents of the database
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Exit successfully if the running bot is live (e.g. for the `HEALTHCHECK` of the Docker
+    /// image)
+    Healthcheck,
+}
+
+#[derive(Debug, ClapArgs)]
+#[group(required = true, multiple = false)]
+struct ScopeArgs {
+    /// Only for one guild, changes show up immediately
+    #[arg(long, value_name = "ID")]
+    guild: Option<u64>,
+    /// For all guilds, changes can take a while to show up
+    #[arg(long)]
+    global: bool,
+}
+
+impl ScopeArgs {
+    fn scope(&self) -> Scope {
+        match self.guild {
+            Some(guild_id) => Scope::Guild(GuildId::new(guild_id)),
+            None => Scope::Global,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum DbCommand {
+    /// Write all tables to a JSON file
+    Export { path: PathBuf },
+    /// Replace the contents of the tables with the ones of a JSON file from `db export`
+    Import { path: PathBuf },
+}
 
 #[tokio::main]
 async fn main() {
-    logger::setup_log();
+    let args = Args::parse();
+    let dotenv_result = dotenvy::dotenv();
 
-    if let Err(e) = dotenvy::dotenv() {
+    let config = match Config::load(args.config.as_deref()) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    if args.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+
+    let command = args.command.unwrap_or(Command::Run);
+    match command {
+        Command::Healthcheck => healthcheck(&config).await,
+        Command::CheckConfig => match check_config(&config) {
+            Ok(()) => {
+                println!("the configuration is valid");
+                return;
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        },
+        _ => {}
+    }
+
+    logger::setup_log(&config.log_filter);
+
+    if let Err(e) = dotenv_result {
         tracing::warn!("couldn't load dotenv: {e:?}");
     }
 
+    let result = match command {
+        Command::Migrate { list } => migrate(&config, list).await,
+        Command::Register(scope) => register(&config, scope.scope()).await,
+        Command::Unregister(scope) => unregister(&config, scope.scope()).await,
+        Command::Db(DbCommand::Export { path }) => export(&config, &path).await,
+        Command::Db(DbCommand::Import { path }) => import(&config, &path).await,
+        Command::Doctor => run_doctor(&config).await,
+        Command::Run => run(config, args.config).await,
+        Command::Healthcheck | Command::CheckConfig => unreachable!(),
+    };
+
+    if let Err(e) = result {
+        tracing::error!("{e}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(config: Config, config_path: Option<PathBuf>) -> Result<(), discord::Error> {
     tracing::info!("Hello world");
 
-    let config = Config::get().unwrap();
     tracing::info!("Database URI: \"{}\"", config.database_path);
-    let db = DatabaseContext::new(&config.database_path).await.unwrap();
+    let db = open_database(&config).await?;
+
+    db.init().await.map_err(|e| e.to_string())?;
+
+    let config_handle = ConfigHandle::new(config.clone(), config_path);
+
+    let outbound = Outbound::new(&config_handle)
+        .map_err(|e| format!("couldn't set up the HTTP clients: {e}"))?;
+    let credentials = Credentials::load(&config)
+        .map_err(|e| format!("couldn't load the credentials key: {e}"))?;
 
-    db.init().await.unwrap();
+    // The media yt-dlp resolves URLs to can be anywhere
+    let ytdl = YtDlp::new(&config.ytdl, db.clone(), outbound.guarded());
+    if config.ytdl.managed {
+        if let Err(e) = ytdl::update::update(&ytdl, &outbound.client(), &config.ytdl).await {
+            tracing::error!("couldn't install yt-dlp: {e}");
+        }
+        ytdl::update::spawn_updates(ytdl.clone(), outbound.client(), config.ytdl.clone());
+    }
+
+    {
+        let db = db.clone();
+        let ytdl = ytdl.clone();
+        tokio::spawn(async move { doctor::run(&ytdl, &db).await.log() });
+    }
+
+    if let Some(library_path) = config.library_path.clone() {
+        let db = db.clone();
+        tokio::spawn(async move {
+            tracing::info!("indexing music library \"{}\"...", library_path.display());
+            match library::index(&db, &library_path).await {
+                Ok(stats) => tracing::info!(
+                    "indexed {} tracks, removed {} ({} couldn't be read)",
+                    stats.indexed,
+                    stats.removed,
+                    stats.failed
+                ),
+                Err(e) => tracing::error!("error while indexing music library: {e}"),
+            }
+        });
+    }
 
-    if let Err(e) = discord::start(config, db).await {
+    if let Err(e) = discord::start(config_handle, db, ytdl, outbound, credentials).await {
         tracing::error!("error while executing discord bot: {e}");
     }
+
+    Ok(())
+}
+
+/// `discomfort-fm check-config`: Also sets up what the bot would fail to start without
+fn check_config(config: &Config) -> Result<(), discord::Error> {
+    Outbound::new(&ConfigHandle::new(config.clone(), None))
+        .map_err(|e| format!("couldn't set up the HTTP clients: {e}"))?;
+    Credentials::load(config).map_err(|e| format!("couldn't load the credentials key: {e}"))?;
+
+    Ok(())
+}
+
+async fn open_database(config: &Config) -> Result<DatabaseContext, discord::Error> {
+    DatabaseContext::new(&config.database_path)
+        .await
+        .map_err(|e| e.to_string().into())
+}
+
+/// `discomfort-fm migrate`: Applies pending migrations or lists them
+async fn migrate(config: &Config, list: bool) -> Result<(), discord::Error> {
+    let db = open_database(config).await?;
+
+    if !list {
+        db.init().await.map_err(|e| e.to_string())?;
+    }
+
+    for migration in db.migrations().await? {
+        println!(
+            "{} {} {}",
+            if migration.applied { "[x]" } else { "[ ]" },
+            migration.version,
+            migration.description
+        );
+    }
+
+    Ok(())
+}
+
+/// `discomfort-fm register`: Registers the slash commands without starting the bot
+async fn register(config: &Config, scope: Scope) -> Result<(), discord::Error> {
+    let db = open_database(config).await?;
+    db.init().await.map_err(|e| e.to_string())?;
+
+    let report = registration::register(config.discord_token.expose(), &db, scope).await?;
+    tracing::info!("registered commands {scope}: {report}");
+
+    Ok(())
+}
+
+/// `discomfort-fm unregister`: Removes all registered slash commands
+async fn unregister(config: &Config, scope: Scope) -> Result<(), discord::Error> {
+    let db = open_database(config).await?;
+    db.init().await.map_err(|e| e.to_string())?;
+
+    let report = registration::unregister(config.discord_token.expose(), &db, scope).await?;
+    tracing::info!("removed commands {scope}: {report}");
+
+    Ok(())
+}
+
+/// `discomfort-fm db export`: Writes the contents of the database to a JSON file
+async fn export(config: &Config, path: &Path) -> Result<(), discord::Error> {
+    let db = open_database(config).await?;
+    let dump = db.export().await?;
+
+    let file = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(std::io::BufWriter::new(file), &dump)?;
+
+    let rows: usize = dump.tables.values().map(Vec::len).sum();
+    tracing::info!(
+        "exported {rows} rows of {} tables to \"{}\"",
+        dump.tables.len(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// `discomfort-fm db import`: Replaces the contents of the database with a JSON file from
+/// `db export`
+async fn import(config: &Config, path: &Path) -> Result<(), discord::Error> {
+    let file = std::fs::File::open(path)?;
+    let dump: Dump = serde_json::from_reader(std::io::BufReader::new(file))?;
+
+    let db = open_database(config).await?;
+    db.init().await.map_err(|e| e.to_string())?;
+    let rows = db.import(&dump).await?;
+    tracing::info!("imported {rows} rows from \"{}\"", path.display());
+
+    Ok(())
+}
+
+/// `discomfort-fm doctor`: Runs the self-check, failing if any check failed
+async fn run_doctor(config: &Config) -> Result<(), discord::Error> {
+    let db = open_database(config).await?;
+    let outbound = Outbound::new(&ConfigHandle::new(config.clone(), None))?;
+    let report = doctor::run(
+        &YtDlp::new(&config.ytdl, db.clone(), outbound.client()),
+        &db,
+    )
+    .await;
+    print!("{report}");
+
+    if !report.is_ok() {
+        return Err("some checks failed".into());
+    }
+
+    Ok(())
+}
+
+/// `discomfort-fm healthcheck`: Exits successfully if the running bot is live
+async fn healthcheck(config: &Config) -> ! {
+    // Without the HTTP server there's no way to reach the bot, which shouldn't mark it unhealthy
+    if config.http_bind.is_none() {
+        println!("HTTP_BIND isn't set, skipping the health check");
+        std::process::exit(0);
+    }
+
+    match health::run_healthcheck(config).await {
+        Ok(()) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("unhealthy: {e}");
+            std::process::exit(1);
+        }
+    }
 }
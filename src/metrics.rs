@@ -0,0 +1,328 @@
+//! Counters and histograms, exposed in the Prometheus text format via `GET /metrics`.
+//!
+//! The metrics are global, so they can be recorded from anywhere without passing them around.
+//! Gauges describing the current state (e.g. voice connections) are computed when scraping.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Executed commands, by name
+pub static COMMANDS: LabeledCounter = LabeledCounter::new();
+/// Commands which failed, by name
+pub static COMMAND_ERRORS: LabeledCounter = LabeledCounter::new();
+/// Retries of live streams after the connection to the upstream failed
+pub static STREAM_RECONNECTS: Counter = Counter::new();
+/// Bytes received from the upstreams of web radios
+pub static STREAM_BYTES: Counter = Counter::new();
+/// Bytes sent to listeners of relays
+pub static RELAY_BYTES: Counter = Counter::new();
+/// How long yt-dlp took, by operation
+pub static YTDL_DURATION: LabeledHistogram<7> =
+    LabeledHistogram::new([0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]);
+/// How long database queries took
+pub static DB_QUERY_DURATION: Histogram<9> =
+    Histogram::new([0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5]);
+
+/// Metrics describing the current state, which are gathered right before rendering
+#[derive(Debug, Clone, Copy)]
+pub struct Gauges {
+    pub voice_connections: u64,
+    pub active_tracks: u64,
+}
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct LabeledCounter(Mutex<BTreeMap<String, u64>>);
+
+impl LabeledCounter {
+    pub const fn new() -> Self {
+        Self(Mutex::new(BTreeMap::new()))
+    }
+
+    pub fn inc(&self, label: &str) {
+        let mut counts = self.0.lock();
+        match counts.get_mut(label) {
+            Some(v) => *v += 1,
+            None => {
+                counts.insert(label.to_string(), 1);
+            }
+        }
+    }
+}
+
+/// Histogram with `N` fixed buckets (upper bounds in seconds, ascending)
+pub struct Histogram<const N: usize> {
+    bounds: [f64; N],
+    state: Mutex<HistogramState<N>>,
+}
+
+#[derive(Clone, Copy)]
+struct HistogramState<const N: usize> {
+    /// Non-cumulative counts per bucket
+    buckets: [u64; N],
+    /// Observations above the highest bound
+    overflow: u64,
+    count: u64,
+    sum: f64,
+}
+
+impl<const N: usize> Histogram<N> {
+    pub const fn new(bounds: [f64; N]) -> Self {
+        Self {
+            bounds,
+            state: Mutex::new(HistogramState {
+                buckets: [0; N],
+                overflow: 0,
+                count: 0,
+                sum: 0.0,
+            }),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        let mut state = self.state.lock();
+        match self.bounds.iter().position(|v| seconds <= *v) {
+            Some(i) => state.buckets[i] += 1,
+            None => state.overflow += 1,
+        }
+        state.count += 1;
+        state.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let state = *self.state.lock();
+        let separator = if labels.is_empty() { "" } else { "," };
+
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(state.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{separator}le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {}",
+            cumulative + state.overflow
+        );
+
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", state.sum);
+        let _ = writeln!(out, "{name}_count{labels} {}", state.count);
+    }
+}
+
+pub struct LabeledHistogram<const N: usize> {
+    bounds: [f64; N],
+    histograms: Mutex<BTreeMap<&'static str, &'static Histogram<N>>>,
+}
+
+impl<const N: usize> LabeledHistogram<N> {
+    pub const fn new(bounds: [f64; N]) -> Self {
+        Self {
+            bounds,
+            histograms: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, label: &'static str, duration: Duration) {
+        // There's only a handful of labels, so leaking their histograms is fine
+        let histogram = *self
+            .histograms
+            .lock()
+            .entry(label)
+            .or_insert_with(|| Box::leak(Box::new(Histogram::new(self.bounds))));
+
+        histogram.observe(duration);
+    }
+}
+
+/// Runs `fut` and records how long it took in [`YTDL_DURATION`]
+pub async fn time_ytdl<F: Future>(operation: &'static str, fut: F) -> F::Output {
+    let start = Instant::now();
+    let result = fut.await;
+    YTDL_DURATION.observe(operation, start.elapsed());
+
+    result
+}
+
+/// Renders all metrics in the Prometheus text format
+pub fn render(gauges: Gauges) -> String {
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "discomfort_commands_total",
+        "counter",
+        "Executed commands",
+    );
+    for (command, count) in COMMANDS.0.lock().iter() {
+        let _ = writeln!(
+            out,
+            "discomfort_commands_total{{command=\"{}\"}} {count}",
+            escape_label(command)
+        );
+    }
+
+    header(
+        &mut out,
+        "discomfort_command_errors_total",
+        "counter",
+        "Commands which failed",
+    );
+    for (command, count) in COMMAND_ERRORS.0.lock().iter() {
+        let _ = writeln!(
+            out,
+            "discomfort_command_errors_total{{command=\"{}\"}} {count}",
+            escape_label(command)
+        );
+    }
+
+    header(
+        &mut out,
+        "discomfort_voice_connections",
+        "gauge",
+        "Connected voice channels",
+    );
+    let _ = writeln!(
+        out,
+        "discomfort_voice_connections {}",
+        gauges.voice_connections
+    );
+
+    header(
+        &mut out,
+        "discomfort_active_tracks",
+        "gauge",
+        "Tracks currently playing",
+    );
+    let _ = writeln!(out, "discomfort_active_tracks {}", gauges.active_tracks);
+
+    header(
+        &mut out,
+        "discomfort_stream_reconnects_total",
+        "counter",
+        "Retries after the connection to a live stream failed",
+    );
+    let _ = writeln!(
+        out,
+        "discomfort_stream_reconnects_total {}",
+        STREAM_RECONNECTS.get()
+    );
+
+    header(
+        &mut out,
+        "discomfort_stream_bytes_total",
+        "counter",
+        "Bytes received from web radio streams",
+    );
+    let _ = writeln!(out, "discomfort_stream_bytes_total {}", STREAM_BYTES.get());
+
+    header(
+        &mut out,
+        "discomfort_relay_sent_bytes_total",
+        "counter",
+        "Bytes sent to relay listeners",
+    );
+    let _ = writeln!(
+        out,
+        "discomfort_relay_sent_bytes_total {}",
+        RELAY_BYTES.get()
+    );
+
+    header(
+        &mut out,
+        "discomfort_ytdl_duration_seconds",
+        "histogram",
+        "Time yt-dlp took to run",
+    );
+    for (operation, histogram) in YTDL_DURATION.histograms.lock().iter() {
+        histogram.render(
+            &mut out,
+            "discomfort_ytdl_duration_seconds",
+            &format!("operation=\"{operation}\""),
+        );
+    }
+
+    header(
+        &mut out,
+        "discomfort_db_query_duration_seconds",
+        "histogram",
+        "Time database queries took",
+    );
+    DB_QUERY_DURATION.render(&mut out, "discomfort_db_query_duration_seconds", "");
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Records the duration of database queries, which sqlx logs for every statement
+pub struct QueryDurationLayer;
+
+impl<S: Subscriber> Layer<S> for QueryDurationLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = ElapsedVisitor(None);
+        event.record(&mut visitor);
+
+        if let Some(seconds) = visitor.0.filter(|v| v.is_finite() && *v >= 0.0) {
+            DB_QUERY_DURATION.observe(Duration::from_secs_f64(seconds));
+        }
+    }
+}
+
+struct ElapsedVisitor(Option<f64>);
+
+impl Visit for ElapsedVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "elapsed_secs" {
+            self.0 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
@@ -0,0 +1,63 @@
+//! The HTTP clients used for everything the bot fetches (streams, feeds, yt-dlp updates, ...).
+//!
+//! They're shared, so connections are reused and the proxy, timeouts and user agent apply
+//! everywhere. The [`Outbound::client`] is only used for endpoints of the config (e.g. GitHub or
+//! Discord), everything users can point the bot to goes through the [`Outbound::guarded`] one.
+
+use std::sync::Arc;
+
+use crate::config::ConfigHandle;
+use crate::discord::Error;
+use crate::url_policy::{self, PublicResolver};
+
+#[derive(Debug, Clone)]
+pub struct Outbound {
+    client: reqwest::Client,
+    guarded: reqwest::Client,
+}
+
+impl Outbound {
+    pub fn new(config: &ConfigHandle) -> Result<Self, Error> {
+        Ok(Self {
+            client: build(config, false)?,
+            guarded: build(config, true)?,
+        })
+    }
+
+    /// The shared client. Cloning it is cheap.
+    pub fn client(&self) -> reqwest::Client {
+        self.client.clone()
+    }
+
+    /// A client with the same settings, which only connects to public addresses and follows
+    /// redirects allowed by the global URL policy. URLs have to be checked against the policy of
+    /// the guild before. Credentials of streams are sent per request, via
+    /// [`reqwest::RequestBuilder::headers`].
+    pub fn guarded(&self) -> reqwest::Client {
+        self.guarded.clone()
+    }
+}
+
+fn build(handle: &ConfigHandle, guarded: bool) -> Result<reqwest::Client, Error> {
+    let config = handle.get();
+    let config = &config.outbound;
+
+    let mut builder = reqwest::Client::builder()
+        .user_agent(config.user_agent.as_str())
+        .connect_timeout(config.connect_timeout);
+
+    match &config.proxy {
+        Some(proxy) => builder = builder.proxy(reqwest::Proxy::all(proxy)?),
+        // With a proxy the proxy resolves the hosts, the resolver would only see the proxy itself
+        None => {
+            if guarded {
+                builder = builder.dns_resolver(Arc::new(PublicResolver::new(handle.clone())));
+            }
+        }
+    }
+    if guarded {
+        builder = builder.redirect(url_policy::redirect_policy(handle.clone()));
+    }
+
+    Ok(builder.build()?)
+}
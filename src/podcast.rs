@@ -0,0 +1,234 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use poise::serenity_prelude::{CreateMessage, Http};
+use reqwest::header::HeaderMap;
+use url::Url;
+
+use crate::config::ConfigHandle;
+use crate::database::actions::{podcast_update_last_episode, podcasts_get_all};
+use crate::database::DatabaseContext;
+use crate::discord::Error;
+use crate::url_policy::UrlPolicy;
+
+/// Maximum size of a feed document
+const MAX_FEED_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct PodcastFeed {
+    /// Where the feed was fetched from, after following redirects
+    pub url: Url,
+    pub title: String,
+    /// Episodes, newest first
+    pub episodes: Vec<Episode>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Episode {
+    pub id: String,
+    pub title: String,
+    /// URL of the enclosed audio file
+    pub url: String,
+    pub duration: Option<Duration>,
+    pub published: Option<DateTime<Utc>>,
+}
+
+/// Fetches and parses the RSS/Atom feed at `url`.
+///
+/// Entries without an audio enclosure are skipped.
+pub async fn fetch_feed(
+    client: &reqwest::Client,
+    policy: &UrlPolicy,
+    url: &str,
+) -> Result<PodcastFeed, Error> {
+    let mut res = policy
+        .get(client, &Url::parse(url)?, &HeaderMap::new())
+        .await?;
+    let feed_url = res.url().clone();
+
+    if res
+        .content_length()
+        .is_some_and(|v| v > MAX_FEED_SIZE as u64)
+    {
+        return Err(format!("feed \"{url}\" is too large").into());
+    }
+
+    // The announced length can't be trusted (or is missing), so stop reading once it's too much
+    let mut body = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        if body.len() + chunk.len() > MAX_FEED_SIZE {
+            return Err(format!("feed \"{url}\" is too large").into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let feed = feed_rs::parser::parse(&body[..])?;
+
+    let title = feed
+        .title
+        .map(|v| v.content)
+        .unwrap_or_else(|| url.to_string());
+
+    let mut episodes = feed
+        .entries
+        .into_iter()
+        .filter_map(|entry| {
+            let (url, duration) = find_enclosure(&entry)?;
+
+            Some(Episode {
+                title: entry
+                    .title
+                    .map(|v| v.content)
+                    .unwrap_or_else(|| url.clone()),
+                id: entry.id,
+                url,
+                duration,
+                published: entry.published.or(entry.updated),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Most feeds are sorted already, but that isn't guaranteed
+    episodes.sort_by_key(|v| Reverse(v.published));
+
+    Ok(PodcastFeed {
+        url: feed_url,
+        title,
+        episodes,
+    })
+}
+
+/// Finds the audio enclosure of a feed entry (RSS `<enclosure>` or Atom `<link rel="enclosure">`)
+fn find_enclosure(entry: &feed_rs::model::Entry) -> Option<(String, Option<Duration>)> {
+    let media = entry.media.iter().find_map(|media| {
+        let content = media.content.iter().find(|content| {
+            content
+                .content_type
+                .as_ref()
+                .map(|v| v.as_str().starts_with("audio/"))
+                .unwrap_or(true)
+                && content.url.is_some()
+        })?;
+
+        Some((
+            content.url.as_ref()?.to_string(),
+            content.duration.or(media.duration),
+        ))
+    });
+
+    media.or_else(|| {
+        entry
+            .links
+            .iter()
+            .find(|link| {
+                link.rel.as_deref() == Some("enclosure")
+                    && link
+                        .media_type
+                        .as_deref()
+                        .map(|v| v.starts_with("audio/"))
+                        .unwrap_or(true)
+            })
+            .map(|link| (link.href.clone(), None))
+    })
+}
+
+/// Periodically fetches all subscribed feeds and announces new episodes
+pub fn spawn_feed_refresh(
+    http: Arc<Http>,
+    db: DatabaseContext,
+    config: ConfigHandle,
+    client: reqwest::Client,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = refresh_feeds(&http, &db, &config, &client).await {
+                tracing::error!("error while refreshing podcast feeds: {e}");
+            }
+        }
+    });
+}
+
+async fn refresh_feeds(
+    http: &Http,
+    db: &DatabaseContext,
+    config: &ConfigHandle,
+    client: &reqwest::Client,
+) -> Result<(), Error> {
+    let mut conn = db.get_connection().await?;
+    let podcasts = podcasts_get_all(&mut conn).await?;
+    let config = config.get();
+    let global_policy = UrlPolicy::global(&config);
+
+    // Multiple guilds can be subscribed to the same feed, only fetch it once
+    let mut feeds = HashMap::new();
+
+    for podcast in podcasts {
+        if !feeds.contains_key(&podcast.feed_url) {
+            let feed = match fetch_feed(client, &global_policy, &podcast.feed_url).await {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    tracing::warn!("couldn't fetch podcast feed \"{}\": {e}", podcast.feed_url);
+                    None
+                }
+            };
+            feeds.insert(podcast.feed_url.clone(), feed);
+        }
+
+        let Some(Some(feed)) = feeds.get(&podcast.feed_url) else {
+            continue;
+        };
+        // The feed is shared, so it was only fetched with the global policy
+        let policy = UrlPolicy::for_guild(&config, db, podcast.guild_id).await?;
+        if let Err(e) = policy.check_static(&feed.url) {
+            tracing::debug!(
+                "not refreshing podcast feed \"{}\" for guild {}: {e}",
+                podcast.feed_url,
+                podcast.guild_id
+            );
+            continue;
+        }
+        let Some(latest) = feed.episodes.first() else {
+            continue;
+        };
+
+        if podcast.last_episode_id.as_deref() == Some(latest.id.as_str()) {
+            continue;
+        }
+
+        tracing::debug!(
+            "new episode \"{}\" of podcast \"{}\" (guild {})",
+            latest.title,
+            feed.title,
+            podcast.guild_id
+        );
+
+        podcast_update_last_episode(&mut conn, &podcast.id, &latest.id).await?;
+
+        // Don't announce the existing episodes of freshly subscribed feeds
+        if podcast.last_episode_id.is_none() {
+            continue;
+        }
+
+        if let Some(channel_id) = podcast.announce_channel_id {
+            let message = CreateMessage::new().content(format!(
+                "New episode of **{}**: {}\nPlay it via `/podcast play`",
+                feed.title, latest.title
+            ));
+
+            if let Err(e) = channel_id.send_message(http, message).await {
+                tracing::warn!("couldn't announce new episode in channel {channel_id}: {e}");
+            }
+        }
+    }
+
+    Ok(())
+}
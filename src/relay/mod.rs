@@ -0,0 +1,244 @@
+//! Re-serves what the bot plays in a guild as an Ogg/Opus stream.
+//!
+//! Tracks of guilds with an enabled relay are decoded by a [`RelayTap`] instead of songbird, which
+//! hands the PCM (after applying the volume) to the [`GuildRelay`]. It is encoded once and
+//! broadcast to every listener connected via HTTP (see `crate::http::relay`).
+//!
+//! Ogg/Opus streams eligible for passthrough aren't decoded, a [`PassthroughTap`] copies their
+//! packets to the relay while songbird reads them.
+
+mod ogg;
+mod tap;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use audiopus::coder::{Decoder, Encoder};
+use audiopus::{Application, Bitrate, Channels, SampleRate};
+use parking_lot::{Mutex, RwLock};
+use poise::serenity_prelude::GuildId;
+use songbird::input::Input;
+use tokio::sync::broadcast;
+
+pub use ogg::OggOpusWriter;
+use tap::{PassthroughTap, RelayTap};
+
+use crate::database::actions::relay_tokens_get_all;
+use crate::database::DatabaseContext;
+use crate::discord::tracks::TrackInfo;
+use crate::discord::Error;
+use crate::http::tokens_match;
+
+/// Relayed audio is always 48 kHz stereo, like the audio sent to Discord
+pub const SAMPLE_RATE: u32 = 48_000;
+pub const CHANNELS: usize = 2;
+/// Samples per channel in a 20 ms Opus frame
+pub const FRAME_SAMPLES: usize = 960;
+
+const BITRATE: i32 = 128_000;
+/// Number of Opus packets buffered per listener (5 seconds)
+const LISTENER_BUFFER: usize = 250;
+/// Upper bound of an encoded Opus packet
+const MAX_PACKET_SIZE: usize = 4000;
+/// Samples per channel in the longest possible Opus packet (120 ms)
+const MAX_PACKET_SAMPLES: usize = 5760;
+
+/// Relays of all guilds which have one enabled
+#[derive(Clone, Default)]
+pub struct Relays {
+    guilds: Arc<RwLock<HashMap<GuildId, Arc<GuildRelay>>>>,
+}
+
+pub struct GuildRelay {
+    token: Mutex<String>,
+    /// Set once the relay was disabled, which disconnects all listeners
+    closed: AtomicBool,
+    /// Volume as `f32` bits
+    volume: AtomicU32,
+    title: Mutex<Option<String>>,
+
+    encoder: Mutex<FrameEncoder>,
+    /// Decodes passed through packets when the volume has to be applied
+    decoder: Mutex<Decoder>,
+    tx: broadcast::Sender<Arc<[u8]>>,
+}
+
+/// Collects PCM until a full frame can be encoded
+struct FrameEncoder {
+    encoder: Encoder,
+    pending: Vec<f32>,
+}
+
+impl Relays {
+    /// Loads the relays of all guilds with a token
+    pub async fn load(db: &DatabaseContext) -> Result<Self, Error> {
+        let mut conn = db.get_connection().await?;
+        let relays = Self::default();
+
+        for row in relay_tokens_get_all(&mut conn).await? {
+            relays.enable(row.guild_id, row.token)?;
+        }
+
+        Ok(relays)
+    }
+
+    pub fn get(&self, guild_id: GuildId) -> Option<Arc<GuildRelay>> {
+        self.guilds.read().get(&guild_id).cloned()
+    }
+
+    /// Enables the relay of a guild or replaces its token, which disconnects current listeners
+    pub fn enable(&self, guild_id: GuildId, token: String) -> Result<(), Error> {
+        if let Some(relay) = self.get(guild_id) {
+            *relay.token.lock() = token;
+            return Ok(());
+        }
+
+        let relay = GuildRelay::new(token)?;
+        self.guilds.write().insert(guild_id, Arc::new(relay));
+
+        Ok(())
+    }
+
+    /// Returns `false` if the guild didn't have a relay
+    pub fn disable(&self, guild_id: GuildId) -> bool {
+        let Some(relay) = self.guilds.write().remove(&guild_id) else {
+            return false;
+        };
+
+        relay.closed.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Routes `input` through the relay of the guild, if it has one.
+    ///
+    /// This has to be applied to every track played in the guild. Tracks eligible for Opus
+    /// passthrough reach songbird unchanged, so it can still pass their frames through to Discord.
+    pub fn tap(&self, guild_id: GuildId, input: Input, track_info: &TrackInfo) -> Input {
+        let Some(relay) = self.get(guild_id) else {
+            return input;
+        };
+        let title = Some(track_info.display_name().to_string());
+
+        if track_info.opus_passthrough {
+            tracing::debug!("relay of guild {guild_id} copies the Opus packets of {title:?}");
+            PassthroughTap::new(input, relay, title).into()
+        } else {
+            tracing::debug!("relay of guild {guild_id} decodes {title:?}");
+            RelayTap::new(input, relay, title).into()
+        }
+    }
+
+    /// Updates the volume applied to the relayed audio. `volume` is `1.0` for 100%
+    pub fn set_volume(&self, guild_id: GuildId, volume: f32) {
+        if let Some(relay) = self.get(guild_id) {
+            relay.volume.store(volume.to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+impl GuildRelay {
+    fn new(token: String) -> Result<Self, Error> {
+        let mut encoder = Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio)?;
+        encoder.set_bitrate(Bitrate::BitsPerSecond(BITRATE))?;
+
+        let (tx, _) = broadcast::channel(LISTENER_BUFFER);
+
+        Ok(Self {
+            token: Mutex::new(token),
+            closed: AtomicBool::new(false),
+            volume: AtomicU32::new(1.0f32.to_bits()),
+            title: Mutex::new(None),
+            encoder: Mutex::new(FrameEncoder {
+                encoder,
+                pending: Vec::with_capacity(FRAME_SAMPLES * CHANNELS * 2),
+            }),
+            decoder: Mutex::new(Decoder::new(SampleRate::Hz48000, Channels::Stereo)?),
+            tx,
+        })
+    }
+
+    /// Checks `token` against the current token of the relay
+    pub fn is_authorized(&self, token: &str) -> bool {
+        if self.closed.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        tokens_match(&self.token.lock(), token)
+    }
+
+    /// Receives the encoded Opus packets (20 ms each)
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<[u8]>> {
+        self.tx.subscribe()
+    }
+
+    /// Title of the track which is currently played
+    pub fn title(&self) -> Option<String> {
+        self.title.lock().clone()
+    }
+
+    fn set_title(&self, title: Option<String>) {
+        *self.title.lock() = title;
+    }
+
+    /// Encodes interleaved 48 kHz stereo samples for the listeners
+    fn push_pcm(&self, samples: &[f32]) {
+        let mut encoder = self.encoder.lock();
+
+        // Nobody is listening, don't waste any time on encoding
+        if self.tx.receiver_count() == 0 {
+            encoder.pending.clear();
+            return;
+        }
+
+        let volume = f32::from_bits(self.volume.load(Ordering::Relaxed));
+        encoder
+            .pending
+            .extend(samples.iter().map(|v| (v * volume).clamp(-1.0, 1.0)));
+
+        let frame_len = FRAME_SAMPLES * CHANNELS;
+        let mut packet = [0u8; MAX_PACKET_SIZE];
+        let mut offset = 0;
+
+        while encoder.pending.len() - offset >= frame_len {
+            let frame = &encoder.pending[offset..offset + frame_len];
+            offset += frame_len;
+
+            match encoder.encoder.encode_float(frame, &mut packet) {
+                Ok(len) => drop(self.tx.send(Arc::from(&packet[..len]))),
+                Err(e) => tracing::warn!("couldn't encode relay frame: {e}"),
+            }
+        }
+
+        encoder.pending.drain(..offset);
+    }
+
+    /// Broadcasts a packet of a track which songbird passes through to Discord.
+    ///
+    /// The packet is sent unchanged, unless the volume has to be applied to it.
+    fn push_opus(&self, packet: &[u8]) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+
+        let volume = f32::from_bits(self.volume.load(Ordering::Relaxed));
+        if (volume - 1.0).abs() < f32::EPSILON {
+            self.encoder.lock().pending.clear();
+            drop(self.tx.send(Arc::from(packet)));
+            return;
+        }
+
+        let mut pcm = vec![0.0; MAX_PACKET_SAMPLES * CHANNELS];
+        let decoded = packet.try_into().and_then(|packet| {
+            let output = pcm.as_mut_slice().try_into()?;
+            self.decoder
+                .lock()
+                .decode_float(Some(packet), output, false)
+        });
+
+        match decoded {
+            Ok(samples) => self.push_pcm(&pcm[..samples * CHANNELS]),
+            Err(e) => tracing::debug!("skipping broken packet of relayed track: {e}"),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Minimal Ogg muxer and demuxer for Opus packets (RFC 7845)
+//!
+//! ICY metadata can't be part of the Ogg pages, so the title is a `TITLE` comment in the
+//! `OpusTags` header as well. When it changes, the current logical stream ends and a new one with
+//! the new tags is chained to it, like Icecast does for Ogg mounts.
+
+use super::{CHANNELS, FRAME_SAMPLES, SAMPLE_RATE};
+
+/// Samples the decoder should drop at the start of the stream
+const PRE_SKIP: u16 = 312;
+const VENDOR: &str = "discomfort.fm";
+/// Keeps the `OpusTags` packet well below the 64 KB a single page can hold
+const MAX_TITLE_CHARS: usize = 1000;
+
+const FLAG_CONTINUED: u8 = 0x01;
+const FLAG_BOS: u8 = 0x02;
+const FLAG_EOS: u8 = 0x04;
+const PAGE_HEADER_LEN: usize = 27;
+/// `OpusHead` and `OpusTags` start every logical stream
+const HEADER_PACKETS: usize = 2;
+
+/// Wraps Opus packets into Ogg pages, one packet per page.
+///
+/// Every listener gets its own writer, as the page sequence and granule position start at the
+/// beginning of each stream.
+pub struct OggOpusWriter {
+    serial: u32,
+    sequence: u32,
+    granule: u64,
+    pre_skip: u16,
+}
+
+impl OggOpusWriter {
+    pub fn new(serial: u32) -> Self {
+        Self {
+            serial,
+            sequence: 0,
+            granule: 0,
+            pre_skip: PRE_SKIP,
+        }
+    }
+
+    /// The `OpusHead` and `OpusTags` pages which have to start the (logical) stream
+    pub fn headers(&mut self, title: Option<&str>) -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // version
+        head.push(CHANNELS as u8);
+        head.extend_from_slice(&self.pre_skip.to_le_bytes());
+        head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+
+        let comments = title
+            .map(|v| {
+                format!(
+                    "TITLE={}",
+                    v.chars().take(MAX_TITLE_CHARS).collect::<String>()
+                )
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+        tags.extend_from_slice(VENDOR.as_bytes());
+        tags.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in &comments {
+            tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            tags.extend_from_slice(comment.as_bytes());
+        }
+
+        let mut out = self.page(&head, FLAG_BOS, 0);
+        out.extend(self.page(&tags, 0x00, 0));
+        out
+    }
+
+    /// A page containing a single 20 ms Opus packet
+    pub fn packet(&mut self, packet: &[u8]) -> Vec<u8> {
+        self.granule += FRAME_SAMPLES as u64;
+        self.page(packet, 0x00, self.granule)
+    }
+
+    /// Ends the logical stream with `packet` and chains a new one, whose tags contain `title`
+    pub fn chain(&mut self, packet: &[u8], title: Option<&str>) -> Vec<u8> {
+        self.granule += FRAME_SAMPLES as u64;
+        let mut out = self.page(packet, FLAG_EOS, self.granule);
+
+        // Chained streams need a different serial
+        self.serial = self.serial.wrapping_add(1);
+        self.sequence = 0;
+        self.granule = 0;
+        // The encoder keeps running, so there's nothing to skip in the following streams
+        self.pre_skip = 0;
+
+        out.extend(self.headers(title));
+        out
+    }
+
+    fn page(&mut self, data: &[u8], header_type: u8, granule: u64) -> Vec<u8> {
+        // Packets are split into segments of 255 bytes, a shorter segment ends the packet
+        let mut lacing = vec![255u8; data.len() / 255];
+        lacing.push((data.len() % 255) as u8);
+
+        let mut page = Vec::with_capacity(27 + lacing.len() + data.len());
+        page.extend_from_slice(b"OggS");
+        page.push(0); // version
+        page.push(header_type);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&0u32.to_le_bytes()); // checksum, filled in below
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        page.extend_from_slice(data);
+
+        let checksum = crc32(&page);
+        page[22..26].copy_from_slice(&checksum.to_le_bytes());
+
+        self.sequence += 1;
+        page
+    }
+}
+
+/// Extracts the audio packets of an Ogg/Opus stream which is fed in arbitrary chunks.
+///
+/// Only meant for streams which are demuxed properly somewhere else, so the checksums aren't
+/// verified and a broken page only costs the packets in it.
+#[derive(Default)]
+pub struct OggOpusReader {
+    buf: Vec<u8>,
+    /// Start of the unparsed data in `buf`
+    pos: usize,
+    /// Packet continued on the next page
+    partial: Vec<u8>,
+    /// Header packets of the current logical stream which are still to be skipped
+    headers_left: usize,
+}
+
+impl OggOpusReader {
+    /// Adds `data` and calls `on_packet` for every Opus packet which is complete now
+    pub fn push(&mut self, data: &[u8], mut on_packet: impl FnMut(&[u8])) {
+        self.buf.extend_from_slice(data);
+
+        while let Some(len) = self.next_page(&mut on_packet) {
+            self.pos += len;
+        }
+
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+    }
+
+    /// Parses the page at `pos`, returns its length or `None` if it isn't complete yet
+    fn next_page(&mut self, on_packet: &mut impl FnMut(&[u8])) -> Option<usize> {
+        let data = &self.buf[self.pos..];
+
+        // Skip to the next capture pattern, e.g. when starting in the middle of a stream
+        let Some(start) = data.windows(4).position(|v| v == b"OggS") else {
+            // The capture pattern might be split between two chunks
+            let skip = data.len().saturating_sub(3);
+            return (skip > 0).then_some(skip);
+        };
+        if start > 0 {
+            self.partial.clear();
+            return Some(start);
+        }
+
+        let header = data.get(..PAGE_HEADER_LEN)?;
+        let header_type = header[5];
+        let lacing = data.get(PAGE_HEADER_LEN..PAGE_HEADER_LEN + header[26] as usize)?;
+        let body_len = lacing.iter().map(|v| *v as usize).sum::<usize>();
+        let page_len = PAGE_HEADER_LEN + lacing.len() + body_len;
+        let body = data.get(PAGE_HEADER_LEN + lacing.len()..page_len)?;
+
+        if header_type & FLAG_BOS != 0 {
+            self.headers_left = HEADER_PACKETS;
+        }
+        if header_type & FLAG_CONTINUED == 0 {
+            self.partial.clear();
+        }
+
+        let mut offset = 0;
+        for len in lacing {
+            self.partial
+                .extend_from_slice(&body[offset..offset + *len as usize]);
+            offset += *len as usize;
+
+            // A segment shorter than 255 bytes ends the packet
+            if *len < 255 {
+                if self.headers_left > 0 {
+                    self.headers_left -= 1;
+                } else if !self.partial.is_empty() {
+                    on_packet(&self.partial);
+                }
+                self.partial.clear();
+            }
+        }
+
+        Some(page_len)
+    }
+}
+
+/// CRC-32 as used by Ogg (polynomial 0x04C11DB7, no reflection, no final XOR)
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |crc, byte| {
+        let mut crc = crc ^ ((*byte as u32) << 24);
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(data: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+        let mut reader = OggOpusReader::default();
+        let mut packets = Vec::new();
+        for chunk in data.chunks(chunk_size) {
+            reader.push(chunk, |v| packets.push(v.to_vec()));
+        }
+        packets
+    }
+
+    #[test]
+    fn packets() {
+        let packets = [vec![1; 3], vec![2; 255], vec![3; 600], vec![4; 100]];
+
+        let mut writer = OggOpusWriter::new(1);
+        let mut data = b"garbage from the middle of a page".to_vec();
+        data.extend(writer.headers(Some("First")));
+        data.extend(writer.packet(&packets[0]));
+        data.extend(writer.packet(&packets[1]));
+        data.extend(writer.chain(&packets[2], Some("Second")));
+        data.extend(writer.packet(&packets[3]));
+
+        for chunk_size in [1, 7, 4096] {
+            assert_eq!(read_all(&data, chunk_size), packets, "{chunk_size}");
+        }
+    }
+
+    #[test]
+    fn packet_across_pages() {
+        let mut writer = OggOpusWriter::new(1);
+        let mut data = writer.headers(None);
+
+        // A packet of 300 bytes, split after its first segment
+        let mut first = writer.page(&[5; 255], 0x00, 0);
+        // Without the terminating empty segment
+        first[26] = 1;
+        first.remove(PAGE_HEADER_LEN + 1);
+        data.extend(first);
+        data.extend(writer.page(&[5; 45], FLAG_CONTINUED, 960));
+
+        assert_eq!(read_all(&data, 100), [vec![5; 300]]);
+    }
+}
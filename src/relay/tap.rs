@@ -0,0 +1,527 @@
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use songbird::input::codecs::{CODEC_REGISTRY, PROBE};
+use songbird::input::{
+    AudioStream, AudioStreamError, Compose, Input, LiveInput, Parsed, RawAdapter,
+};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{SeekMode, SeekTo};
+use symphonia::core::io::MediaSource;
+use symphonia::core::units::Time;
+
+use super::ogg::OggOpusReader;
+use super::{GuildRelay, CHANNELS, SAMPLE_RATE};
+
+/// Size of the header `RawAdapter` puts in front of the samples
+const RAW_HEADER_LEN: u64 = 16;
+const BYTES_PER_FRAME: u64 = (CHANNELS * std::mem::size_of::<f32>()) as u64;
+
+/// Lazy input which decodes another input itself, so the audio can be relayed
+pub struct RelayTap {
+    /// Lazy inputs are kept, so songbird can recreate the tap (e.g. to seek backwards)
+    inner: Option<Input>,
+    relay: Arc<GuildRelay>,
+    title: Option<String>,
+}
+
+impl RelayTap {
+    pub fn new(inner: Input, relay: Arc<GuildRelay>, title: Option<String>) -> Self {
+        Self {
+            inner: Some(inner),
+            relay,
+            title,
+        }
+    }
+
+    async fn create_parsed(&mut self) -> Result<Parsed, AudioStreamError> {
+        let input = match &mut self.inner {
+            Some(Input::Lazy(compose)) => {
+                let stream = if compose.should_create_async() {
+                    compose.create_async().await?
+                } else {
+                    compose.create()?
+                };
+                Input::Live(LiveInput::Raw(stream), None)
+            }
+            Some(Input::Live(..)) => self.inner.take().expect("checked above"),
+            None => return Err(AudioStreamError::Unsupported),
+        };
+
+        let input = input
+            .make_playable_async(&CODEC_REGISTRY, &PROBE)
+            .await
+            .map_err(|e| AudioStreamError::Fail(e.to_string().into()))?;
+
+        match input {
+            Input::Live(LiveInput::Parsed(parsed), _) => Ok(parsed),
+            _ => Err(AudioStreamError::Fail("input wasn't parsed".into())),
+        }
+    }
+}
+
+#[async_trait]
+impl Compose for RelayTap {
+    fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        Err(AudioStreamError::Unsupported)
+    }
+
+    async fn create_async(
+        &mut self,
+    ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        let parsed = self.create_parsed().await?;
+
+        let source = TapSource {
+            parsed,
+            relay: self.relay.clone(),
+            title: self.title.clone(),
+            started: false,
+            sample_buf: None,
+            resampler: Resampler::default(),
+            pcm: Vec::new(),
+            out: Vec::new(),
+            pos: 0,
+        };
+
+        Ok(AudioStream {
+            input: Box::new(RawAdapter::new(source, SAMPLE_RATE, CHANNELS as u32)),
+            hint: None,
+        })
+    }
+
+    fn should_create_async(&self) -> bool {
+        true
+    }
+}
+
+impl From<RelayTap> for Input {
+    fn from(val: RelayTap) -> Self {
+        Input::Lazy(Box::new(val))
+    }
+}
+
+/// Lazy input which copies the packets of an Ogg/Opus stream to the relay as songbird reads them.
+///
+/// Nothing is decoded, songbird gets the stream as it is and can still pass its frames through to
+/// Discord.
+pub struct PassthroughTap {
+    inner: Option<Input>,
+    relay: Arc<GuildRelay>,
+    title: Option<String>,
+}
+
+impl PassthroughTap {
+    pub fn new(inner: Input, relay: Arc<GuildRelay>, title: Option<String>) -> Self {
+        Self {
+            inner: Some(inner),
+            relay,
+            title,
+        }
+    }
+}
+
+#[async_trait]
+impl Compose for PassthroughTap {
+    fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        Err(AudioStreamError::Unsupported)
+    }
+
+    async fn create_async(
+        &mut self,
+    ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        let stream = match &mut self.inner {
+            Some(Input::Lazy(compose)) => {
+                if compose.should_create_async() {
+                    compose.create_async().await?
+                } else {
+                    compose.create()?
+                }
+            }
+            Some(Input::Live(LiveInput::Raw(_), _)) => match self.inner.take() {
+                Some(Input::Live(LiveInput::Raw(stream), _)) => stream,
+                _ => unreachable!("checked above"),
+            },
+            _ => return Err(AudioStreamError::Unsupported),
+        };
+
+        let source = PassthroughSource {
+            inner: stream.input,
+            relay: self.relay.clone(),
+            title: self.title.clone(),
+            started: false,
+            reader: OggOpusReader::default(),
+        };
+
+        Ok(AudioStream {
+            input: Box::new(source),
+            hint: stream.hint,
+        })
+    }
+
+    fn should_create_async(&self) -> bool {
+        true
+    }
+}
+
+impl From<PassthroughTap> for Input {
+    fn from(val: PassthroughTap) -> Self {
+        Input::Lazy(Box::new(val))
+    }
+}
+
+/// Passes the bytes of the stream on to songbird, handing each complete Opus packet to the relay
+struct PassthroughSource {
+    inner: Box<dyn MediaSource>,
+    relay: Arc<GuildRelay>,
+    title: Option<String>,
+    started: bool,
+    reader: OggOpusReader,
+}
+
+impl Read for PassthroughSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+
+        let Self {
+            relay,
+            title,
+            started,
+            reader,
+            ..
+        } = self;
+        reader.push(&buf[..len], |packet| {
+            if !*started {
+                *started = true;
+                relay.set_title(title.clone());
+            }
+            relay.push_opus(packet);
+        });
+
+        Ok(len)
+    }
+}
+
+/// Only live streams are passed through, so seeking isn't supported. Otherwise symphonia would
+/// also read the end of the stream while probing it, which would end up at the relay.
+impl Seek for PassthroughSource {
+    fn seek(&mut self, _pos: SeekFrom) -> std::io::Result<u64> {
+        Err(IoErrorKind::Unsupported.into())
+    }
+}
+
+impl MediaSource for PassthroughSource {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Decodes the wrapped input into 48 kHz stereo `f32` samples, which are read by songbird and
+/// handed to the relay at the same time.
+///
+/// Songbird reads at playback speed, so the relay gets the audio in real time.
+struct TapSource {
+    parsed: Parsed,
+    relay: Arc<GuildRelay>,
+    title: Option<String>,
+    started: bool,
+
+    sample_buf: Option<SampleBuffer<f32>>,
+    resampler: Resampler,
+    /// Samples of the last decoded packet
+    pcm: Vec<f32>,
+    /// `pcm` as little-endian bytes
+    out: Vec<u8>,
+    pos: usize,
+}
+
+impl TapSource {
+    /// Decodes the next packet. Returns `false` at the end of the input
+    fn decode_next(&mut self) -> Result<bool, IoError> {
+        loop {
+            let packet = match self.parsed.format.next_packet() {
+                Ok(v) => v,
+                Err(SymphoniaError::IoError(e)) if e.kind() == IoErrorKind::UnexpectedEof => {
+                    return Ok(false)
+                }
+                Err(e) => return Err(IoError::other(e)),
+            };
+            if packet.track_id() != self.parsed.track_id {
+                continue;
+            }
+
+            let decoded = match self.parsed.decoder.decode(&packet) {
+                Ok(v) => v,
+                Err(SymphoniaError::DecodeError(e)) => {
+                    tracing::debug!("skipping broken packet of relayed track: {e}");
+                    continue;
+                }
+                Err(e) => return Err(IoError::other(e)),
+            };
+
+            let spec = *decoded.spec();
+            let samples_needed = decoded.capacity() * spec.channels.count();
+            let sample_buf = match &mut self.sample_buf {
+                Some(buf) if buf.capacity() >= samples_needed => buf,
+                buf => buf.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+            };
+            sample_buf.copy_interleaved_ref(decoded);
+
+            self.resampler.process(
+                sample_buf.samples(),
+                spec.channels.count(),
+                spec.rate,
+                &mut self.pcm,
+            );
+
+            if !self.started {
+                self.started = true;
+                self.relay.set_title(self.title.clone());
+            }
+            self.relay.push_pcm(&self.pcm);
+
+            self.out.clear();
+            self.out
+                .extend(self.pcm.iter().flat_map(|v| v.to_le_bytes()));
+            self.pos = 0;
+
+            return Ok(true);
+        }
+    }
+}
+
+impl Read for TapSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.out.len() {
+            if !self.decode_next()? {
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.out.len() - self.pos);
+        buf[..len].copy_from_slice(&self.out[self.pos..self.pos + len]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
+impl Seek for TapSource {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        // `RawAdapter` passes on positions including its header
+        let SeekFrom::Start(pos) = pos else {
+            return Err(IoErrorKind::Unsupported.into());
+        };
+        let pos = pos.saturating_sub(RAW_HEADER_LEN);
+
+        let frame = pos / BYTES_PER_FRAME;
+        let time = Time::new(
+            frame / SAMPLE_RATE as u64,
+            (frame % SAMPLE_RATE as u64) as f64 / SAMPLE_RATE as f64,
+        );
+
+        self.parsed
+            .format
+            .seek(
+                SeekMode::Coarse,
+                SeekTo::Time {
+                    time,
+                    track_id: Some(self.parsed.track_id),
+                },
+            )
+            .map_err(IoError::other)?;
+
+        self.parsed.decoder.reset();
+        self.resampler = Resampler::default();
+        self.out.clear();
+        self.pos = 0;
+
+        Ok(frame * BYTES_PER_FRAME)
+    }
+}
+
+impl MediaSource for TapSource {
+    fn is_seekable(&self) -> bool {
+        self.parsed.supports_backseek
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Converts audio to 48 kHz stereo via linear interpolation
+struct Resampler {
+    /// Last frame of the previous input
+    last: [f32; 2],
+    /// Position of the next output frame, relative to `last`
+    position: f64,
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Self {
+            last: [0.0; 2],
+            position: 1.0,
+        }
+    }
+}
+
+impl Resampler {
+    fn process(&mut self, input: &[f32], channels: usize, rate: u32, out: &mut Vec<f32>) {
+        out.clear();
+
+        let channels = channels.max(1);
+        let frames = input.len() / channels;
+        let frame = |i: usize| -> [f32; 2] {
+            let left = input[i * channels];
+            let right = if channels > 1 {
+                input[i * channels + 1]
+            } else {
+                left
+            };
+            [left, right]
+        };
+
+        if frames == 0 {
+            return;
+        }
+
+        if rate == SAMPLE_RATE {
+            out.extend((0..frames).flat_map(frame));
+            self.last = frame(frames - 1);
+            return;
+        }
+
+        // Index 0 is the last frame of the previous input, index `i + 1` is frame `i` of `input`
+        let last = self.last;
+        let extended = |i: usize| if i == 0 { last } else { frame(i - 1) };
+        let step = rate as f64 / SAMPLE_RATE as f64;
+
+        while self.position < frames as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let (a, b) = (extended(index), extended(index + 1));
+
+            out.push(a[0] + (b[0] - a[0]) * fraction);
+            out.push(a[1] + (b[1] - a[1]) * fraction);
+
+            self.position += step;
+        }
+
+        self.position -= frames as f64;
+        self.last = frame(frames - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+    use std::io::Cursor;
+
+    use audiopus::coder::Encoder;
+    use audiopus::{Application, Channels, SampleRate};
+    use symphonia::core::codecs::CODEC_TYPE_OPUS;
+
+    use super::super::{OggOpusWriter, FRAME_SAMPLES};
+    use super::*;
+
+    /// An Ogg/Opus stream of a 440 Hz sine, and its packets
+    fn sine_stream(frames: usize) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let encoder =
+            Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio).unwrap();
+        let mut writer = OggOpusWriter::new(1);
+        let mut data = writer.headers(Some("Sine"));
+        let mut packets = Vec::new();
+
+        for frame in 0..frames {
+            let pcm = (0..FRAME_SAMPLES)
+                .map(|i| (frame * FRAME_SAMPLES + i) as f32 * 440.0 / SAMPLE_RATE as f32)
+                .flat_map(|t| [(t * TAU).sin() * 0.5; CHANNELS])
+                .collect::<Vec<_>>();
+            let mut packet = [0; 4000];
+            let len = encoder.encode_float(&pcm, &mut packet).unwrap();
+
+            data.extend(writer.packet(&packet[..len]));
+            packets.push(packet[..len].to_vec());
+        }
+
+        (data, packets)
+    }
+
+    fn raw_input(data: Vec<u8>) -> Input {
+        let stream = AudioStream {
+            input: Box::new(Cursor::new(data)) as Box<dyn MediaSource>,
+            hint: None,
+        };
+        Input::Live(LiveInput::Raw(stream), None)
+    }
+
+    /// Reads the packets like the mixer of songbird does, returns them and whether songbird can
+    /// pass them through
+    async fn play(input: Input) -> (Vec<Vec<u8>>, bool) {
+        let input = input
+            .make_playable_async(&CODEC_REGISTRY, &PROBE)
+            .await
+            .unwrap();
+        let Input::Live(LiveInput::Parsed(mut parsed), _) = input else {
+            panic!("input wasn't parsed");
+        };
+
+        let is_opus = parsed.decoder.codec_params().codec == CODEC_TYPE_OPUS;
+        let mut packets = Vec::new();
+        while let Ok(packet) = parsed.format.next_packet() {
+            packets.push(packet.buf().to_vec());
+        }
+
+        (packets, is_opus)
+    }
+
+    #[tokio::test]
+    async fn passthrough() {
+        let (data, packets) = sine_stream(100);
+        let relay = Arc::new(GuildRelay::new("token".to_string()).unwrap());
+        let mut rx = relay.subscribe();
+
+        let tap = PassthroughTap::new(raw_input(data), relay.clone(), Some("Title".into()));
+        let (played, is_opus) = play(tap.into()).await;
+
+        // Songbird passes the frames of Opus tracks through, without touching its decoder
+        assert!(is_opus);
+        assert_eq!(played, packets);
+
+        let mut relayed = Vec::new();
+        while let Ok(packet) = rx.try_recv() {
+            relayed.push(packet.to_vec());
+        }
+        assert_eq!(relayed, packets);
+        assert_eq!(relay.title().as_deref(), Some("Title"));
+    }
+
+    #[tokio::test]
+    async fn passthrough_with_volume() {
+        let (data, packets) = sine_stream(100);
+        let relay = Arc::new(GuildRelay::new("token".to_string()).unwrap());
+        relay
+            .volume
+            .store(0.5f32.to_bits(), std::sync::atomic::Ordering::Relaxed);
+        let mut rx = relay.subscribe();
+
+        let tap = PassthroughTap::new(raw_input(data), relay, None);
+        let (played, _) = play(tap.into()).await;
+        assert_eq!(played, packets);
+
+        // The relay has to apply the volume, so its packets are encoded again
+        let mut relayed = Vec::new();
+        while let Ok(packet) = rx.try_recv() {
+            relayed.push(packet.to_vec());
+        }
+        assert_eq!(relayed.len(), packets.len());
+        assert_ne!(relayed, packets);
+    }
+}
@@ -0,0 +1,88 @@
+//! Starts playing at the times configured via the dashboard
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{Datelike, Local};
+use songbird::Songbird;
+use url::Url;
+
+use crate::database::actions::{schedule_update_last_run, schedules_get_all};
+use crate::database::ScheduleRow;
+use crate::discord::player::{join_channel, play_only, webradio_input, WebradioInput};
+use crate::discord::{Data, Error};
+
+/// How often the schedules are checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Schedules which were missed for longer than this (e.g. because the bot was offline) are skipped
+const MAX_DELAY: chrono::Duration = chrono::Duration::minutes(5);
+
+/// Days of the week in the order of the bits in [`ScheduleRow::weekdays`]
+pub const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+pub fn spawn_scheduler(data: Data, songbird: Arc<Songbird>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = run_due_schedules(&data, &songbird).await {
+                tracing::error!("error while running schedules: {e}");
+            }
+        }
+    });
+}
+
+async fn run_due_schedules(data: &Data, songbird: &Songbird) -> Result<(), Error> {
+    let now = Local::now().naive_local();
+    let today = now.date();
+
+    let mut conn = data.database.get_connection().await?;
+
+    for schedule in schedules_get_all(&mut conn).await? {
+        let delay = now - today.and_time(schedule.time);
+        let is_due = schedule.runs_on(today.weekday())
+            && schedule.last_run != Some(today)
+            && delay >= chrono::Duration::zero()
+            && delay <= MAX_DELAY;
+        if !is_due {
+            continue;
+        }
+
+        // Marked first, so a failing schedule isn't retried every few seconds
+        schedule_update_last_run(&mut conn, &schedule.id, today).await?;
+
+        tracing::info!(
+            "starting schedule {} in guild {}",
+            schedule.id,
+            schedule.guild_id
+        );
+        if let Err(e) = run_schedule(data, songbird, &schedule).await {
+            tracing::warn!("couldn't run schedule {}: {e}", schedule.id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_schedule(
+    data: &Data,
+    songbird: &Songbird,
+    schedule: &ScheduleRow,
+) -> Result<(), Error> {
+    let url = Url::parse(&schedule.url)?;
+
+    let (input, track_info) = match webradio_input(data, schedule.guild_id, &url, None).await? {
+        WebradioInput::Playable(input, track_info) => (input, track_info),
+        WebradioInput::Unsupported(reason) => return Err(reason.into()),
+        WebradioInput::Blocked(blocked) => return Err(blocked.into()),
+    };
+
+    let voice_handler =
+        join_channel(data, songbird, schedule.guild_id, schedule.channel_id).await?;
+    play_only(data, schedule.guild_id, &voice_handler, input, track_info).await?;
+
+    Ok(())
+}
@@ -0,0 +1,381 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use songbird::input::codecs::{CODEC_REGISTRY, PROBE};
+use songbird::input::{AudioStream, AudioStreamError, AuxMetadata, Compose, Input};
+use symphonia::core::codecs::{CodecType, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use url::Url;
+
+use crate::discord::Error;
+use crate::metrics;
+use crate::url_policy::UrlPolicy;
+
+/// How much of a stream is downloaded for probing
+const PROBE_SIZE: usize = 256 * 1024;
+/// Live streams are only downloaded for this long, even if less than `PROBE_SIZE` arrived
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Discord expects 48 kHz audio in frames of 20 ms
+const OPUS_PASSTHROUGH_SAMPLE_RATE: u32 = 48_000;
+const OPUS_PASSTHROUGH_FRAME_DURATION: Duration = Duration::from_millis(20);
+
+/// Information about a direct audio stream, gathered by probing its first bytes
+#[derive(Debug, Clone)]
+pub struct StreamInfo {
+    /// URL of the stream after following redirects
+    pub url: Url,
+    /// The server didn't announce a length, so this is most likely a radio stream
+    pub live: bool,
+    pub container: &'static str,
+    pub codec: String,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<usize>,
+    /// Bits per second, as announced by the server or estimated from the probed data
+    pub bitrate: Option<u32>,
+    /// Name of the station (`icy-name` header)
+    pub name: Option<String>,
+    /// Why the stream can't be played, `None` if it can
+    pub unsupported: Option<String>,
+    /// Duration of the first audio frame
+    pub frame_duration: Option<Duration>,
+}
+
+/// Probes the audio stream at `url`.
+///
+/// Returns `Ok(None)` if `url` doesn't point to an audio stream (e.g. a website, which should be
+/// handed to yt-dlp instead).
+pub async fn probe(
+    client: &reqwest::Client,
+    policy: &UrlPolicy,
+    url: &Url,
+    headers: &HeaderMap,
+) -> Result<Option<StreamInfo>, Error> {
+    let mut res = policy.get(client, url, headers).await?;
+
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_lowercase())
+        .unwrap_or_default();
+
+    // Generic binary data could be anything, so it's only treated as audio if probing succeeds
+    let is_octet_stream = content_type == "application/octet-stream";
+    if !is_audio_content_type(&content_type) && !is_octet_stream {
+        return Ok(None);
+    }
+
+    let header = |name: &str| {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let name = header("icy-name");
+    let resolved_url = res.url().clone();
+    let live = res.content_length().is_none();
+    // Shoutcast/Icecast announce the bitrate in kbit/s, sometimes as a list
+    let announced_bitrate = header("icy-br")
+        .and_then(|v| v.split(',').next()?.trim().parse::<u32>().ok())
+        .map(|v| v * 1000);
+
+    let mut data = Vec::new();
+    let read = async {
+        while data.len() < PROBE_SIZE {
+            match res.chunk().await? {
+                Some(chunk) => data.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+        Ok::<_, Error>(())
+    };
+    // Running into the timeout is fine, slow live streams are probed with what arrived so far
+    if let Ok(result) = tokio::time::timeout(PROBE_TIMEOUT, read).await {
+        result?;
+    }
+
+    if data.is_empty() {
+        return Err(format!("stream \"{url}\" didn't send any data").into());
+    }
+
+    let analysis = {
+        let resolved_url = resolved_url.clone();
+        tokio::task::spawn_blocking(move || analyze(data, &content_type, resolved_url, live))
+            .await?
+    };
+
+    let info = match analysis {
+        Ok(v) => StreamInfo {
+            bitrate: announced_bitrate.or(v.bitrate),
+            name,
+            ..v
+        },
+        Err(_) if is_octet_stream => return Ok(None),
+        Err(e) => StreamInfo {
+            url: resolved_url,
+            live,
+            container: "unknown",
+            codec: "unknown".to_string(),
+            sample_rate: None,
+            channels: None,
+            bitrate: announced_bitrate,
+            name,
+            unsupported: Some(format!("the format couldn't be detected ({e})")),
+            frame_duration: None,
+        },
+    };
+
+    Ok(Some(info))
+}
+
+impl StreamInfo {
+    /// Checks whether the Opus frames of the stream can be sent to Discord without re-encoding.
+    ///
+    /// Songbird passes frames through as long as the track is the only one playing and its volume
+    /// is exactly `1.0` (`/volume 100`). Returns the reason if the stream itself prevents that.
+    pub fn opus_passthrough(&self) -> Result<(), String> {
+        if self.codec != "opus" {
+            return Err(format!("the stream is {}, not Opus", self.codec));
+        }
+        if self.sample_rate != Some(OPUS_PASSTHROUGH_SAMPLE_RATE) {
+            return Err("the sample rate isn't 48 kHz".to_string());
+        }
+        if self.channels.map(|v| v > 2).unwrap_or(true) {
+            return Err("it isn't mono or stereo".to_string());
+        }
+        if self.frame_duration != Some(OPUS_PASSTHROUGH_FRAME_DURATION) {
+            return Err("its frames aren't 20 ms long".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+fn is_audio_content_type(content_type: &str) -> bool {
+    content_type.starts_with("audio/")
+        || content_type == "application/ogg"
+        || content_type == "application/aacp"
+}
+
+/// Runs the probed data through symphonia and tries to decode the first packet
+fn analyze(data: Vec<u8>, content_type: &str, url: Url, live: bool) -> Result<StreamInfo, Error> {
+    let container = sniff_container(&data);
+
+    let mss = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
+    let mut hint = Hint::new();
+    if !content_type.is_empty() {
+        hint.mime_type(content_type);
+    }
+
+    let mut probed = PROBE.format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let format = &mut probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|v| v.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("the stream doesn't contain an audio track")?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    let mut info = StreamInfo {
+        url,
+        live,
+        container,
+        codec: codec_name(params.codec),
+        sample_rate: params.sample_rate,
+        channels: params.channels.map(|v| v.count()),
+        bitrate: None,
+        name: None,
+        unsupported: None,
+        frame_duration: None,
+    };
+
+    let mut decoder = match CODEC_REGISTRY.make(&params, &DecoderOptions::default()) {
+        Ok(v) => v,
+        Err(e) => {
+            info.unsupported = Some(format!("{} isn't supported ({e})", info.codec));
+            return Ok(info);
+        }
+    };
+
+    let mut decoded_any = false;
+    let mut bytes = 0;
+    let mut first_ts = None;
+    let mut end_ts = 0;
+
+    // Read everything that was downloaded, the end of the data is usually a truncated packet
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        if !decoded_any {
+            match decoder.decode(&packet) {
+                Ok(buf) => {
+                    info.sample_rate = Some(buf.spec().rate);
+                    info.channels = Some(buf.spec().channels.count());
+                    decoded_any = true;
+                }
+                Err(e) => {
+                    info.unsupported = Some(format!("{} couldn't be decoded ({e})", info.codec));
+                    return Ok(info);
+                }
+            }
+        }
+
+        bytes += packet.data.len() as u64;
+        first_ts.get_or_insert(packet.ts());
+        if info.frame_duration.is_none() {
+            info.frame_duration = params.time_base.map(|v| {
+                let time = v.calc_time(packet.dur());
+                Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac)
+            });
+        }
+        end_ts = packet.ts() + packet.dur();
+    }
+
+    if !decoded_any {
+        info.unsupported = Some("no audio could be read from the stream".to_string());
+        return Ok(info);
+    }
+
+    if let (Some(time_base), Some(first_ts)) = (params.time_base, first_ts) {
+        let time = time_base.calc_time(end_ts.saturating_sub(first_ts));
+        let seconds = time.seconds as f64 + time.frac;
+        if seconds > 0.0 {
+            info.bitrate = Some((bytes as f64 * 8.0 / seconds) as u32);
+        }
+    }
+
+    Ok(info)
+}
+
+fn codec_name(codec: CodecType) -> String {
+    use symphonia::core::codecs::*;
+
+    if let Some(descriptor) = CODEC_REGISTRY.get_codec(codec) {
+        return descriptor.short_name.to_string();
+    }
+
+    // Codecs symphonia knows about, but can't decode
+    let name = match codec {
+        CODEC_TYPE_ALAC => "alac",
+        CODEC_TYPE_EAC3 => "ac3",
+        CODEC_TYPE_DCA => "dts",
+        CODEC_TYPE_WMA => "wma",
+        CODEC_TYPE_SPEEX => "speex",
+        CODEC_TYPE_MP1 => "mp1",
+        _ => return format!("unknown ({codec})"),
+    };
+    name.to_string()
+}
+
+/// Guesses the container from the magic bytes at the start of the stream
+fn sniff_container(data: &[u8]) -> &'static str {
+    let mut data = data;
+
+    // Skip an ID3v2 tag in front of MPEG audio
+    if data.starts_with(b"ID3") && data.len() >= 10 {
+        let size = data[6..10]
+            .iter()
+            .fold(0usize, |acc, v| (acc << 7) | (*v & 0x7F) as usize);
+        data = data.get(10 + size..).unwrap_or_default();
+    }
+
+    match data {
+        [b'O', b'g', b'g', b'S', ..] => "Ogg",
+        [b'f', b'L', b'a', b'C', ..] => "FLAC",
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => "Matroska/WebM",
+        [b'R', b'I', b'F', b'F', ..] => "WAV",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "MP4",
+        // ADTS has the same sync word as MPEG audio, but its layer is always 0
+        [0xFF, b, ..] if b & 0xF6 == 0xF0 => "ADTS",
+        [0xFF, b, ..] if b & 0xE0 == 0xE0 => "MPEG audio",
+        _ => "unknown",
+    }
+}
+
+/// Lazy input which counts the bytes received from the upstream as `discomfort_stream_bytes_total`
+pub struct CountedInput<C> {
+    inner: C,
+}
+
+impl<C: Compose> CountedInput<C> {
+    pub fn new(inner: C) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl<C: Compose> Compose for CountedInput<C> {
+    fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        self.inner.create().map(counted)
+    }
+
+    async fn create_async(
+        &mut self,
+    ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        self.inner.create_async().await.map(counted)
+    }
+
+    fn should_create_async(&self) -> bool {
+        self.inner.should_create_async()
+    }
+
+    async fn aux_metadata(&mut self) -> Result<AuxMetadata, AudioStreamError> {
+        self.inner.aux_metadata().await
+    }
+}
+
+impl<C: Compose + 'static> From<CountedInput<C>> for Input {
+    fn from(val: CountedInput<C>) -> Self {
+        Input::Lazy(Box::new(val))
+    }
+}
+
+fn counted(stream: AudioStream<Box<dyn MediaSource>>) -> AudioStream<Box<dyn MediaSource>> {
+    AudioStream {
+        input: Box::new(CountedSource(stream.input)),
+        hint: stream.hint,
+    }
+}
+
+struct CountedSource(Box<dyn MediaSource>);
+
+impl Read for CountedSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.0.read(buf)?;
+        metrics::STREAM_BYTES.inc_by(len as u64);
+        Ok(len)
+    }
+}
+
+impl Seek for CountedSource {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl MediaSource for CountedSource {
+    fn is_seekable(&self) -> bool {
+        self.0.is_seekable()
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.0.byte_len()
+    }
+}
@@ -0,0 +1,523 @@
+//! Checks URLs before the bot fetches them or hands them to yt-dlp, so nobody can make it request
+//! e.g. `http://127.0.0.1`, a cloud metadata endpoint or something else in its network.
+//!
+//! URLs have to use HTTP(S), pass the global and the guild's domain lists and must not resolve to
+//! a loopback, private or link-local address (unless `OUTBOUND_ALLOW_PRIVATE` is set). The client
+//! from [`crate::outbound::Outbound::guarded`] checks every address it connects to and every
+//! redirect as well, so a URL can't get around it by redirecting or resolving differently the
+//! second time. That client is shared by all guilds, so redirects only have to pass the global
+//! lists there. [`UrlPolicy::get`] checks where a response ended up against the lists of the guild
+//! too. What yt-dlp fetches itself can only be checked up front.
+
+use std::error::Error as StdError;
+use std::fmt::{self, Display};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use hyper::client::connect::dns::Name;
+use poise::serenity_prelude::GuildId;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::HeaderMap;
+use url::{Host, Url};
+
+use crate::config::{Config, ConfigHandle};
+use crate::database::{actions, DatabaseContext};
+use crate::discord::Error;
+
+/// Same as the default policy of reqwest
+const MAX_REDIRECTS: usize = 10;
+
+/// Why a URL can't be played
+#[derive(Debug, Clone)]
+pub enum Blocked {
+    Scheme(String),
+    NoHost,
+    /// The host is on a blocklist, of the guild if `by_guild` is set
+    Domain {
+        host: String,
+        by_guild: bool,
+    },
+    /// The host isn't on an allowlist
+    NotAllowed {
+        host: String,
+        by_guild: bool,
+    },
+    Private {
+        host: String,
+        ip: IpAddr,
+    },
+    Unresolvable {
+        host: String,
+        error: String,
+    },
+}
+
+impl Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whose = |by_guild: bool| if by_guild { "this server" } else { "the bot" };
+
+        match self {
+            Self::Scheme(scheme) => write!(f, "only http and https URLs are allowed, not {scheme}"),
+            Self::NoHost => write!(f, "the URL has no host"),
+            Self::Domain { host, by_guild } => {
+                write!(f, "`{host}` is on the blocklist of {}", whose(*by_guild))
+            }
+            Self::NotAllowed { host, by_guild } => {
+                write!(f, "`{host}` isn't on the allowlist of {}", whose(*by_guild))
+            }
+            Self::Private { host, ip } => {
+                write!(f, "`{host}` points to a private address ({ip})")
+            }
+            Self::Unresolvable { host, error } => write!(f, "couldn't resolve `{host}`: {error}"),
+        }
+    }
+}
+
+impl StdError for Blocked {}
+
+/// Allowed and blocked domains (including their subdomains)
+#[derive(Debug, Clone, Default)]
+struct DomainList {
+    allowed: Vec<String>,
+    blocked: Vec<String>,
+    by_guild: bool,
+}
+
+impl DomainList {
+    fn check(&self, host: &str) -> Result<(), Blocked> {
+        let matches = |domain: &String| {
+            host == domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|v| v.ends_with('.'))
+        };
+
+        if self.blocked.iter().any(matches) {
+            return Err(Blocked::Domain {
+                host: host.to_string(),
+                by_guild: self.by_guild,
+            });
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(matches) {
+            return Err(Blocked::NotAllowed {
+                host: host.to_string(),
+                by_guild: self.by_guild,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UrlPolicy {
+    allow_private: bool,
+    /// The global lists and those of the guild, URLs have to pass all of them
+    lists: Vec<DomainList>,
+}
+
+impl UrlPolicy {
+    /// The global policy
+    pub fn global(config: &Config) -> Self {
+        Self {
+            allow_private: config.outbound.allow_private,
+            lists: vec![DomainList {
+                allowed: config.allowed_domains.clone(),
+                blocked: config.blocked_domains.clone(),
+                by_guild: false,
+            }],
+        }
+    }
+
+    /// Lets everything through, for tests against local servers
+    #[cfg(test)]
+    pub fn allow_all() -> Self {
+        Self {
+            allow_private: true,
+            lists: Vec::new(),
+        }
+    }
+
+    /// The global policy, with the domain lists of the guild
+    pub async fn for_guild(
+        config: &Config,
+        database: &DatabaseContext,
+        guild_id: GuildId,
+    ) -> Result<Self, Error> {
+        let mut conn = database.get_connection().await?;
+        let rows = actions::guild_domains_get(&mut conn, guild_id).await?;
+
+        let mut list = DomainList {
+            by_guild: true,
+            ..DomainList::default()
+        };
+        for row in rows {
+            if row.blocked {
+                list.blocked.push(row.domain);
+            } else {
+                list.allowed.push(row.domain);
+            }
+        }
+
+        let mut policy = Self::global(config);
+        policy.lists.push(list);
+
+        Ok(policy)
+    }
+
+    /// Checks everything that doesn't need a DNS lookup: The scheme, the domain lists and hosts
+    /// which are IP addresses
+    pub fn check_static(&self, url: &Url) -> Result<(), Blocked> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Blocked::Scheme(url.scheme().to_string()));
+        }
+
+        let host = url.host().ok_or(Blocked::NoHost)?;
+        let host_str = host.to_string();
+        // `example.com.` is the same host as `example.com`, but wouldn't match it
+        let domain = host_str.trim_end_matches('.');
+        for list in &self.lists {
+            list.check(domain)?;
+        }
+
+        let ip = match host {
+            Host::Ipv4(v) => IpAddr::V4(v),
+            Host::Ipv6(v) => IpAddr::V6(v),
+            Host::Domain(_) => return Ok(()),
+        };
+        if !self.allow_private && is_private(ip) {
+            return Err(Blocked::Private { host: host_str, ip });
+        }
+
+        Ok(())
+    }
+
+    /// Checks `url`, including the addresses its host resolves to
+    /// Requests `url` with `client`, which has to be the guarded one.
+    ///
+    /// The client only checks redirects against the global policy, so the URL the response ended
+    /// up at is checked against this one before it's returned.
+    pub async fn get(
+        &self,
+        client: &reqwest::Client,
+        url: &Url,
+        headers: &HeaderMap,
+    ) -> Result<reqwest::Response, Error> {
+        self.check_static(url)?;
+
+        let res = client
+            .get(url.clone())
+            .headers(headers.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        self.check_static(res.url())?;
+
+        Ok(res)
+    }
+
+    pub async fn check(&self, url: &Url) -> Result<(), Blocked> {
+        self.check_static(url)?;
+
+        if self.allow_private {
+            return Ok(());
+        }
+        let Some(Host::Domain(host)) = url.host() else {
+            return Ok(());
+        };
+
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs =
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| Blocked::Unresolvable {
+                    host: host.to_string(),
+                    error: e.to_string(),
+                })?;
+        for addr in addrs {
+            if is_private(addr.ip()) {
+                return Err(Blocked::Private {
+                    host: host.to_string(),
+                    ip: addr.ip(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds out whether a request failed because a URL was blocked, e.g. one it was redirected to
+pub fn blocked_reason<'a>(e: &'a (dyn StdError + 'static)) -> Option<&'a Blocked> {
+    let mut source = Some(e);
+    while let Some(e) = source {
+        if let Some(blocked) = e.downcast_ref::<Blocked>() {
+            return Some(blocked);
+        }
+        source = e.source();
+    }
+
+    None
+}
+
+/// Follows redirects which pass the global policy, which is read from the current config on every
+/// redirect
+pub fn redirect_policy(config: ConfigHandle) -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+
+        match UrlPolicy::global(&config.get()).check_static(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(e) => attempt.error(e),
+        }
+    })
+}
+
+/// DNS resolver which drops private addresses (unless they're allowed), so a host can't resolve to
+/// a public address when it's checked and to a private one when connecting
+#[derive(Clone)]
+pub struct PublicResolver {
+    config: ConfigHandle,
+}
+
+impl PublicResolver {
+    pub fn new(config: ConfigHandle) -> Self {
+        Self { config }
+    }
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allow_private = self.config.get().outbound.allow_private;
+
+        Box::pin(async move {
+            let host = name.as_str();
+            // The port is replaced by the one of the URL
+            let addrs = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|v| allow_private || !is_private(v.ip()))
+                .collect::<Vec<SocketAddr>>();
+
+            if addrs.is_empty() {
+                return Err(format!("\"{host}\" has no public address").into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `ip` is in a loopback, private, link-local or otherwise non-public range
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v) => is_private_v4(v),
+        IpAddr::V6(v) => is_private_v6(v),
+    }
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network"
+        || a == 0
+        // Shared address space (carrier-grade NAT)
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking
+        || (a == 198 && (18..20).contains(&b))
+        // Reserved
+        || a >= 240
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+
+    // IPv4-mapped (::ffff:0:0/96) and NAT64 (64:ff9b::/96) addresses reach IPv4 hosts
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_private_v4(v4);
+    }
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., hi, lo] = segments;
+        return is_private_v4(Ipv4Addr::from(((hi as u32) << 16) | lo as u32));
+    }
+
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link-local
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Site-local (deprecated)
+        || (segments[0] & 0xffc0) == 0xfec0
+        // Documentation
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8)
+}
+
+/// Brings a domain from a list into the form of URL hosts (lowercase, punycode), `*.` is allowed
+/// but not needed. Returns `None` if it isn't a domain.
+pub fn normalize_domain(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = value.strip_prefix("*.").unwrap_or(value).trim_matches('.');
+    if value.is_empty() {
+        return None;
+    }
+
+    Host::parse(value).ok().map(|v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn private_v4() {
+        for value in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.178.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "255.255.255.255",
+            "224.0.0.1",
+            "240.0.0.1",
+        ] {
+            assert!(is_private(ip(value)), "{value}");
+        }
+        for value in ["1.1.1.1", "8.8.8.8", "172.32.0.1", "100.128.0.1"] {
+            assert!(!is_private(ip(value)), "{value}");
+        }
+    }
+
+    #[test]
+    fn private_v6() {
+        for value in [
+            "::1",
+            "::",
+            // Link-local
+            "fe80::1",
+            // Unique local
+            "fc00::1",
+            "fd12:3456::1",
+            "2001:db8::1",
+            "ff02::1",
+        ] {
+            assert!(is_private(ip(value)), "{value}");
+        }
+        for value in ["2606:4700:4700::1111", "2a00:1450:4001::200e"] {
+            assert!(!is_private(ip(value)), "{value}");
+        }
+    }
+
+    #[test]
+    fn private_v4_in_v6() {
+        // IPv4-mapped
+        assert!(is_private(ip("::ffff:127.0.0.1")));
+        assert!(is_private(ip("::ffff:169.254.169.254")));
+        assert!(!is_private(ip("::ffff:1.1.1.1")));
+
+        // NAT64
+        assert!(is_private(ip("64:ff9b::7f00:1")));
+        assert!(is_private(ip("64:ff9b::10.0.0.1")));
+        assert!(!is_private(ip("64:ff9b::1.1.1.1")));
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            normalize_domain("Example.COM").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            normalize_domain("example.com.").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            normalize_domain(" *.example.com ").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            normalize_domain("Bücher.example").as_deref(),
+            Some("xn--bcher-kva.example")
+        );
+        assert_eq!(normalize_domain(""), None);
+        assert_eq!(normalize_domain("."), None);
+        assert_eq!(normalize_domain("exa mple.com"), None);
+    }
+
+    #[test]
+    fn domain_list() {
+        let list = DomainList {
+            allowed: Vec::new(),
+            blocked: vec!["example.com".to_string()],
+            by_guild: true,
+        };
+        assert!(list.check("example.com").is_err());
+        assert!(list.check("radio.example.com").is_err());
+        assert!(list.check("evilexample.com").is_ok());
+        assert!(list.check("example.com.evil.org").is_ok());
+
+        let list = DomainList {
+            allowed: vec!["example.com".to_string()],
+            blocked: Vec::new(),
+            by_guild: false,
+        };
+        assert!(list.check("example.com").is_ok());
+        assert!(list.check("stream.radio.example.com").is_ok());
+        assert!(matches!(
+            list.check("evilexample.com"),
+            Err(Blocked::NotAllowed {
+                by_guild: false,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn check_static() {
+        let policy = UrlPolicy {
+            allow_private: false,
+            lists: vec![DomainList {
+                allowed: Vec::new(),
+                blocked: vec!["example.com".to_string()],
+                by_guild: false,
+            }],
+        };
+        let check = |url: &str| policy.check_static(&Url::parse(url).unwrap());
+
+        assert!(matches!(
+            check("file:///etc/passwd"),
+            Err(Blocked::Scheme(_))
+        ));
+        assert!(matches!(
+            check("http://127.0.0.1/"),
+            Err(Blocked::Private { .. })
+        ));
+        assert!(matches!(
+            check("http://[::1]:8080/"),
+            Err(Blocked::Private { .. })
+        ));
+        // The URL parser brings hosts into the same form as the lists
+        assert!(matches!(
+            check("https://RADIO.Example.com./"),
+            Err(Blocked::Domain { .. })
+        ));
+        assert!(check("https://evilexample.com/stream").is_ok());
+        assert!(check("https://1.1.1.1/").is_ok());
+    }
+}
@@ -0,0 +1,145 @@
+//! Cache of what yt-dlp resolved URLs to, so playing them again doesn't have to wait for it.
+//!
+//! Entries are kept in memory and in the database (so they survive restarts) until they expire,
+//! as the media URLs of most sites only work for a few hours. Entries are dropped as soon as
+//! playback fails.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+
+use crate::database::{actions, DatabaseContext};
+use crate::discord::Error;
+use crate::ytdl::Output;
+
+/// Entries kept in memory, the database isn't limited
+const MAX_MEMORY_ENTRIES: usize = 1000;
+
+/// Resolutions by URL, with the time they expire
+type Entries = HashMap<String, (Output, DateTime<Utc>)>;
+
+#[derive(Debug, Clone)]
+pub struct ResolutionCache {
+    database: DatabaseContext,
+    /// `None` if caching is disabled
+    ttl: Option<Duration>,
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl ResolutionCache {
+    pub fn new(database: DatabaseContext, ttl: Option<Duration>) -> Self {
+        Self {
+            database,
+            ttl,
+            entries: Arc::default(),
+        }
+    }
+
+    pub(super) async fn get(&self, url: &str) -> Option<Output> {
+        self.ttl?;
+
+        {
+            let mut entries = self.entries.lock();
+            match entries.get(url) {
+                Some((output, expires_at)) if *expires_at > Utc::now() => {
+                    return Some(output.clone())
+                }
+                Some(_) => {
+                    entries.remove(url);
+                }
+                None => {}
+            }
+        }
+
+        match self.load(url).await {
+            Ok(Some((output, expires_at))) => {
+                self.insert_memory(url, output.clone(), expires_at);
+                Some(output)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                tracing::warn!("couldn't load the cached resolution of \"{url}\": {e}");
+                None
+            }
+        }
+    }
+
+    pub(super) async fn insert(&self, url: &str, output: &Output) {
+        let Some(ttl) = self.ttl else {
+            return;
+        };
+        let expires_at = Utc::now() + chrono::Duration::from_std(ttl).unwrap_or_default();
+
+        self.insert_memory(url, output.clone(), expires_at);
+
+        if let Err(e) = self.save(url, output, expires_at).await {
+            tracing::warn!("couldn't save the resolution of \"{url}\": {e}");
+        }
+    }
+
+    /// Forgets the resolution of `url`, e.g. because its media URL doesn't work anymore
+    pub async fn invalidate(&self, url: &str) {
+        if self.ttl.is_none() {
+            return;
+        }
+
+        self.entries.lock().remove(url);
+
+        let result = match self.database.get_connection().await {
+            Ok(mut conn) => actions::resolution_delete(&mut conn, url).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::warn!("couldn't remove the cached resolution of \"{url}\": {e}");
+        }
+    }
+
+    fn insert_memory(&self, url: &str, output: Output, expires_at: DateTime<Utc>) {
+        let mut entries = self.entries.lock();
+
+        if entries.len() >= MAX_MEMORY_ENTRIES {
+            let now = Utc::now();
+            entries.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+        // Still full, so the entry which expires first makes room
+        if entries.len() >= MAX_MEMORY_ENTRIES {
+            let first = entries
+                .iter()
+                .min_by_key(|(_, (_, expires_at))| *expires_at)
+                .map(|(k, _)| k.clone());
+            if let Some(first) = first {
+                entries.remove(&first);
+            }
+        }
+
+        entries.insert(url.to_string(), (output, expires_at));
+    }
+
+    async fn load(&self, url: &str) -> Result<Option<(Output, DateTime<Utc>)>, Error> {
+        let mut conn = self.database.get_connection().await?;
+        let Some(row) = actions::resolution_get(&mut conn, url).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some((serde_json::from_str(&row.output)?, row.expires_at)))
+    }
+
+    async fn save(
+        &self,
+        url: &str,
+        output: &Output,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let mut conn = self.database.get_connection().await?;
+        actions::resolution_insert_or_update(
+            &mut conn,
+            url,
+            &serde_json::to_string(output)?,
+            expires_at,
+        )
+        .await
+    }
+}
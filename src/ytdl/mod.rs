@@ -0,0 +1,515 @@
+mod cache;
+pub mod update;
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use songbird::input::{
+    AudioStream, AudioStreamError, AuxMetadata, Compose, HlsRequest, HttpRequest, Input,
+};
+use symphonia::core::io::MediaSource;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use url::Url;
+
+use crate::config::YtdlConfig;
+use crate::database::DatabaseContext;
+use crate::discord::Error;
+use crate::metrics;
+pub use cache::ResolutionCache;
+
+/// Wrapper around the yt-dlp executable.
+///
+/// The executable can be swapped out via the config (e.g. with a stub script for testing).
+/// Every process is killed after the configured timeout, and only a limited number of them run
+/// at once.
+#[derive(Debug, Clone)]
+pub struct YtDlp {
+    program: Arc<str>,
+    client: reqwest::Client,
+    /// Added to every invocation (cookies, proxy and the configured extra arguments)
+    args: Arc<[String]>,
+    format: Arc<str>,
+    timeout: Duration,
+    permits: Arc<Semaphore>,
+    cache: ResolutionCache,
+}
+
+/// A playlist, as returned by `yt-dlp --flat-playlist -J`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Playlist {
+    pub title: Option<String>,
+    #[serde(default)]
+    pub entries: Vec<PlaylistEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlaylistEntry {
+    pub url: Option<String>,
+    pub title: Option<String>,
+    /// Duration in seconds
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FlatPlaylistOutput {
+    #[serde(rename = "_type")]
+    kind: Option<String>,
+    #[serde(flatten)]
+    playlist: Playlist,
+}
+
+/// A resolved video or track, as returned by `yt-dlp -j`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Output {
+    url: String,
+    protocol: Option<String>,
+    http_headers: Option<HashMap<String, String>>,
+    filesize: Option<u64>,
+
+    title: Option<String>,
+    track: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    channel: Option<String>,
+    uploader: Option<String>,
+    /// Duration in seconds
+    duration: Option<f64>,
+    release_date: Option<String>,
+    upload_date: Option<String>,
+    thumbnail: Option<String>,
+    webpage_url: Option<String>,
+}
+
+/// Lazily resolved input of a URL, which is only passed to yt-dlp once it's needed
+#[derive(Debug, Clone)]
+pub struct YtDlpInput {
+    ytdl: YtDlp,
+    url: String,
+    /// Added for this URL only (e.g. the headers of its credentials). Resolutions with extra
+    /// arguments aren't cached.
+    args: Arc<[String]>,
+    metadata: Option<AuxMetadata>,
+}
+
+impl YtDlp {
+    /// `client` fetches the resolved media
+    pub fn new(config: &YtdlConfig, database: DatabaseContext, client: reqwest::Client) -> Self {
+        let mut args = Vec::new();
+        if let Some(cookies) = &config.cookies {
+            args.push("--cookies".to_string());
+            args.push(cookies.to_string_lossy().into_owned());
+        }
+        if let Some(proxy) = &config.proxy {
+            args.push("--proxy".to_string());
+            args.push(proxy.clone());
+        }
+        args.extend(config.extra_args.iter().cloned());
+
+        Self {
+            program: config.program.as_str().into(),
+            client,
+            args: args.into(),
+            format: config.format.as_str().into(),
+            timeout: config.timeout,
+            permits: Arc::new(Semaphore::new(config.max_concurrent)),
+            cache: ResolutionCache::new(database, config.cache_ttl),
+        }
+    }
+
+    /// Create a lazy input for `url`
+    pub fn input(&self, url: &str) -> YtDlpInput {
+        self.input_with_args(url, Vec::new())
+    }
+
+    /// Create a lazy input for `url`, which passes `args` to yt-dlp
+    pub fn input_with_args(&self, url: &str, args: Vec<String>) -> YtDlpInput {
+        YtDlpInput {
+            ytdl: self.clone(),
+            url: url.to_string(),
+            args: args.into(),
+            metadata: None,
+        }
+    }
+
+    /// Forgets the cached resolution of `url`, e.g. because playing it failed
+    pub async fn invalidate(&self, url: &str) {
+        self.cache.invalidate(url).await;
+    }
+
+    /// Search YouTube for `query`, returning up to `n_results` results
+    pub async fn search(&self, query: &str, n_results: usize) -> Result<Vec<AuxMetadata>, Error> {
+        let outputs = metrics::time_ytdl(
+            "search",
+            self.resolve(&format!("ytsearch{n_results}:{query}"), &[]),
+        )
+        .await?;
+
+        Ok(outputs.iter().map(Output::aux_metadata).collect())
+    }
+
+    /// Get the entries of the playlist at `url` without resolving each of them.
+    ///
+    /// Returns `Ok(None)` if `url` doesn't point to a playlist.
+    pub async fn playlist(&self, url: &str) -> Result<Option<Playlist>, Error> {
+        let stdout =
+            metrics::time_ytdl("playlist", self.run(&["--flat-playlist", "-J", url])).await?;
+
+        let output: FlatPlaylistOutput = serde_json::from_slice(&stdout)?;
+        if output.kind.as_deref() != Some("playlist") {
+            return Ok(None);
+        }
+
+        Ok(Some(output.playlist))
+    }
+
+    /// Version of the executable, as printed by `yt-dlp --version`
+    pub async fn version(&self) -> Result<String, Error> {
+        let stdout = self.run(&["--version"]).await?;
+
+        Ok(String::from_utf8_lossy(&stdout).trim().to_string())
+    }
+
+    /// Resolves `query` (a URL or e.g. `ytsearch5:...`) to the audio of each result
+    async fn resolve(&self, query: &str, extra_args: &[String]) -> Result<Vec<Output>, Error> {
+        let mut args = vec!["-j", query, "-f", &self.format, "--no-playlist"];
+        args.extend(extra_args.iter().map(String::as_str));
+        let stdout = self.run(&args).await?;
+
+        let outputs = stdout
+            .split(|v| *v == b'\n')
+            .filter(|v| !v.is_empty())
+            .map(serde_json::from_slice)
+            .collect::<Result<Vec<Output>, _>>()?;
+        if outputs.is_empty() {
+            return Err(format!("no results found for \"{query}\"").into());
+        }
+
+        Ok(outputs)
+    }
+
+    /// Runs yt-dlp with `args` after the configured ones, returning what it printed to stdout
+    async fn run(&self, args: &[&str]) -> Result<Vec<u8>, Error> {
+        // The semaphore is never closed
+        let _permit = self.permits.acquire().await?;
+
+        let output = tokio::time::timeout(
+            self.timeout,
+            Command::new(&*self.program)
+                .args(self.args.iter())
+                .args(args)
+                .kill_on_drop(true)
+                .output(),
+        )
+        .await
+        .map_err(|_| {
+            format!(
+                "{} didn't finish within {}s",
+                self.program,
+                self.timeout.as_secs()
+            )
+        })?
+        .map_err(|e| -> Error {
+            if e.kind() == ErrorKind::NotFound {
+                format!("couldn't find executable \"{}\"", self.program).into()
+            } else {
+                e.into()
+            }
+        })?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )
+            .into());
+        }
+
+        Ok(output.stdout)
+    }
+}
+
+impl YtDlpInput {
+    /// Resolves the URL, using the cache if `cached` is set. Returns whether the output came from
+    /// the cache.
+    async fn resolve(&mut self, cached: bool) -> Result<(Output, bool), AudioStreamError> {
+        let cached = cached && self.args.is_empty();
+        if cached {
+            if let Some(output) = self.ytdl.cache.get(&self.url).await {
+                self.metadata = Some(output.aux_metadata());
+                return Ok((output, true));
+            }
+        }
+
+        let mut outputs = self
+            .ytdl
+            .resolve(&self.url, &self.args)
+            .await
+            .map_err(AudioStreamError::Fail)?;
+        let output = outputs.swap_remove(0);
+        self.metadata = Some(output.aux_metadata());
+        if self.args.is_empty() {
+            self.ytdl.cache.insert(&self.url, &output).await;
+        }
+
+        Ok((output, false))
+    }
+
+    async fn open(
+        &self,
+        output: Output,
+    ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        let headers = output
+            .http_headers
+            .iter()
+            .flatten()
+            .filter_map(|(k, v)| {
+                Some((
+                    HeaderName::from_bytes(k.as_bytes()).ok()?,
+                    HeaderValue::from_str(v).ok()?,
+                ))
+            })
+            .collect::<HeaderMap>();
+
+        if output.protocol.as_deref() == Some("m3u8_native") {
+            HlsRequest::new_with_headers(self.ytdl.client.clone(), output.url, headers).create()
+        } else {
+            HttpRequest {
+                client: self.ytdl.client.clone(),
+                request: output.url,
+                headers,
+                content_length: output.filesize,
+            }
+            .create_async()
+            .await
+        }
+    }
+}
+
+#[async_trait]
+impl Compose for YtDlpInput {
+    fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        Err(AudioStreamError::Unsupported)
+    }
+
+    async fn create_async(
+        &mut self,
+    ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        let (output, cached) = self.resolve(true).await?;
+
+        match self.open(output).await {
+            // The cached media URL might not work anymore, so it's resolved again
+            Err(e) if cached => {
+                tracing::debug!("cached resolution of \"{}\" failed: {e:?}", self.url);
+                self.ytdl.cache.invalidate(&self.url).await;
+
+                let (output, _) = self.resolve(false).await?;
+                self.open(output).await
+            }
+            result => result,
+        }
+    }
+
+    fn should_create_async(&self) -> bool {
+        true
+    }
+
+    async fn aux_metadata(&mut self) -> Result<AuxMetadata, AudioStreamError> {
+        if let Some(metadata) = &self.metadata {
+            return Ok(metadata.clone());
+        }
+
+        Ok(self.resolve(true).await?.0.aux_metadata())
+    }
+}
+
+impl From<YtDlpInput> for Input {
+    fn from(val: YtDlpInput) -> Self {
+        Input::Lazy(Box::new(val))
+    }
+}
+
+impl Output {
+    fn aux_metadata(&self) -> AuxMetadata {
+        AuxMetadata {
+            track: self.track.clone(),
+            artist: self.artist.clone().or_else(|| self.uploader.clone()),
+            album: self.album.clone(),
+            date: self
+                .release_date
+                .clone()
+                .or_else(|| self.upload_date.clone()),
+            channels: Some(2),
+            channel: self.channel.clone(),
+            duration: self
+                .duration
+                .filter(|v| v.is_finite() && *v >= 0.0)
+                .map(Duration::from_secs_f64),
+            sample_rate: Some(48000),
+            source_url: self.webpage_url.clone(),
+            title: self.title.clone(),
+            thumbnail: self.thumbnail.clone(),
+            ..AuxMetadata::default()
+        }
+    }
+}
+
+impl PlaylistEntry {
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+            .filter(|v| v.is_finite() && *v >= 0.0)
+            .map(Duration::from_secs_f64)
+    }
+}
+
+/// Checks whether `url` points to a playlist on one of the common sites, e.g. via a `list` query
+/// parameter. [`YtDlp::playlist`] has the final say, so this only decides whether to ask yt-dlp.
+pub fn is_playlist_url(url: &Url) -> bool {
+    let host = url.host_str().unwrap_or_default();
+    let path = url.path();
+
+    if host.ends_with("youtube.com") || host.ends_with("youtu.be") {
+        return url.query_pairs().any(|(k, _)| k == "list");
+    }
+
+    if host.ends_with("soundcloud.com") {
+        return path.contains("/sets/");
+    }
+
+    if host.ends_with("bandcamp.com") {
+        return path.starts_with("/album/");
+    }
+
+    url.query_pairs().any(|(k, _)| k == "list")
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// yt-dlp replaced by a shell script, which is run via `sh` so it doesn't have to be executable
+    async fn stub(script: &str, timeout: Duration) -> (YtDlp, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("yt-dlp.sh");
+        std::fs::write(&path, script).unwrap();
+
+        let config = YtdlConfig {
+            program: "sh".to_string(),
+            managed: false,
+            version: None,
+            update_interval: None,
+            cookies: None,
+            format: "bestaudio".to_string(),
+            proxy: None,
+            extra_args: vec![path.to_string_lossy().into_owned()],
+            timeout,
+            max_concurrent: 1,
+            cache_ttl: None,
+        };
+        let database = DatabaseContext::new("sqlite::memory:").await.unwrap();
+
+        (YtDlp::new(&config, database, reqwest::Client::new()), dir)
+    }
+
+    #[tokio::test]
+    async fn search() {
+        let (ytdl, _dir) = stub(
+            r#"
+echo '{"url": "https://example.com/1.opus", "title": "First", "duration": 61.5}'
+echo '{"url": "https://example.com/2.opus", "title": "Second"}'
+"#,
+            Duration::from_secs(10),
+        )
+        .await;
+
+        let results = ytdl.search("query", 2).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].title.as_deref(), Some("First"));
+        assert_eq!(results[0].duration, Some(Duration::from_millis(61_500)));
+        assert_eq!(results[1].title.as_deref(), Some("Second"));
+    }
+
+    #[tokio::test]
+    async fn playlist() {
+        let (ytdl, _dir) = stub(
+            r#"echo '{"_type": "playlist", "title": "Mix", "entries": [{"url": "https://example.com/1"}, {"title": "No URL"}]}'"#,
+            Duration::from_secs(10),
+        )
+        .await;
+
+        let playlist = ytdl
+            .playlist("https://example.com/mix")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(playlist.title.as_deref(), Some("Mix"));
+        assert_eq!(playlist.entries.len(), 2);
+        assert_eq!(playlist.entries[1].url, None);
+
+        let (ytdl, _dir) = stub(
+            r#"echo '{"_type": "video", "title": "Single"}'"#,
+            Duration::from_secs(10),
+        )
+        .await;
+        assert!(ytdl
+            .playlist("https://example.com/video")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn invalid_json() {
+        let (ytdl, _dir) = stub("echo 'not json'", Duration::from_secs(10)).await;
+
+        let e = ytdl.search("query", 1).await.unwrap_err();
+        assert!(e.is::<serde_json::Error>(), "{e}");
+    }
+
+    #[tokio::test]
+    async fn failure() {
+        let (ytdl, _dir) = stub(
+            "echo 'ERROR: Unsupported URL' >&2; exit 1",
+            Duration::from_secs(10),
+        )
+        .await;
+
+        let e = ytdl.version().await.unwrap_err().to_string();
+        assert!(e.contains("Unsupported URL"), "{e}");
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let (ytdl, _dir) = stub("sleep 10", Duration::from_millis(200)).await;
+
+        let started = std::time::Instant::now();
+        let e = ytdl.version().await.unwrap_err().to_string();
+        assert!(e.contains("didn't finish"), "{e}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn playlist_urls() {
+        let is_playlist = |url: &str| is_playlist_url(&Url::parse(url).unwrap());
+
+        assert!(is_playlist("https://www.youtube.com/playlist?list=PL123"));
+        assert!(is_playlist(
+            "https://www.youtube.com/watch?v=abc&list=PL123"
+        ));
+        assert!(!is_playlist("https://www.youtube.com/watch?v=abc"));
+        assert!(is_playlist("https://soundcloud.com/artist/sets/mix"));
+        assert!(is_playlist("https://artist.bandcamp.com/album/first"));
+        assert!(is_playlist("https://example.com/watch?list=1"));
+        assert!(!is_playlist("https://example.com/playlists/radio.mp3"));
+        assert!(!is_playlist("https://example.com/stream/playlist.m3u8"));
+    }
+}
@@ -0,0 +1,154 @@
+//! Installs and updates yt-dlp if it's managed by the bot (`YTDL_MANAGED`).
+//!
+//! The platform independent build (a Python zipapp) is downloaded from the GitHub releases, so
+//! Python 3 has to be installed.
+
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::time::MissedTickBehavior;
+
+use crate::config::YtdlConfig;
+use crate::discord::Error;
+use crate::ytdl::YtDlp;
+
+const RELEASES_URL: &str = "https://github.com/yt-dlp/yt-dlp/releases";
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/yt-dlp/yt-dlp/releases/latest";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+/// The zipapp is a few MB, anything this big isn't yt-dlp
+const MAX_DOWNLOAD_SIZE: usize = 64 * 1024 * 1024;
+/// The file listing the checksums of a release's assets
+const CHECKSUMS_FILE: &str = "SHA2-256SUMS";
+const ASSET_NAME: &str = "yt-dlp";
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// Installs the pinned (or latest) release, unless it's installed already. `client` is the
+/// unguarded one, GitHub redirects downloads to a domain which may not be on the allowlist.
+pub async fn update(
+    ytdl: &YtDlp,
+    client: &reqwest::Client,
+    config: &YtdlConfig,
+) -> Result<(), Error> {
+    let installed = ytdl.version().await.ok();
+    let wanted = match &config.version {
+        Some(v) => v.clone(),
+        None => {
+            client
+                .get(LATEST_RELEASE_URL)
+                .timeout(DOWNLOAD_TIMEOUT)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<Release>()
+                .await?
+                .tag_name
+        }
+    };
+
+    if installed.as_deref() == Some(wanted.as_str()) {
+        tracing::debug!("yt-dlp {wanted} is installed already");
+        return Ok(());
+    }
+
+    download(client, Path::new(&config.program), &wanted).await?;
+
+    match installed {
+        Some(installed) => tracing::info!("updated yt-dlp from {installed} to {wanted}"),
+        None => tracing::info!("installed yt-dlp {wanted}"),
+    }
+
+    Ok(())
+}
+
+/// Checks for a new release in the configured interval, unless the version is pinned
+pub fn spawn_updates(ytdl: YtDlp, client: reqwest::Client, config: YtdlConfig) {
+    let Some(period) = config.update_interval.filter(|_| config.version.is_none()) else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately, but yt-dlp was just updated on startup
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = update(&ytdl, &client, &config).await {
+                tracing::warn!("couldn't update yt-dlp: {e}");
+            }
+        }
+    });
+}
+
+/// Downloads `version` to `path`. The old executable is only replaced once the download is
+/// complete and matches the checksum of the release, so running processes aren't affected.
+async fn download(client: &reqwest::Client, path: &Path, version: &str) -> Result<(), Error> {
+    let checksums = fetch(client, version, CHECKSUMS_FILE).await?;
+    let expected = String::from_utf8_lossy(&checksums)
+        .lines()
+        .find_map(|line| {
+            let (hash, name) = line.split_once(char::is_whitespace)?;
+            (name.trim_start().trim_start_matches('*') == ASSET_NAME)
+                .then(|| hash.to_ascii_lowercase())
+        })
+        .ok_or_else(|| format!("{CHECKSUMS_FILE} of yt-dlp {version} has no checksum for it"))?;
+
+    let bytes = fetch(client, version, ASSET_NAME).await?;
+
+    let actual = format!("{:x}", Sha256::digest(&bytes));
+    if actual != expected {
+        return Err(format!(
+            "the download of yt-dlp {version} is corrupted, its SHA-256 is {actual} instead of {expected}"
+        )
+        .into());
+    }
+
+    if let Some(parent) = path.parent().filter(|v| !v.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let partial = path.with_extension("download");
+    tokio::fs::write(&partial, &bytes).await?;
+
+    tokio::fs::set_permissions(&partial, Permissions::from_mode(0o755)).await?;
+
+    tokio::fs::rename(&partial, path).await?;
+
+    Ok(())
+}
+
+/// Downloads the asset `name` of the release `version`, up to [`MAX_DOWNLOAD_SIZE`]
+async fn fetch(client: &reqwest::Client, version: &str, name: &str) -> Result<Vec<u8>, Error> {
+    let url = format!("{RELEASES_URL}/download/{version}/{name}");
+    let mut res = client
+        .get(&url)
+        .timeout(DOWNLOAD_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()
+        .map_err(|e| format!("couldn't download {name} of yt-dlp {version}: {e}"))?;
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        if bytes.len() + chunk.len() > MAX_DOWNLOAD_SIZE {
+            return Err(format!(
+                "{name} of yt-dlp {version} is larger than {} MB",
+                MAX_DOWNLOAD_SIZE / 1024 / 1024
+            )
+            .into());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}